@@ -1,40 +1,296 @@
 use std::fs::OpenOptions;
+use std::io;
 use std::io::Read;
 use std::io::Write;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
 
 use lz4::Decoder;
 use lz4::EncoderBuilder;
+use serde::Deserialize;
+use tempfile::NamedTempFile;
 
+use crate::error::Error;
 use crate::error::Result;
 
-const COMPRESS_BUFFER_SIZE: usize = 102400;
+pub const DEFAULT_COMPRESS_BUFFER_SIZE: usize = 102400;
 
-pub fn compress_buffer(data: &[u8]) -> Result<Vec<u8>> {
-    let mut buffer = Vec::with_capacity(data.len());
-    let mut encoder = EncoderBuilder::new().build(&mut buffer)?;
+// size of the buffer used to read, compress and decompress data, set once
+// from `runtime.compress_buffer_size` when config is loaded.
+static COMPRESS_BUFFER_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_COMPRESS_BUFFER_SIZE);
 
-    encoder.write_all(data)?;
-    let (compressed_data, result) = encoder.finish();
-    result?;
+pub fn set_compress_buffer_size(size: usize) {
+    COMPRESS_BUFFER_SIZE.store(size, Ordering::Relaxed);
+}
 
-    Ok(compressed_data.to_owned())
+fn compress_buffer_size() -> usize {
+    COMPRESS_BUFFER_SIZE.load(Ordering::Relaxed)
 }
 
-pub fn compress_file(file_name: &str) -> Result<Vec<u8>> {
-    // Open db file descriptor
-    let mut reader = OpenOptions::new().read(true).open(file_name)?;
-    let bytes = reader.metadata()?.len() as usize;
-    let mut buffer = Vec::with_capacity(bytes);
-    let mut encoder = EncoderBuilder::new().build(&mut buffer)?;
+// number of threads compressing or decompressing blocks of `Lz4Block`, set
+// once from `runtime.compress_threads`, 0 means the number of cpus.
+static COMPRESS_THREADS: AtomicUsize = AtomicUsize::new(0);
 
-    let mut temp_buffer = vec![0; COMPRESS_BUFFER_SIZE];
+pub fn set_compress_threads(threads: usize) {
+    COMPRESS_THREADS.store(threads, Ordering::Relaxed);
+}
+
+fn compress_threads() -> usize {
+    match COMPRESS_THREADS.load(Ordering::Relaxed) {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        threads => threads,
+    }
+}
+
+// copies reader into writer with a buffer of `compress_buffer_size`, returns
+// the number of copied bytes.
+fn copy_buffered<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<u64> {
+    let mut buffer = vec![0; compress_buffer_size()];
+    let mut copied = 0;
 
     loop {
-        let bytes_read = reader.read(&mut temp_buffer)?;
-        if bytes_read == 0 {
-            break; // EOF
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(0) => break, // EOF
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        writer.write_all(&buffer[..bytes_read])?;
+        copied += bytes_read as u64;
+    }
+
+    Ok(copied)
+}
+
+static LZ4_EXTENSION: &str = ".lz4";
+static LZ4_BLOCK_EXTENSION: &str = ".lz4b";
+
+// compression of snapshots and wal segments, also decides the file extension.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum Compression {
+    #[default]
+    Lz4,
+    // data split into blocks of `COMPRESS_BLOCK_SIZE` compressed as lz4 frames
+    // in parallel, see `compress_blocks` for the format.
+    Lz4Block,
+    // passthrough for data which is already compressed.
+    None,
+}
+
+impl Compression {
+    // returns the extension appended to the snapshot or wal segment file name.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Lz4 => LZ4_EXTENSION,
+            Compression::Lz4Block => LZ4_BLOCK_EXTENSION,
+            Compression::None => "",
+        }
+    }
+
+    // returns the name of compression, the same as in config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Lz4 => "Lz4",
+            Compression::Lz4Block => "Lz4Block",
+            Compression::None => "None",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Lz4" => Some(Compression::Lz4),
+            "Lz4Block" => Some(Compression::Lz4Block),
+            "None" => Some(Compression::None),
+            _ => None,
         }
-        encoder.write_all(&temp_buffer[..bytes_read])?;
+    }
+
+    // detect compression from the extension of a file name.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(LZ4_EXTENSION) {
+            Compression::Lz4
+        } else if path.ends_with(LZ4_BLOCK_EXTENSION) {
+            Compression::Lz4Block
+        } else {
+            Compression::None
+        }
+    }
+}
+
+// max lz4 compression level, higher levels are treated as this level by lz4.
+pub const MAX_COMPRESSION_LEVEL: u32 = 12;
+
+// max size of the uncompressed data in a block of `Lz4Block`, at most
+// `compress_threads` blocks are in memory at the same time.
+const COMPRESS_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+
+static BLOCK_MAGIC: &[u8; 4] = b"RPB1";
+
+// reads until `buf` is full or EOF, returns the number of read bytes.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(read)
+}
+
+// runs `f` on each item with a thread per item, results keep the item order.
+fn map_parallel<T, F>(items: &[T], f: F) -> Result<Vec<Vec<u8>>>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<Vec<u8>> + Sync,
+{
+    if items.len() == 1 {
+        return Ok(vec![f(&items[0])?]);
+    }
+
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = items
+            .iter()
+            .map(|item| scope.spawn(move || f(item)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .map_err(|_| Error::PanicError("compress block thread panicked"))?
+            })
+            .collect()
+    })
+}
+
+// writes `Lz4Block` data: the magic, then blocks each with a header of its
+// uncompressed and compressed size as big endian u32 followed by an lz4
+// frame, ends with a header of zero sizes. Sizes record block boundaries so
+// that blocks are decoded independently. `compress_threads` blocks are read
+// and compressed at a time, returns the number of uncompressed bytes.
+fn compress_blocks<R: Read, W: Write>(reader: &mut R, level: u32, writer: &mut W) -> Result<u64> {
+    writer.write_all(BLOCK_MAGIC)?;
+
+    let threads = compress_threads();
+    let mut total = 0;
+    let mut eof = false;
+    while !eof {
+        let mut blocks = Vec::with_capacity(threads);
+        while !eof && blocks.len() < threads {
+            let mut block = vec![0; COMPRESS_BLOCK_SIZE];
+            let n = read_full(reader, &mut block)?;
+            eof = n < COMPRESS_BLOCK_SIZE;
+            if n > 0 {
+                block.truncate(n);
+                blocks.push(block);
+            }
+        }
+        if blocks.is_empty() {
+            break;
+        }
+
+        let compressed = map_parallel(&blocks, |block| {
+            compress_buffer(block, Compression::Lz4, level)
+        })?;
+        for (block, data) in blocks.iter().zip(compressed) {
+            writer.write_all(&(block.len() as u32).to_be_bytes())?;
+            writer.write_all(&(data.len() as u32).to_be_bytes())?;
+            writer.write_all(&data)?;
+            total += block.len() as u64;
+        }
+    }
+
+    writer.write_all(&[0; 8])?;
+    Ok(total)
+}
+
+fn decompress_block(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut block = Vec::with_capacity(size);
+    Decoder::new(data)?.read_to_end(&mut block)?;
+    if block.len() != size {
+        return Err(Error::InvalidCompressedBlock(format!(
+            "block size {} mismatch with header size {}",
+            block.len(),
+            size
+        )));
+    }
+    Ok(block)
+}
+
+// decodes data written by `compress_blocks`, `compress_threads` blocks are
+// read and decompressed at a time, returns the number of decompressed bytes.
+fn decompress_blocks<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> Result<u64> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != BLOCK_MAGIC {
+        return Err(Error::InvalidCompressedBlock(format!(
+            "invalid block magic {:?}",
+            magic
+        )));
+    }
+
+    let threads = compress_threads();
+    let mut total = 0;
+    let mut end = false;
+    while !end {
+        let mut blocks = Vec::with_capacity(threads);
+        while blocks.len() < threads {
+            let mut header = [0; 8];
+            reader.read_exact(&mut header)?;
+            let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+            let compressed_size = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+            if size == 0 {
+                end = true;
+                break;
+            }
+            // refuse corrupted headers before allocating buffers for them.
+            if size > COMPRESS_BLOCK_SIZE || compressed_size > 2 * COMPRESS_BLOCK_SIZE {
+                return Err(Error::InvalidCompressedBlock(format!(
+                    "invalid block header, size {} compressed size {}",
+                    size, compressed_size
+                )));
+            }
+
+            let mut data = vec![0; compressed_size];
+            reader.read_exact(&mut data)?;
+            blocks.push((size, data));
+        }
+        if blocks.is_empty() {
+            break;
+        }
+
+        let decompressed = map_parallel(&blocks, |(size, data)| decompress_block(data, *size))?;
+        for block in decompressed {
+            writer.write_all(&block)?;
+            total += block.len() as u64;
+        }
+    }
+
+    Ok(total)
+}
+
+// level only affects encoding, lz4 frames are decoded the same way
+// whatever the level is.
+pub fn compress_buffer(data: &[u8], compression: Compression, level: u32) -> Result<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(data.len());
+    match compression {
+        Compression::Lz4 => {}
+        Compression::Lz4Block => {
+            compress_blocks(&mut &data[..], level, &mut buffer)?;
+            return Ok(buffer);
+        }
+        Compression::None => return Ok(data.to_vec()),
+    }
+
+    let mut encoder = EncoderBuilder::new().level(level).build(&mut buffer)?;
+
+    for chunk in data.chunks(compress_buffer_size()) {
+        encoder.write_all(chunk)?;
     }
     let (compressed_data, result) = encoder.finish();
     result?;
@@ -42,19 +298,112 @@ pub fn compress_file(file_name: &str) -> Result<Vec<u8>> {
     Ok(compressed_data.to_owned())
 }
 
-pub fn decompressed_data(compressed_data: Vec<u8>) -> Result<Vec<u8>> {
-    let compressed_data = compressed_data.as_slice();
-    let mut decoder = Decoder::new(compressed_data)?;
-    let mut decompressed_data = Vec::new();
-    let mut buffer = vec![0; COMPRESS_BUFFER_SIZE];
+// compress file into a temp file created in `temp_dir`, so that a large db
+// file is never buffered in memory. the temp file is removed when dropped,
+// `reopen` it to read it with independent cursors.
+pub fn compress_file(
+    file_name: &str,
+    compression: Compression,
+    level: u32,
+    temp_dir: &str,
+) -> Result<NamedTempFile> {
+    let mut reader = OpenOptions::new().read(true).open(file_name)?;
+    let mut temp_file = NamedTempFile::new_in(temp_dir)?;
 
-    loop {
-        let bytes_read = decoder.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break; // EOF
+    match compression {
+        Compression::Lz4 => {
+            let mut encoder = EncoderBuilder::new().level(level).build(&mut temp_file)?;
+            copy_buffered(&mut reader, &mut encoder)?;
+            let (_, result) = encoder.finish();
+            result?;
+        }
+        Compression::Lz4Block => {
+            compress_blocks(&mut reader, level, &mut temp_file)?;
+        }
+        Compression::None => {
+            copy_buffered(&mut reader, &mut temp_file)?;
+        }
+    }
+
+    temp_file.flush()?;
+    Ok(temp_file)
+}
+
+// decompress data from reader into writer without buffering all of it,
+// returns the number of decompressed bytes.
+pub fn decompress_to<R: Read, W: Write>(
+    reader: R,
+    compression: Compression,
+    writer: &mut W,
+) -> Result<u64> {
+    let mut reader = reader;
+    match compression {
+        Compression::Lz4 => {
+            let mut decoder = Decoder::new(reader)?;
+            copy_buffered(&mut decoder, writer)
         }
-        decompressed_data.extend_from_slice(&buffer[..bytes_read]);
+        Compression::Lz4Block => decompress_blocks(&mut reader, writer),
+        Compression::None => copy_buffered(&mut reader, writer),
+    }
+}
+
+pub fn decompressed_data(compressed_data: Vec<u8>, compression: Compression) -> Result<Vec<u8>> {
+    if compression == Compression::None {
+        return Ok(compressed_data);
     }
 
+    let mut decompressed_data = Vec::new();
+    decompress_to(
+        compressed_data.as_slice(),
+        compression,
+        &mut decompressed_data,
+    )?;
+
     Ok(decompressed_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::compress_buffer;
+    use super::decompress_to;
+    use super::decompressed_data;
+    use super::set_compress_threads;
+    use super::Compression;
+    use super::COMPRESS_BLOCK_SIZE;
+    use crate::error::Result;
+
+    #[test]
+    fn test_decompress_to() -> Result<()> {
+        let data = b"replited".repeat(1024);
+        for compression in [Compression::Lz4, Compression::Lz4Block, Compression::None] {
+            let compressed = compress_buffer(&data, compression, 0)?;
+            let mut decompressed = Vec::new();
+            let n = decompress_to(compressed.as_slice(), compression, &mut decompressed)?;
+            assert_eq!(n, data.len() as u64);
+            assert_eq!(decompressed, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_blocks() -> Result<()> {
+        // spans several batches of blocks with a partial last block.
+        set_compress_threads(2);
+        let data: Vec<u8> = (0..COMPRESS_BLOCK_SIZE * 5 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let compressed = compress_buffer(&data, Compression::Lz4Block, 0)?;
+        assert_eq!(
+            decompressed_data(compressed.clone(), Compression::Lz4Block)?,
+            data
+        );
+
+        // a truncated object is refused instead of restored partially.
+        let truncated = compressed[..compressed.len() - 8].to_vec();
+        assert!(decompressed_data(truncated, Compression::Lz4Block).is_err());
+
+        let empty = compress_buffer(&[], Compression::Lz4Block, 0)?;
+        assert!(decompressed_data(empty, Compression::Lz4Block)?.is_empty());
+        Ok(())
+    }
+}
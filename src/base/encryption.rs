@@ -0,0 +1,267 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+
+use aes_gcm::aead::Aead;
+use aes_gcm::aead::AeadCore;
+use aes_gcm::aead::KeyInit;
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::Payload;
+use aes_gcm::Aes256Gcm;
+use aes_gcm::Nonce;
+use sha2::Digest;
+use sha2::Sha256;
+
+use crate::error::Error;
+use crate::error::Result;
+
+// length of AES-256 key in bytes.
+pub const ENCRYPTION_KEY_LEN: usize = 32;
+// length of AES-GCM nonce in bytes.
+const NONCE_LEN: usize = 12;
+// marks encrypted data, so that it is never taken as plain data. Compressed
+// or raw sqlite data never starts with it.
+pub const ENCRYPTED_MAGIC: &[u8] = b"RPLTDENC";
+
+// length of key id in bytes.
+const KEY_ID_LEN: usize = 8;
+
+// returns true if data is encrypted by `Cipher`.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(ENCRYPTED_MAGIC)
+}
+
+// returns the id of a key, the hex encoded prefix of its sha256 digest. It
+// identifies the key without revealing it.
+pub fn key_id(key: &[u8]) -> String {
+    hex::encode(&Sha256::digest(key)[..KEY_ID_LEN])
+}
+
+// returns a new random data key.
+pub fn generate_data_key() -> Vec<u8> {
+    Aes256Gcm::generate_key(&mut OsRng).to_vec()
+}
+
+// associated data authenticated with the wrapped data key of generation.
+fn data_key_aad(generation: &str) -> String {
+    format!("data-key/{}", generation)
+}
+
+// AES-256-GCM cipher, encrypted data is formatted as
+// `magic || nonce || ciphertext`, with a random 96 bits nonce for each object.
+// The ciphertext carries a 16 bytes authentication tag.
+#[derive(Clone)]
+pub struct Cipher {
+    cipher: Aes256Gcm,
+}
+
+impl Cipher {
+    pub fn try_create(key: &[u8]) -> Result<Self> {
+        if key.len() != ENCRYPTION_KEY_LEN {
+            return Err(Error::InvalidConfig(format!(
+                "encryption key MUST be {} bytes, but got {} bytes",
+                ENCRYPTION_KEY_LEN,
+                key.len()
+            )));
+        }
+        let cipher = match Aes256Gcm::new_from_slice(key) {
+            Ok(cipher) => cipher,
+            Err(e) => {
+                return Err(Error::InvalidConfig(format!(
+                    "invalid encryption key: {:?}",
+                    e
+                )));
+            }
+        };
+
+        Ok(Self { cipher })
+    }
+
+    // encrypt data, `aad` is authenticated but not encrypted.
+    pub fn encrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = match self.cipher.encrypt(&nonce, Payload { msg: data, aad }) {
+            Ok(ciphertext) => ciphertext,
+            Err(e) => return Err(Error::Internal(format!("encrypt data fail: {:?}", e))),
+        };
+
+        let mut encrypted_data =
+            Vec::with_capacity(ENCRYPTED_MAGIC.len() + NONCE_LEN + ciphertext.len());
+        encrypted_data.extend_from_slice(ENCRYPTED_MAGIC);
+        encrypted_data.extend_from_slice(&nonce);
+        encrypted_data.extend_from_slice(&ciphertext);
+        Ok(encrypted_data)
+    }
+
+    // decrypt data encrypted by `encrypt` with the same `aad`, fails if the key
+    // or `aad` is wrong, or the data has been tampered.
+    pub fn decrypt(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if !is_encrypted(data) {
+            return Err(Error::DecryptError("data is not encrypted"));
+        }
+        let data = &data[ENCRYPTED_MAGIC.len()..];
+        if data.len() < NONCE_LEN {
+            return Err(Error::DecryptError("encrypted data too short"));
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        match self.cipher.decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        ) {
+            Ok(data) => Ok(data),
+            Err(_) => Err(Error::DecryptError(
+                "decrypt data fail, wrong encryption key or corrupted data",
+            )),
+        }
+    }
+}
+
+impl Debug for Cipher {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Cipher").finish_non_exhaustive()
+    }
+}
+
+// master keys of envelope encryption: each generation is encrypted with its
+// own data key, which is wrapped by a master key and recorded with the id of
+// the master key. The current key wraps data keys of new generations, the
+// previous keys only unwrap data keys not rekeyed yet.
+#[derive(Clone, Debug)]
+pub struct KeyRing {
+    // (key id, cipher) of the current key.
+    current: (String, Cipher),
+    previous: Vec<(String, Cipher)>,
+}
+
+impl KeyRing {
+    pub fn try_create(key: &[u8], previous_keys: &[Vec<u8>]) -> Result<Self> {
+        let mut previous = Vec::with_capacity(previous_keys.len());
+        for previous_key in previous_keys {
+            previous.push((key_id(previous_key), Cipher::try_create(previous_key)?));
+        }
+
+        Ok(Self {
+            current: (key_id(key), Cipher::try_create(key)?),
+            previous,
+        })
+    }
+
+    // returns id of the current key.
+    pub fn key_id(&self) -> &str {
+        &self.current.0
+    }
+
+    // returns cipher of the current key.
+    pub fn cipher(&self) -> &Cipher {
+        &self.current.1
+    }
+
+    // wraps data key of generation with the current key, returns it hex encoded.
+    pub fn wrap(&self, generation: &str, data_key: &[u8]) -> Result<String> {
+        let aad = data_key_aad(generation);
+        let wrapped_key = self.cipher().encrypt(data_key, aad.as_bytes())?;
+        Ok(hex::encode(wrapped_key))
+    }
+
+    // unwraps data key of generation wrapped by the key of `key_id`.
+    pub fn unwrap(&self, generation: &str, key_id: &str, wrapped_key: &str) -> Result<Vec<u8>> {
+        let cipher = match std::iter::once(&self.current)
+            .chain(self.previous.iter())
+            .find(|(id, _)| id == key_id)
+        {
+            Some((_, cipher)) => cipher,
+            None => {
+                return Err(Error::EncryptionKeyRequired(format!(
+                    "data key of generation {} is wrapped by key {}, which is neither the key nor one of the previous_keys",
+                    generation, key_id
+                )));
+            }
+        };
+
+        let wrapped_key = match hex::decode(wrapped_key) {
+            Ok(wrapped_key) => wrapped_key,
+            Err(_) => return Err(Error::DecryptError("wrapped data key is not valid hex")),
+        };
+        let aad = data_key_aad(generation);
+        cipher.decrypt(&wrapped_key, aad.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::generate_data_key;
+    use super::is_encrypted;
+    use super::key_id;
+    use super::Cipher;
+    use super::KeyRing;
+    use crate::error::Error;
+    use crate::error::Result;
+
+    #[test]
+    fn test_encrypt_decrypt() -> Result<()> {
+        let cipher = Cipher::try_create(&[1; 32])?;
+        let data = b"replited".to_vec();
+
+        let encrypted = cipher.encrypt(&data, b"aad")?;
+        assert!(is_encrypted(&encrypted));
+        assert_ne!(encrypted[20..], data[..]);
+        assert_eq!(cipher.decrypt(&encrypted, b"aad")?, data);
+
+        // plain data
+        assert!(!is_encrypted(&data));
+        let err = cipher.decrypt(&data, b"aad").unwrap_err();
+        assert_eq!(err.code(), Error::DECRYPT_ERROR);
+
+        // wrong aad
+        let err = cipher.decrypt(&encrypted, b"other").unwrap_err();
+        assert_eq!(err.code(), Error::DECRYPT_ERROR);
+
+        // wrong key
+        let other = Cipher::try_create(&[2; 32])?;
+        let err = other.decrypt(&encrypted, b"aad").unwrap_err();
+        assert_eq!(err.code(), Error::DECRYPT_ERROR);
+
+        assert!(Cipher::try_create(&[1; 16]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_ring() -> Result<()> {
+        let old = KeyRing::try_create(&[1; 32], &[])?;
+        let data_key = generate_data_key();
+        assert_eq!(data_key.len(), 32);
+        let wrapped_key = old.wrap("generation", &data_key)?;
+        assert_eq!(
+            old.unwrap("generation", old.key_id(), &wrapped_key)?,
+            data_key
+        );
+
+        // wrapped for another generation.
+        let err = old.unwrap("other", old.key_id(), &wrapped_key).unwrap_err();
+        assert_eq!(err.code(), Error::DECRYPT_ERROR);
+
+        // a new key unwraps data keys of the previous keys, and wraps them again.
+        let new = KeyRing::try_create(&[2; 32], &[vec![1; 32]])?;
+        assert_ne!(new.key_id(), old.key_id());
+        assert_eq!(new.key_id(), key_id(&[2; 32]));
+        assert_eq!(
+            new.unwrap("generation", old.key_id(), &wrapped_key)?,
+            data_key
+        );
+        let rewrapped_key = new.wrap("generation", &data_key)?;
+        assert_eq!(
+            new.unwrap("generation", new.key_id(), &rewrapped_key)?,
+            data_key
+        );
+
+        // the key is unknown once removed from the previous keys.
+        let err = old
+            .unwrap("generation", new.key_id(), &rewrapped_key)
+            .unwrap_err();
+        assert_eq!(err.code(), Error::ENCRYPTION_KEY_REQUIRED);
+        Ok(())
+    }
+}
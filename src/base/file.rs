@@ -4,17 +4,19 @@ use std::sync::LazyLock;
 
 use regex::Regex;
 
+use super::Compression;
 use crate::error::Error;
 use crate::error::Result;
 
 static WAL_EXTENDION: &str = ".wal";
 static WAL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^([0-9]{10})\.wal$").unwrap());
-static WAL_SEGMENT_EXTENDION: &str = ".wal.lz4";
+static WAL_SEGMENT_EXTENDION: &str = ".wal";
 static WAL_SEGMENT_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^([0-9]{10})(?:_([0-9]{10}))\.wal\.lz4$").unwrap());
+    LazyLock::new(|| Regex::new(r"^([0-9]{10})(?:_([0-9]{10}))\.wal(?:\.lz4b?)?$").unwrap());
 static SNAPSHOT_REGEX: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^([0-9]{10})\.snapshot\.lz4$").unwrap());
-static SNAPSHOT_EXTENDION: &str = ".snapshot.lz4";
+    LazyLock::new(|| Regex::new(r"^([0-9]{10})\.snapshot(?:\.lz4b?)?$").unwrap());
+static SNAPSHOT_EXTENDION: &str = ".snapshot";
+static CHECKSUM_EXTENSION: &str = ".sha256";
 
 // return base name of path
 pub fn path_base(path: &str) -> Result<String> {
@@ -98,8 +100,13 @@ pub fn parse_snapshot_path(path: &str) -> Result<u64> {
     Ok(a.parse::<u64>()?)
 }
 
-pub fn format_snapshot_path(index: u64) -> String {
-    format!("{:0>10}{}", index, SNAPSHOT_EXTENDION)
+pub fn format_snapshot_path(index: u64, compression: Compression) -> String {
+    format!(
+        "{:0>10}{}{}",
+        index,
+        SNAPSHOT_EXTENDION,
+        compression.extension()
+    )
 }
 
 pub fn local_generations_dir(meta_dir: &str) -> String {
@@ -111,6 +118,16 @@ pub fn local_generations_dir(meta_dir: &str) -> String {
         .to_string()
 }
 
+// returns the remote directory of db, which is under `prefix` if not empty.
+pub fn remote_db_dir(prefix: &str, db_name: &str) -> String {
+    let prefix = prefix.trim_matches('/');
+    if prefix.is_empty() {
+        db_name.to_string()
+    } else {
+        format!("{}/{}", prefix, db_name)
+    }
+}
+
 pub fn remote_generations_dir(db_name: &str) -> String {
     Path::new(db_name)
         .join("generations/")
@@ -120,6 +137,26 @@ pub fn remote_generations_dir(db_name: &str) -> String {
         .to_string()
 }
 
+// returns the path of the heartbeat object of db.
+pub fn heartbeat_file(db_name: &str) -> String {
+    Path::new(db_name)
+        .join("heartbeat")
+        .as_path()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+// returns the path of the status object of db.
+pub fn status_file(db_name: &str) -> String {
+    Path::new(db_name)
+        .join("status.json")
+        .as_path()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
 // returns the path of a single generation.
 pub fn generation_dir(meta_dir: &str, generation: &str) -> String {
     Path::new(meta_dir)
@@ -131,6 +168,16 @@ pub fn generation_dir(meta_dir: &str, generation: &str) -> String {
         .to_string()
 }
 
+// returns the path of the manifest of a generation.
+pub fn manifest_file(db: &str, generation: &str) -> String {
+    Path::new(&generation_dir(db, generation))
+        .join("manifest.json")
+        .as_path()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
 pub fn snapshots_dir(db: &str, generation: &str) -> String {
     Path::new(&generation_dir(db, generation))
         .join("snapshots/")
@@ -140,10 +187,10 @@ pub fn snapshots_dir(db: &str, generation: &str) -> String {
         .to_string()
 }
 
-pub fn snapshot_file(db: &str, generation: &str, index: u64) -> String {
+pub fn snapshot_file(db: &str, generation: &str, index: u64, compression: Compression) -> String {
     Path::new(&generation_dir(db, generation))
         .join("snapshots")
-        .join(format_snapshot_path(index))
+        .join(format_snapshot_path(index, compression))
         .as_path()
         .to_str()
         .unwrap()
@@ -159,21 +206,62 @@ pub fn walsegments_dir(db: &str, generation: &str) -> String {
         .to_string()
 }
 
-pub fn walsegment_file(db: &str, generation: &str, index: u64, offset: u64) -> String {
+pub fn walsegment_file(
+    db: &str,
+    generation: &str,
+    index: u64,
+    offset: u64,
+    compression: Compression,
+) -> String {
     Path::new(&generation_dir(db, generation))
         .join("wal")
-        .join(format_walsegment_path(index, offset))
+        .join(format_walsegment_path(index, offset, compression))
         .as_path()
         .to_str()
         .unwrap()
         .to_string()
 }
 
-pub fn format_walsegment_path(index: u64, offset: u64) -> String {
-    format!("{:0>10}_{:0>10}{}", index, offset, WAL_SEGMENT_EXTENDION)
+pub fn format_walsegment_path(index: u64, offset: u64, compression: Compression) -> String {
+    format!(
+        "{:0>10}_{:0>10}{}{}",
+        index,
+        offset,
+        WAL_SEGMENT_EXTENDION,
+        compression.extension()
+    )
 }
 
 // returns the path of the name of the current generation.
+// sidecar object holding the hex encoded sha256 of the object at `path`.
+pub fn checksum_file(path: &str) -> String {
+    format!("{}{}", path, CHECKSUM_EXTENSION)
+}
+
+pub fn is_checksum_file(path: &str) -> bool {
+    path.ends_with(CHECKSUM_EXTENSION)
+}
+
+// file persisting the position of the replicate with `index` in db config.
+pub fn replicate_position_file(meta_dir: &str, index: usize) -> String {
+    Path::new(meta_dir)
+        .join(format!("replicate-{}.position", index))
+        .as_path()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+// lock file held by the replited process replicating the db.
+pub fn process_lock_file(meta_dir: &str) -> String {
+    Path::new(meta_dir)
+        .join("replited.lock")
+        .as_path()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
 pub fn generation_file_path(meta_dir: &str) -> String {
     Path::new(meta_dir)
         .join("generation")
@@ -211,6 +299,9 @@ mod tests {
     use super::parse_wal_path;
     use super::parse_wal_segment_path;
     use super::path_base;
+    use super::remote_db_dir;
+    use super::walsegments_dir;
+    use crate::base::Compression;
     use crate::error::Result;
 
     #[test]
@@ -258,9 +349,21 @@ mod tests {
         let index = parse_snapshot_path(path);
         assert!(index.is_err());
 
-        let path = format!("a/b/{}", format_snapshot_path(19));
+        let path = format!("a/b/{}", format_snapshot_path(19, Compression::Lz4));
         let index = parse_snapshot_path(&path)?;
         assert_eq!(index, 19);
+
+        let path = format!("a/b/{}", format_snapshot_path(19, Compression::None));
+        assert_eq!(path, "a/b/0000000019.snapshot");
+        let index = parse_snapshot_path(&path)?;
+        assert_eq!(index, 19);
+        assert_eq!(Compression::from_path(&path), Compression::None);
+
+        let path = format!("a/b/{}", format_snapshot_path(19, Compression::Lz4Block));
+        assert_eq!(path, "a/b/0000000019.snapshot.lz4b");
+        let index = parse_snapshot_path(&path)?;
+        assert_eq!(index, 19);
+        assert_eq!(Compression::from_path(&path), Compression::Lz4Block);
         Ok(())
     }
 
@@ -271,10 +374,18 @@ mod tests {
         assert_eq!(index, 19);
         assert_eq!(offset, 20);
 
-        let path = format!("a/b/{}", format_walsegment_path(19, 20));
+        let path = format!("a/b/{}", format_walsegment_path(19, 20, Compression::Lz4));
         let (index, offset) = parse_wal_segment_path(&path)?;
         assert_eq!(index, 19);
         assert_eq!(offset, 20);
+        assert_eq!(Compression::from_path(&path), Compression::Lz4);
+
+        let path = format!("a/b/{}", format_walsegment_path(19, 20, Compression::None));
+        assert_eq!(path, "a/b/0000000019_0000000020.wal");
+        let (index, offset) = parse_wal_segment_path(&path)?;
+        assert_eq!(index, 19);
+        assert_eq!(offset, 20);
+        assert_eq!(Compression::from_path(&path), Compression::None);
 
         Ok(())
     }
@@ -287,4 +398,16 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_remote_db_dir() {
+        assert_eq!(remote_db_dir("", "test.db"), "test.db");
+        assert_eq!(remote_db_dir("/host-a/", "test.db"), "host-a/test.db");
+
+        let db_dir = remote_db_dir("host-a", "test.db");
+        assert_eq!(
+            walsegments_dir(&db_dir, "0191f4bd8a2a7b4c9e1d3f5a6b7c8d9e"),
+            "host-a/test.db/generations/0191f4bd8a2a7b4c9e1d3f5a6b7c8d9e/wal/"
+        );
+    }
 }
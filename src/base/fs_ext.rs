@@ -0,0 +1,81 @@
+#[cfg(unix)]
+use std::fs;
+use std::fs::File;
+use std::fs::Metadata;
+use std::fs::OpenOptions;
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::OpenOptionsExt;
+
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+// reads from `offset` of file into buf, returns the number of bytes read.
+// the file cursor is not used on unix, but moved on windows.
+pub fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    #[cfg(unix)]
+    {
+        file.read_at(buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        file.seek_read(buf, offset)
+    }
+}
+
+// copies mode and owner of `from` to file of path on unix, it is a no-op
+// on windows which has neither of them.
+pub fn copy_permissions(from: &Metadata, path: &str) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        fs::set_permissions(path, from.permissions())?;
+        std::os::unix::fs::chown(path, Some(from.uid()), Some(from.gid()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (from, path);
+
+    Ok(())
+}
+
+// opens the lock file of path and locks it exclusively without blocking,
+// returns None if it is locked by another process. the lock is released
+// when the file is closed, also when the process crashes, so a lock file
+// left by a crashed process never blocks. it is a `flock` on unix and an
+// exclusive share mode on windows.
+pub fn open_lock_file(path: &str) -> io::Result<Option<File>> {
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create(true).truncate(false);
+
+    #[cfg(unix)]
+    {
+        let file = options.open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(Some(file));
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+            return Ok(None);
+        }
+        Err(err)
+    }
+    #[cfg(windows)]
+    {
+        match options.share_mode(0).open(path) {
+            Ok(file) => Ok(Some(file)),
+            Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        options.open(path).map(Some)
+    }
+}
@@ -1,13 +1,16 @@
+use std::cmp::Ordering;
 use std::fmt::Display;
 use std::fmt::Formatter;
 
+use chrono::DateTime;
+use chrono::Utc;
 use uuid::timestamp;
 use uuid::NoContext;
 use uuid::Uuid;
 
 use crate::error::Result;
 
-#[derive(Eq, PartialEq, PartialOrd, Debug, Clone, Default)]
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
 pub struct Generation {
     uuid: Uuid,
     generation: String,
@@ -35,6 +38,33 @@ impl Generation {
     pub fn is_empty(&self) -> bool {
         self.uuid.is_nil()
     }
+
+    // returns the creation time encoded in the v7 uuid of generation.
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        let (secs, nanos) = self.unix_timestamp()?;
+        DateTime::from_timestamp(secs as i64, nanos)
+    }
+
+    fn unix_timestamp(&self) -> Option<(u64, u32)> {
+        Some(self.uuid.get_timestamp()?.to_unix())
+    }
+}
+
+// generations are ordered by their creation time, the older the smaller.
+// generations without timestamp(e.g. the empty one) are the smallest, and
+// generations created in the same time are ordered by uuid.
+impl Ord for Generation {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.unix_timestamp()
+            .cmp(&other.unix_timestamp())
+            .then_with(|| self.uuid.cmp(&other.uuid))
+    }
+}
+
+impl PartialOrd for Generation {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl Display for Generation {
@@ -42,3 +72,32 @@ impl Display for Generation {
         write!(f, "{:?}", self.generation)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use super::Generation;
+    use crate::error::Result;
+
+    #[test]
+    fn test_generation_order() -> Result<()> {
+        let older = Generation::new();
+        sleep(Duration::from_millis(2));
+        let newer = Generation::new();
+        assert!(older < newer);
+        assert!(older.created_at() < newer.created_at());
+
+        // parsed generations keep the order.
+        let parsed_older = Generation::try_create(older.as_str())?;
+        let parsed_newer = Generation::try_create(newer.as_str())?;
+        assert!(parsed_older < parsed_newer);
+
+        let mut generations = vec![older.clone(), Generation::default(), newer.clone()];
+        generations.sort_by(|a, b| b.cmp(a));
+        assert_eq!(generations, vec![newer, older, Generation::default()]);
+
+        Ok(())
+    }
+}
@@ -1,27 +1,52 @@
 mod compress;
+mod encryption;
 mod file;
+mod fs_ext;
 mod generation;
 mod numerical;
 mod string;
 
 pub use compress::compress_buffer;
 pub use compress::compress_file;
+pub use compress::decompress_to;
 pub use compress::decompressed_data;
+pub use compress::set_compress_buffer_size;
+pub use compress::set_compress_threads;
+pub use compress::Compression;
+pub use compress::DEFAULT_COMPRESS_BUFFER_SIZE;
+pub use compress::MAX_COMPRESSION_LEVEL;
+pub use encryption::generate_data_key;
+pub use encryption::is_encrypted;
+pub use encryption::Cipher;
+pub use encryption::KeyRing;
+pub use encryption::ENCRYPTED_MAGIC;
+pub use encryption::ENCRYPTION_KEY_LEN;
+pub use file::checksum_file;
 pub use file::generation_dir;
 pub use file::generation_file_path;
+pub use file::heartbeat_file;
+pub use file::is_checksum_file;
 pub use file::local_generations_dir;
+pub use file::manifest_file;
 pub use file::parent_dir;
 pub use file::parse_snapshot_path;
 pub use file::parse_wal_path;
 pub use file::parse_wal_segment_path;
 pub use file::path_base;
+pub use file::process_lock_file;
+pub use file::remote_db_dir;
 pub use file::remote_generations_dir;
+pub use file::replicate_position_file;
 pub use file::shadow_wal_dir;
 pub use file::shadow_wal_file;
 pub use file::snapshot_file;
 pub use file::snapshots_dir;
+pub use file::status_file;
 pub use file::walsegment_file;
 pub use file::walsegments_dir;
+pub use fs_ext::copy_permissions;
+pub use fs_ext::open_lock_file;
+pub use fs_ext::read_at;
 pub use generation::Generation;
 pub use numerical::is_power_of_two;
 pub use string::mask_string;
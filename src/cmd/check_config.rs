@@ -0,0 +1,77 @@
+use super::command::Command;
+use crate::config::CheckConfigOptions;
+use crate::config::Config;
+use crate::error::Result;
+use crate::storage::StorageClient;
+
+pub struct CheckConfig {
+    config_file: String,
+    options: CheckConfigOptions,
+}
+
+impl CheckConfig {
+    // config is loaded in run, so that a bad config is reported instead of
+    // failing to create the command.
+    pub fn try_create(config_file: &str, options: CheckConfigOptions) -> Result<Box<Self>> {
+        Ok(Box::new(CheckConfig {
+            config_file: config_file.to_string(),
+            options,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for CheckConfig {
+    // load and validate config, then probe every replicate if asked. Returns
+    // the last error, so any problem exits non-zero.
+    async fn run(&mut self) -> Result<()> {
+        let config = match Config::load(&self.config_file) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("config {} is invalid: {}", self.config_file, e.message());
+                return Err(e);
+            }
+        };
+        println!(
+            "config {} is valid, {} databases",
+            self.config_file,
+            config.database.len()
+        );
+
+        let mut ret = Ok(());
+        for db in &config.database {
+            for replicate in &db.replicate {
+                if !self.options.probe {
+                    println!(
+                        "  db {} replicate {}: {}",
+                        db.db, replicate.name, replicate.params
+                    );
+                    continue;
+                }
+
+                let result = match StorageClient::try_create(db.db.clone(), replicate.clone()) {
+                    Ok(client) => client.check().await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => println!(
+                        "  db {} replicate {}: {} OK",
+                        db.db, replicate.name, replicate.params
+                    ),
+                    Err(e) => {
+                        println!(
+                            "  db {} replicate {}: {} FAIL: {}",
+                            db.db,
+                            replicate.name,
+                            replicate.params,
+                            e.message()
+                        );
+                        ret = Err(e);
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+}
@@ -1,11 +1,31 @@
+use super::CheckConfig;
+use super::Diff;
+use super::Follow;
+use super::List;
+use super::Prune;
+use super::Rekey;
 use super::Replicate;
 use super::Restore;
+use super::Status;
+use super::SyncOnce;
+use super::Thaw;
+use super::Verify;
 use crate::config::Arg;
 use crate::config::ArgCommand;
 use crate::error::Result;
 
 pub const REPLICATE_CMD: &str = "replicate";
 pub const RESTORE_CMD: &str = "restore";
+pub const DIFF_CMD: &str = "diff";
+pub const LIST_CMD: &str = "list";
+pub const PRUNE_CMD: &str = "prune";
+pub const THAW_CMD: &str = "thaw";
+pub const REKEY_CMD: &str = "rekey";
+pub const VERIFY_CMD: &str = "verify";
+pub const SYNC_CMD: &str = "sync";
+pub const STATUS_CMD: &str = "status";
+pub const FOLLOW_CMD: &str = "follow";
+pub const CHECK_CONFIG_CMD: &str = "check-config";
 
 #[async_trait::async_trait]
 pub trait Command {
@@ -14,7 +34,19 @@ pub trait Command {
 
 pub fn command(arg: Arg) -> Result<Box<dyn Command>> {
     match &arg.cmd {
-        ArgCommand::Replicate => Ok(Replicate::try_create(&arg.config)?),
+        ArgCommand::Replicate(options) => Ok(Replicate::try_create(&arg.config, options.clone())?),
         ArgCommand::Restore(options) => Ok(Restore::try_create(&arg.config, options.clone())?),
+        ArgCommand::Diff(options) => Ok(Diff::try_create(&arg.config, options.clone())?),
+        ArgCommand::List(options) => Ok(List::try_create(&arg.config, options.clone())?),
+        ArgCommand::Prune(options) => Ok(Prune::try_create(&arg.config, options.clone())?),
+        ArgCommand::Thaw(options) => Ok(Thaw::try_create(&arg.config, options.clone())?),
+        ArgCommand::Rekey(options) => Ok(Rekey::try_create(&arg.config, options.clone())?),
+        ArgCommand::Verify(options) => Ok(Verify::try_create(&arg.config, options.clone())?),
+        ArgCommand::Sync(options) => Ok(SyncOnce::try_create(&arg.config, options.clone())?),
+        ArgCommand::Status(options) => Ok(Status::try_create(&arg.config, options.clone())?),
+        ArgCommand::Follow(options) => Ok(Follow::try_create(&arg.config, options.clone())?),
+        ArgCommand::CheckConfig(options) => {
+            Ok(CheckConfig::try_create(&arg.config, options.clone())?)
+        }
     }
 }
@@ -0,0 +1,38 @@
+use super::command::Command;
+use crate::config::Config;
+use crate::config::DiffOptions;
+use crate::error::Error;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::run_diff;
+
+pub struct Diff {
+    config: Config,
+    options: DiffOptions,
+}
+
+impl Diff {
+    pub fn try_create(config: &str, options: DiffOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Diff { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Diff {
+    async fn run(&mut self) -> Result<()> {
+        self.options.validate()?;
+
+        if let Some(config) = self.config.find_db(&self.options.db)? {
+            return run_diff(config, &self.options).await;
+        }
+
+        Err(Error::InvalidArg(format!(
+            "cannot find db {} in config file",
+            self.options.db
+        )))
+    }
+}
@@ -0,0 +1,48 @@
+use tokio::select;
+
+use super::command::Command;
+use super::replicate::shutdown_signal;
+use crate::config::Config;
+use crate::config::FollowOptions;
+use crate::error::Error;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::run_follow;
+
+pub struct Follow {
+    config: Config,
+    options: FollowOptions,
+}
+
+impl Follow {
+    pub fn try_create(config: &str, options: FollowOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Follow { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Follow {
+    async fn run(&mut self) -> Result<()> {
+        self.options.validate()?;
+
+        let config = match self.config.find_db(&self.options.db)? {
+            Some(config) => config,
+            None => {
+                return Err(Error::InvalidArg(format!(
+                    "cannot find db {} in config file",
+                    self.options.db
+                )))
+            }
+        };
+
+        let shutdown = shutdown_signal()?;
+        select! {
+            result = run_follow(config, &self.options) => result,
+            _ = shutdown => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use super::command::Command;
+use crate::config::Config;
+use crate::config::ListOptions;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::list_generations;
+use crate::sync::print_generations;
+
+pub struct List {
+    config: Config,
+    options: ListOptions,
+}
+
+impl List {
+    pub fn try_create(config: &str, options: ListOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(List { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for List {
+    async fn run(&mut self) -> Result<()> {
+        let mut generations = vec![];
+        for config in self.config.find_dbs(&self.options.db)? {
+            generations.extend(list_generations(config).await?);
+        }
+
+        print_generations(&generations, self.options.format)
+    }
+}
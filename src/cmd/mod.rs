@@ -1,7 +1,27 @@
+mod check_config;
 mod command;
+mod diff;
+mod follow;
+mod list;
+mod prune;
+mod rekey;
 mod replicate;
 mod restore;
+mod status;
+mod sync;
+mod thaw;
+mod verify;
 
+pub use check_config::CheckConfig;
 pub use command::command;
+pub use diff::Diff;
+pub use follow::Follow;
+pub use list::List;
+pub use prune::Prune;
+pub use rekey::Rekey;
 pub use replicate::Replicate;
 pub use restore::Restore;
+pub use status::Status;
+pub use sync::SyncOnce;
+pub use thaw::Thaw;
+pub use verify::Verify;
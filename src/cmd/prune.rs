@@ -0,0 +1,32 @@
+use super::command::Command;
+use crate::config::Config;
+use crate::config::PruneOptions;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::run_prune;
+
+pub struct Prune {
+    config: Config,
+    options: PruneOptions,
+}
+
+impl Prune {
+    pub fn try_create(config: &str, options: PruneOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Prune { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Prune {
+    async fn run(&mut self) -> Result<()> {
+        for config in self.config.find_dbs(&self.options.db)? {
+            run_prune(config, self.options.dry_run).await?;
+        }
+
+        Ok(())
+    }
+}
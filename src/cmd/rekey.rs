@@ -0,0 +1,32 @@
+use super::command::Command;
+use crate::config::Config;
+use crate::config::RekeyOptions;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::run_rekey;
+
+pub struct Rekey {
+    config: Config,
+    options: RekeyOptions,
+}
+
+impl Rekey {
+    pub fn try_create(config: &str, options: RekeyOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Rekey { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Rekey {
+    async fn run(&mut self) -> Result<()> {
+        for config in self.config.find_dbs(&self.options.db)? {
+            run_rekey(config).await?;
+        }
+
+        Ok(())
+    }
+}
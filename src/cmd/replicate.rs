@@ -1,39 +1,357 @@
+use std::collections::BTreeMap;
+use std::future::pending;
+use std::future::Future;
+use std::sync::Arc;
+
+use log::error;
+use log::info;
+use log::warn;
+use tokio::select;
+use tokio::signal::ctrl_c;
+#[cfg(unix)]
+use tokio::signal::unix::signal;
+#[cfg(unix)]
+use tokio::signal::unix::Signal;
+#[cfg(unix)]
+use tokio::signal::unix::SignalKind;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::watch;
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
 use super::command::Command;
+use crate::base::path_base;
 use crate::config::Config;
+use crate::config::DbConfig;
+use crate::config::ReplicateOptions;
 use crate::database::run_database;
+use crate::error::Error;
 use crate::error::Result;
 use crate::log::init_log;
+use crate::metrics::start_metrics_server;
+use crate::metrics::unregister_db;
+use crate::runtime::Runtime;
 
 pub struct Replicate {
+    config_file: String,
     config: Config,
+    options: ReplicateOptions,
+}
+
+// a running db, keyed by db path.
+struct DbTask {
+    id: u64,
+    config: DbConfig,
+    // stops only this db, e.g. when it is removed from config by a reload.
+    shutdown: watch::Sender<bool>,
+    handle: JoinHandle<Result<()>>,
+}
+
+// spawns db tasks, which report their id on exit.
+struct DbSpawner {
+    runtime: Runtime,
+    shutdown_sender: Arc<watch::Sender<bool>>,
+    exit_sender: UnboundedSender<u64>,
+    // bounds the number of dbs syncing at the same time, None if unlimited.
+    limiter: Option<Arc<Semaphore>>,
+    fail_fast: bool,
+    next_id: u64,
+}
+
+impl DbSpawner {
+    fn spawn(&mut self, config: DbConfig) -> DbTask {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (shutdown, shutdown_receiver) = watch::channel(false);
+        let database = config.clone();
+        let shutdown_sender = self.shutdown_sender.clone();
+        let exit_sender = self.exit_sender.clone();
+        let limiter = self.limiter.clone();
+        let fail_fast = self.fail_fast;
+        let handle = self.runtime.spawn(async move {
+            let db = database.db.clone();
+            let result = run_database(database, shutdown_receiver, limiter).await;
+            if result.is_err() && fail_fast {
+                info!("db {} failed, shutting down for fail fast", db);
+                let _ = shutdown_sender.send(true);
+            }
+            let _ = exit_sender.send(id);
+            result
+        });
+
+        DbTask {
+            id,
+            config,
+            shutdown,
+            handle,
+        }
+    }
+}
+
+async fn join_db_task(db: &str, handle: JoinHandle<Result<()>>) -> Result<()> {
+    let result = match handle.await {
+        Ok(result) => result,
+        Err(e) => Err(Error::SpawnDatabaseTaskError(format!(
+            "db {} task error: {:?}",
+            db, e
+        ))),
+    };
+    if let Err(e) = &result {
+        error!("replicate db {} error: {:?}", db, e);
+    }
+    result
 }
 
 impl Replicate {
-    pub fn try_create(config: &str) -> Result<Box<Self>> {
-        let config = Config::load(config)?;
+    pub fn try_create(config_file: &str, options: ReplicateOptions) -> Result<Box<Self>> {
+        let config = Config::load(config_file)?;
         let log_config = config.log.clone();
 
         init_log(log_config)?;
-        Ok(Box::new(Replicate { config }))
+        Ok(Box::new(Replicate {
+            config_file: config_file.to_string(),
+            config,
+            options,
+        }))
+    }
+
+    // reloads the config, stops dbs removed or changed in it and starts dbs
+    // added or changed. Dbs not changed keep running. The old config keeps
+    // running if the new one is invalid. Removed dbs are only signaled to
+    // stop, they are joined when they exit, and a changed db is restarted
+    // once its old task exited.
+    fn reload(&mut self, spawner: &mut DbSpawner, dbs: &mut DbTasks) {
+        info!("reload config {}", self.config_file);
+        let config = match Config::reload(&self.config_file) {
+            Ok(config) => config,
+            Err(e) => {
+                error!(
+                    "reload config {} error, keep running the old config: {:?}",
+                    self.config_file, e
+                );
+                return;
+            }
+        };
+
+        let stopped: Vec<String> = dbs
+            .running
+            .iter()
+            .filter(|(_, task)| !config.database.contains(&task.config))
+            .map(|(db, _)| db.clone())
+            .collect();
+        for db in stopped {
+            let task = dbs.running.remove(&db).unwrap();
+            info!("reload config stop db {}", db);
+            let _ = task.shutdown.send(true);
+            dbs.stopping.insert(task.id, task);
+        }
+
+        dbs.pending.clear();
+        for database in &config.database {
+            if dbs.running.contains_key(&database.db) {
+                continue;
+            }
+            if dbs.is_stopping(&database.db) {
+                info!("reload config restart db {} once stopped", database.db);
+                dbs.pending.insert(database.db.clone(), database.clone());
+            } else {
+                info!("reload config start db {}", database.db);
+                dbs.running
+                    .insert(database.db.clone(), spawner.spawn(database.clone()));
+            }
+        }
+
+        if config.log != self.config.log
+            || config.runtime != self.config.runtime
+            || config.metrics != self.config.metrics
+        {
+            warn!("reload config only applies database changes, restart to apply the others");
+        }
+        self.config = config;
+    }
+}
+
+// db tasks of replicate, keyed by db path except stopping ones.
+#[derive(Default)]
+struct DbTasks {
+    running: BTreeMap<String, DbTask>,
+    // dbs removed or changed by reload, keyed by task id.
+    stopping: BTreeMap<u64, DbTask>,
+    // changed dbs waiting for their old task to exit.
+    pending: BTreeMap<String, DbConfig>,
+}
+
+impl DbTasks {
+    fn is_empty(&self) -> bool {
+        self.running.is_empty() && self.stopping.is_empty()
+    }
+
+    fn is_stopping(&self, db: &str) -> bool {
+        self.stopping.values().any(|task| task.config.db == db)
+    }
+
+    // handles the exit of task `id`, returns its error if it was running.
+    async fn exited(&mut self, spawner: &mut DbSpawner, id: u64) -> Result<()> {
+        if let Some(task) = self.stopping.remove(&id) {
+            let db = task.config.db.clone();
+            let _ = join_db_task(&db, task.handle).await;
+            if let Ok(name) = path_base(&db) {
+                unregister_db(&name);
+            }
+            if let Some(config) = self.pending.remove(&db) {
+                info!("reload config start db {}", db);
+                self.running.insert(db, spawner.spawn(config));
+            }
+            return Ok(());
+        }
+
+        let Some(db) = self
+            .running
+            .iter()
+            .find(|(_, task)| task.id == id)
+            .map(|(db, _)| db.clone())
+        else {
+            return Ok(());
+        };
+        let task = self.running.remove(&db).unwrap();
+        join_db_task(&db, task.handle).await
+    }
+
+    // signals all dbs to stop first so they shut down in parallel, then
+    // waits for them, returns the last error if any db failed.
+    async fn stop(self) -> Result<()> {
+        for task in self.running.values() {
+            let _ = task.shutdown.send(true);
+        }
+
+        let mut ret = Ok(());
+        for (db, task) in self.running {
+            if let Err(e) = join_db_task(&db, task.handle).await {
+                ret = Err(e);
+            }
+        }
+        for task in self.stopping.into_values() {
+            let _ = join_db_task(&task.config.db, task.handle).await;
+        }
+        ret
     }
 }
 
 #[async_trait::async_trait]
 impl Command for Replicate {
     async fn run(&mut self) -> Result<()> {
-        let mut handles = vec![];
+        if let Some(metrics) = &self.config.metrics {
+            start_metrics_server(metrics).await?;
+        }
+
+        // run databases on a dedicated runtime, so slow sync cannot delay
+        // other tasks on the main runtime.
+        let runtime =
+            Runtime::with_worker_threads("sync", self.config.runtime.sync_worker_threads)?;
+
+        let (shutdown_sender, mut shutdown_receiver) = watch::channel(false);
+        let shutdown_sender = Arc::new(shutdown_sender);
+        let shutdown_signal = shutdown_signal()?;
+        let signal_sender = shutdown_sender.clone();
+        tokio::spawn(async move {
+            shutdown_signal.await;
+            let _ = signal_sender.send(true);
+        });
+        let mut reload_signal = reload_signal()?;
+
+        let max_concurrent_databases = self
+            .options
+            .concurrency
+            .unwrap_or(self.config.runtime.max_concurrent_databases);
+        let limiter = if max_concurrent_databases > 0 {
+            info!("max concurrent databases: {}", max_concurrent_databases);
+            Some(Arc::new(Semaphore::new(max_concurrent_databases)))
+        } else {
+            None
+        };
+
+        let (exit_sender, mut exit_receiver) = mpsc::unbounded_channel();
+        let mut spawner = DbSpawner {
+            runtime,
+            shutdown_sender,
+            exit_sender,
+            limiter,
+            fail_fast: self.options.fail_fast,
+            next_id: 0,
+        };
+        let mut dbs = DbTasks::default();
         for database in &self.config.database {
-            let datatase = database.clone();
-            let handle = tokio::spawn(async move {
-                let _ = run_database(datatase).await;
-            });
+            dbs.running
+                .insert(database.db.clone(), spawner.spawn(database.clone()));
+        }
 
-            handles.push(handle);
+        // run until shutdown or all dbs exited, the process exits with the
+        // last error if any db failed, e.g. all dbs failed to start.
+        let mut ret = Ok(());
+        while !dbs.is_empty() {
+            select! {
+                _ = shutdown_receiver.changed() => break,
+                _ = reload_requested(&mut reload_signal) => {
+                    self.reload(&mut spawner, &mut dbs);
+                }
+                Some(id) = exit_receiver.recv() => {
+                    if let Err(e) = dbs.exited(&mut spawner, id).await {
+                        ret = Err(e);
+                    }
+                }
+            }
         }
 
-        for h in handles {
-            h.await.unwrap();
+        if let Err(e) = dbs.stop().await {
+            ret = Err(e);
         }
-        Ok(())
+        ret
+    }
+}
+
+// receives SIGHUP to reload config.
+#[cfg(unix)]
+fn reload_signal() -> Result<Signal> {
+    Ok(signal(SignalKind::hangup())?)
+}
+
+#[cfg(unix)]
+async fn reload_requested(signal: &mut Signal) {
+    if signal.recv().await.is_none() {
+        pending::<()>().await;
     }
 }
+
+// there is no SIGHUP on windows, config is never reloaded.
+#[cfg(not(unix))]
+fn reload_signal() -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn reload_requested(_signal: &mut ()) {
+    pending::<()>().await;
+}
+
+// resolves when receiving SIGINT or SIGTERM.
+#[cfg(unix)]
+pub(super) fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    Ok(async move {
+        select! {
+            _ = ctrl_c() => info!("receive SIGINT, shutting down"),
+            _ = sigterm.recv() => info!("receive SIGTERM, shutting down"),
+        }
+    })
+}
+
+// resolves when receiving ctrl-c, there is no SIGTERM on windows.
+#[cfg(not(unix))]
+pub(super) fn shutdown_signal() -> Result<impl Future<Output = ()>> {
+    Ok(async move {
+        let _ = ctrl_c().await;
+        info!("receive ctrl-c, shutting down");
+    })
+}
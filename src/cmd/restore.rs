@@ -1,8 +1,12 @@
 use super::command::Command;
 use crate::config::Config;
+use crate::config::LogTarget;
 use crate::config::RestoreOptions;
+use crate::error::Error;
 use crate::error::Result;
 use crate::log::init_log;
+use crate::sync::list_generations;
+use crate::sync::print_generations;
 use crate::sync::run_restore;
 
 pub struct Restore {
@@ -15,6 +19,13 @@ impl Restore {
         let config = Config::load(config)?;
         let log_config = config.log.clone();
 
+        // stdout carries only the restored db.
+        if options.output_to_stdout() && log_config.target != LogTarget::File {
+            return Err(Error::InvalidArg(
+                "log target MUST be File when restore output is stdout",
+            ));
+        }
+
         init_log(log_config)?;
         Ok(Box::new(Restore { config, options }))
     }
@@ -25,16 +36,24 @@ impl Command for Restore {
     async fn run(&mut self) -> Result<()> {
         self.options.validate()?;
 
-        for config in &self.config.database {
-            if config.db == self.options.db {
-                let ret = run_restore(config, &self.options).await;
+        if let Some(config) = self.config.find_db(&self.options.db)? {
+            if self.options.list_generations {
+                let generations = list_generations(config).await?;
+                return print_generations(&generations, self.options.format);
+            }
+
+            let ret = run_restore(config, &self.options).await;
+            if self.options.output_to_stdout() {
+                eprintln!("restore result: {:?}", ret);
+            } else {
                 println!("restore result: {:?}", ret);
-                return Ok(());
             }
+            return ret;
         }
 
-        println!("cannot find db {} in config file", self.options.db);
-
-        Ok(())
+        Err(Error::InvalidArg(format!(
+            "cannot find db {} in config file",
+            self.options.db
+        )))
     }
 }
@@ -0,0 +1,34 @@
+use super::command::Command;
+use crate::config::Config;
+use crate::config::StatusOptions;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::db_status;
+use crate::sync::print_status;
+
+pub struct Status {
+    config: Config,
+    options: StatusOptions,
+}
+
+impl Status {
+    pub fn try_create(config: &str, options: StatusOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Status { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Status {
+    async fn run(&mut self) -> Result<()> {
+        let mut statuses = vec![];
+        for config in self.config.find_dbs(&self.options.db)? {
+            statuses.extend(db_status(config).await?);
+        }
+
+        print_status(&statuses, self.options.format)
+    }
+}
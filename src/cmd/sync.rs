@@ -0,0 +1,39 @@
+use log::error;
+
+use super::command::Command;
+use crate::config::Config;
+use crate::config::SyncOptions;
+use crate::database::sync_database;
+use crate::error::Result;
+use crate::log::init_log;
+
+pub struct SyncOnce {
+    config: Config,
+    options: SyncOptions,
+}
+
+impl SyncOnce {
+    pub fn try_create(config: &str, options: SyncOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(SyncOnce { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for SyncOnce {
+    // sync every db once even if some of them fail, returns the last error.
+    async fn run(&mut self) -> Result<()> {
+        let mut ret = Ok(());
+        for config in self.config.find_dbs(&self.options.db)? {
+            if let Err(e) = sync_database(config.clone()).await {
+                error!("sync db {} error: {:?}", config.db, e);
+                ret = Err(e);
+            }
+        }
+
+        ret
+    }
+}
@@ -0,0 +1,32 @@
+use super::command::Command;
+use crate::config::Config;
+use crate::config::ThawOptions;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::run_thaw;
+
+pub struct Thaw {
+    config: Config,
+    options: ThawOptions,
+}
+
+impl Thaw {
+    pub fn try_create(config: &str, options: ThawOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Thaw { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Thaw {
+    async fn run(&mut self) -> Result<()> {
+        for config in self.config.find_dbs(&self.options.db)? {
+            run_thaw(config, &self.options).await?;
+        }
+
+        Ok(())
+    }
+}
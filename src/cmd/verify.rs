@@ -0,0 +1,39 @@
+use log::error;
+
+use super::command::Command;
+use crate::config::Config;
+use crate::config::VerifyOptions;
+use crate::error::Result;
+use crate::log::init_log;
+use crate::sync::run_verify;
+
+pub struct Verify {
+    config: Config,
+    options: VerifyOptions,
+}
+
+impl Verify {
+    pub fn try_create(config: &str, options: VerifyOptions) -> Result<Box<Self>> {
+        let config = Config::load(config)?;
+        let log_config = config.log.clone();
+
+        init_log(log_config)?;
+        Ok(Box::new(Verify { config, options }))
+    }
+}
+
+#[async_trait::async_trait]
+impl Command for Verify {
+    // verify every db even if some of them fail, returns the last error.
+    async fn run(&mut self) -> Result<()> {
+        let mut ret = Ok(());
+        for config in self.config.find_dbs(&self.options.db)? {
+            if let Err(e) = run_verify(config, &self.options).await {
+                error!("verify db {} error: {:?}", config.db, e);
+                ret = Err(e);
+            }
+        }
+
+        ret
+    }
+}
@@ -1,43 +1,125 @@
+use chrono::DateTime;
+use chrono::Utc;
 use clap::Parser;
 use clap::Subcommand;
+use clap::ValueEnum;
 
 use crate::error::Error;
 use crate::error::Result;
 
+// restore output writing the restored db to stdout.
+const STDOUT_OUTPUT: &str = "-";
+
 #[derive(Parser, Debug)]
 #[command(author="replited", version, about="Replicate sqlite to everywhere", long_about = None)]
 pub struct Arg {
+    // config file path, "-" to read config from stdin.
+    // ignored if `REPLITED_CONFIG` env is set to an inline toml config.
     #[arg(short, long, default_value = "/etc/replited.toml")]
     pub config: String,
 
+    // worker threads of the main runtime, 0 means number of cpu cores.
+    #[arg(long, default_value_t = 0)]
+    pub worker_threads: usize,
+
     #[command(subcommand)]
     pub cmd: ArgCommand,
 }
 
 #[derive(Subcommand, Clone, Debug)]
 pub enum ArgCommand {
-    Replicate,
+    Replicate(ReplicateOptions),
 
     Restore(RestoreOptions),
+
+    Diff(DiffOptions),
+
+    List(ListOptions),
+
+    Prune(PruneOptions),
+
+    Thaw(ThawOptions),
+
+    Rekey(RekeyOptions),
+
+    Verify(VerifyOptions),
+
+    Sync(SyncOptions),
+
+    Status(StatusOptions),
+
+    Follow(FollowOptions),
+
+    CheckConfig(CheckConfigOptions),
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ReplicateOptions {
+    // shut down all dbs and exit once any db fails, instead of keeping the
+    // others running
+    #[arg(long, default_value_t = false)]
+    pub fail_fast: bool,
+
+    // max number of databases syncing at the same time, overrides
+    // `max_concurrent_databases` in runtime config, 0 means unlimited
+    #[arg(long)]
+    pub concurrency: Option<usize>,
 }
 
 #[derive(Parser, Debug, Clone)]
 pub struct RestoreOptions {
-    // restore db path in config file
+    // restore db path in config file, or the base name of it
     #[arg(short, long, default_value = "")]
     pub db: String,
 
-    // restore db output path
+    // restore db output path, `-` writes the restored db to stdout
     #[arg(long, default_value = "")]
     pub output: String,
+
     // restore db generation string.
     // when empty, use the most recent generation from replicates.
-    //#[arg(short, long, default_value = "")]
-    // pub generation: String,
+    #[arg(short, long, default_value = "")]
+    pub generation: String,
 
     // if overwrite existing db in the same path
-    //#[arg(long, default_value_t = false)]
-    // pub overwrite: bool,
+    #[arg(long, default_value_t = false)]
+    pub overwrite: bool,
+
+    // run integrity_check and foreign_key_check on restored db before moving it to output
+    #[arg(long, default_value_t = false)]
+    pub verify: bool,
+
+    // restore wal segments up to the first gap instead of failing the restore
+    #[arg(long, default_value_t = false)]
+    pub best_effort: bool,
+
+    // restore db to the state at this RFC3339 timestamp, e.g. 2024-01-01T02:00:00Z.
+    // when empty, restore to the latest state.
+    #[arg(long)]
+    pub timestamp: Option<DateTime<Utc>>,
+
+    // keep replited internal tables(`_replited_seq` and `_replited_lock`)
+    // in restored db, which are dropped by default
+    #[arg(long, default_value_t = false)]
+    pub keep_internal_tables: bool,
+
+    // list generations of db in replicates instead of restore
+    #[arg(long, default_value_t = false)]
+    pub list_generations: bool,
+
+    // output format of list generations
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    // do not print restore progress
+    #[arg(short, long, default_value_t = false)]
+    pub quiet: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
 }
 
 impl RestoreOptions {
@@ -47,11 +129,223 @@ impl RestoreOptions {
             return Err(Error::InvalidArg("arg MUST Specify db path in config"));
         }
 
+        if self.list_generations {
+            return Ok(());
+        }
+
         if self.output.is_empty() {
             println!("restore MUST Specify db output path");
             return Err(Error::InvalidArg("arg MUST Specify db output pathg"));
         }
 
+        if self.output_to_stdout() && self.overwrite {
+            eprintln!("restore cannot overwrite stdout output");
+            return Err(Error::InvalidArg(
+                "arg overwrite cannot be used with stdout output",
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn output_to_stdout(&self) -> bool {
+        self.output == STDOUT_OUTPUT
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct DiffOptions {
+    // db path in config file, or the base name of it
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // generation to diff from
+    #[arg(long, default_value = "")]
+    pub from: String,
+
+    // generation to diff to
+    #[arg(long, default_value = "")]
+    pub to: String,
+}
+
+impl DiffOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.db.is_empty() {
+            println!("diff MUST Specify db path in config");
+            return Err(Error::InvalidArg("arg MUST Specify db path in config"));
+        }
+
+        if self.from.is_empty() || self.to.is_empty() {
+            println!("diff MUST Specify both from and to generation");
+            return Err(Error::InvalidArg(
+                "arg MUST Specify both from and to generation",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct SyncOptions {
+    // db path in config file, or the base name of it.
+    // when empty, sync all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ListOptions {
+    // db path in config file, or the base name of it.
+    // when empty, list all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // output format of generations
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct StatusOptions {
+    // db path in config file, or the base name of it.
+    // when empty, show status of all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // output format of status
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct PruneOptions {
+    // db path in config file, or the base name of it.
+    // when empty, prune all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // report the generations and objects which would be removed without
+    // removing them.
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct RekeyOptions {
+    // db path in config file, or the base name of it.
+    // when empty, rekey all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct ThawOptions {
+    // generation whose archived snapshots and wal segments to restore.
+    pub generation: String,
+
+    // db path in config file, or the base name of it.
+    // when empty, thaw all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // days to keep the restored copies of archived objects.
+    #[arg(long, default_value_t = 1)]
+    pub days: u32,
+
+    // retrieval tier of restore requests, "Expedited", "Standard" or "Bulk".
+    #[arg(long, default_value = "Standard")]
+    pub tier: String,
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct VerifyOptions {
+    // db path in config file, or the base name of it.
+    // when empty, verify all dbs in config file.
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // generation to verify.
+    // when empty, use the most recent generation from replicates.
+    #[arg(short, long, default_value = "")]
+    pub generation: String,
+}
+
+impl VerifyOptions {
+    // options of restoring db into a temp file to verify it.
+    pub fn restore_options(&self, db: &str) -> RestoreOptions {
+        RestoreOptions {
+            db: db.to_string(),
+            output: String::new(),
+            generation: self.generation.clone(),
+            overwrite: false,
+            verify: true,
+            best_effort: false,
+            timestamp: None,
+            keep_internal_tables: true,
+            list_generations: false,
+            format: OutputFormat::Text,
+            quiet: false,
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct FollowOptions {
+    // db path in config file, or the base name of it
+    #[arg(short, long, default_value = "")]
+    pub db: String,
+
+    // path of the follower db kept up to date
+    #[arg(long, default_value = "")]
+    pub output: String,
+
+    // seconds between polling replicates for new wal segments
+    #[arg(long, default_value_t = 5)]
+    pub interval_secs: u64,
+}
+
+impl FollowOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.db.is_empty() {
+            return Err(Error::InvalidArg("arg MUST Specify db path in config"));
+        }
+
+        if self.output.is_empty() {
+            return Err(Error::InvalidArg("arg MUST Specify db output path"));
+        }
+
+        if self.interval_secs == 0 {
+            return Err(Error::InvalidArg("interval_secs cannot be zero"));
+        }
+
         Ok(())
     }
+
+    // restore options of the initial restore. internal tables are kept, the
+    // follower db MUST stay identical to the replicated db page by page, or
+    // wal frames applied later corrupt it.
+    pub fn restore_options(&self, db: &str) -> RestoreOptions {
+        RestoreOptions {
+            db: db.to_string(),
+            output: self.output.clone(),
+            generation: String::new(),
+            overwrite: true,
+            verify: false,
+            best_effort: false,
+            timestamp: None,
+            keep_internal_tables: true,
+            list_generations: false,
+            format: OutputFormat::Text,
+            quiet: true,
+        }
+    }
+}
+
+#[derive(Parser, Debug, Clone)]
+pub struct CheckConfigOptions {
+    // probe each replicate storage by listing its root, besides validating
+    // the config.
+    #[arg(long, default_value_t = false)]
+    pub probe: bool,
 }
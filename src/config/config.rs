@@ -1,54 +1,259 @@
+use std::cmp::max;
+use std::env;
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
 use std::fs;
+use std::io;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::path::Path;
 
 use serde::Deserialize;
 
 use super::StorageParams;
+use crate::base::mask_string;
+use crate::base::path_base;
+use crate::base::set_compress_buffer_size;
+use crate::base::set_compress_threads;
+use crate::base::Compression;
+use crate::base::DEFAULT_COMPRESS_BUFFER_SIZE;
+use crate::base::ENCRYPTION_KEY_LEN;
+use crate::base::MAX_COMPRESSION_LEVEL;
 use crate::error::Error;
 use crate::error::Result;
+use crate::sqlite::MAX_PAGE_SIZE;
+use crate::sqlite::WAL_FRAME_HEADER_SIZE;
+use crate::sqlite::WAL_HEADER_SIZE;
+
+// env var holding the inline toml config, takes precedence over config file.
+const CONFIG_ENV: &str = "REPLITED_CONFIG";
+// config file name meaning read config from stdin.
+const STDIN_CONFIG_FILE: &str = "-";
+// replaced by the host name in the prefix of replicates.
+const HOSTNAME_PLACEHOLDER: &str = "{hostname}";
+// suffixes of sqlite files next to a db, never matched by a db glob.
+const DB_SIDE_FILE_SUFFIXES: [&str; 3] = ["-wal", "-shm", "-journal"];
+
+const DEFAULT_LOG_DIR: &str = "/var/log/replited";
+const DEFAULT_LOG_ROLLOVER_SIZE_BYTES: usize = 4 * 1024 * 1024;
+const DEFAULT_LOG_MAX_FILES: usize = 9;
 
 const DEFAULT_MIN_CHECKPOINT_PAGE_NUMBER: u64 = 1000;
 const DEFAULT_MAX_CHECKPOINT_PAGE_NUMBER: u64 = 10000;
 const DEFAULT_TRUNCATE_PAGE_NUMBER: u64 = 500000;
 const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 60;
+const DEFAULT_MONITOR_INTERVAL_MS: u64 = 1000;
+const DEFAULT_WATCH_DEBOUNCE_MS: u64 = 100;
+const DEFAULT_CHANNEL_CAPACITY: usize = 16;
+const DEFAULT_LOCK_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_SHRINK_PERCENT: u64 = 25;
+const DEFAULT_DELETE_CONCURRENCY: usize = 100;
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 0;
+const DEFAULT_STATUS_INTERVAL_SECS: u64 = 60;
+const DEFAULT_STORAGE_MAX_RETRIES: usize = 3;
+const DEFAULT_STORAGE_BASE_DELAY_MS: u64 = 1000;
+const DEFAULT_STORAGE_MAX_DELAY_MS: u64 = 30000;
+const DEFAULT_RETENTION_CHECK_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_COMPACTION_TARGET_SIZE: u64 = 16 * 1024 * 1024;
 
 #[derive(Clone, PartialEq, Eq, Deserialize)]
 pub struct Config {
     pub log: LogConfig,
 
+    #[serde(default)]
+    pub runtime: RuntimeConfig,
+
+    // metrics http server, disabled if None.
+    #[serde(default)]
+    pub metrics: Option<MetricsConfig>,
+
     pub database: Vec<DbConfig>,
 }
 
 impl Config {
+    // load config from one of these sources, in order:
+    // 1. stdin, if config_file is "-";
+    // 2. inline toml in `REPLITED_CONFIG` env, if it is set and not empty;
+    // 3. the config file.
     pub fn load(config_file: &str) -> Result<Self> {
-        let toml_str = match fs::read_to_string(config_file) {
-            Ok(toml_str) => toml_str,
+        let config = Self::parse(config_file)?;
+        set_compress_buffer_size(config.runtime.compress_buffer_size);
+        set_compress_threads(config.runtime.compress_threads);
+        Ok(config)
+    }
+
+    // load config on reload, runtime settings are not applied since they
+    // only take effect on restart.
+    pub fn reload(config_file: &str) -> Result<Self> {
+        Self::parse(config_file)
+    }
+
+    fn parse(config_file: &str) -> Result<Self> {
+        let (toml_str, config_file) = Self::read_config(config_file)?;
+
+        // parse into a toml value first, so env references are only expanded
+        // in string values but not in comments.
+        let mut value: toml::Value = match toml::from_str(&toml_str) {
+            Ok(value) => value,
             Err(e) => {
-                return Err(Error::ReadConfigFail(format!(
-                    "read config file {} fail: {:?}",
+                return Err(Error::ParseConfigFail(format!(
+                    "parse config from {} fail: {:?}",
                     config_file, e,
                 )));
             }
         };
+        if let Err(e) = expand_env_value(&mut value) {
+            return Err(Error::ParseConfigFail(format!(
+                "parse config from {} fail: {}",
+                config_file,
+                e.message(),
+            )));
+        }
 
-        let config: Config = match toml::from_str(&toml_str) {
+        let mut config: Config = match value.try_into() {
             Ok(config) => config,
             Err(e) => {
                 return Err(Error::ParseConfigFail(format!(
-                    "parse config file {} fail: {:?}",
+                    "parse config from {} fail: {:?}",
                     config_file, e,
                 )));
             }
         };
 
+        for db in &mut config.database {
+            for replicate in &mut db.replicate {
+                replicate.params.load_credential_files()?;
+            }
+        }
+
+        config.expand_db_globs()?;
         config.validate()?;
         Ok(config)
     }
 
+    // replaces each db config whose db file name is a glob with one db config
+    // per matched file, dbs configured explicitly take precedence.
+    fn expand_db_globs(&mut self) -> Result<()> {
+        if !self.database.iter().any(|db| is_glob(&db.db)) {
+            return Ok(());
+        }
+
+        let mut dbs: Vec<String> = self
+            .database
+            .iter()
+            .filter(|db| !is_glob(&db.db))
+            .map(|db| db.db.clone())
+            .collect();
+        let mut database = Vec::with_capacity(self.database.len());
+        for config in self.database.drain(..) {
+            if !is_glob(&config.db) {
+                database.push(config);
+                continue;
+            }
+
+            for db in expand_db_glob(&config.db)? {
+                if dbs.contains(&db) {
+                    continue;
+                }
+                dbs.push(db.clone());
+                database.push(DbConfig {
+                    db,
+                    ..config.clone()
+                });
+            }
+        }
+        self.database = database;
+
+        Ok(())
+    }
+
+    // return config toml string and the description of its source.
+    fn read_config(config_file: &str) -> Result<(String, String)> {
+        Self::read_config_from(config_file, io::stdin(), env::var(CONFIG_ENV).ok())
+    }
+
+    // see `read_config`, with stdin and the value of `REPLITED_CONFIG` env.
+    fn read_config_from(
+        config_file: &str,
+        mut stdin: impl Read,
+        env_config: Option<String>,
+    ) -> Result<(String, String)> {
+        if config_file == STDIN_CONFIG_FILE {
+            let mut toml_str = String::new();
+            if let Err(e) = stdin.read_to_string(&mut toml_str) {
+                return Err(Error::ReadConfigFail(format!(
+                    "read config from stdin fail: {:?}",
+                    e,
+                )));
+            }
+            return Ok((toml_str, "stdin".to_string()));
+        }
+
+        if let Some(toml_str) = env_config {
+            if !toml_str.trim().is_empty() {
+                return Ok((toml_str, format!("env {}", CONFIG_ENV)));
+            }
+        }
+
+        match fs::read_to_string(config_file) {
+            Ok(toml_str) => Ok((toml_str, config_file.to_string())),
+            Err(e) => Err(Error::ReadConfigFail(format!(
+                "read config file {} fail: {:?}",
+                config_file, e,
+            ))),
+        }
+    }
+
+    // find db config by full db path first, then fallback to match by db base name,
+    // so the config can be used on machines with different paths.
+    pub fn find_db(&self, db: &str) -> Result<Option<&DbConfig>> {
+        if let Some(config) = self.database.iter().find(|config| config.db == db) {
+            return Ok(Some(config));
+        }
+
+        let name = path_base(db)?;
+        let mut found = None;
+        for config in &self.database {
+            if path_base(&config.db)? != name {
+                continue;
+            }
+            if found.is_some() {
+                return Err(Error::InvalidArg(format!(
+                    "db name {} matches more than one db in config, use full db path instead",
+                    name
+                )));
+            }
+            found = Some(config);
+        }
+
+        Ok(found)
+    }
+
+    // returns all db configs if db is empty, otherwise the one found by `find_db`.
+    pub fn find_dbs(&self, db: &str) -> Result<Vec<&DbConfig>> {
+        if db.is_empty() {
+            return Ok(self.database.iter().collect());
+        }
+
+        match self.find_db(db)? {
+            Some(config) => Ok(vec![config]),
+            None => Err(Error::InvalidArg(format!(
+                "cannot find db {} in config file",
+                db
+            ))),
+        }
+    }
+
     fn validate(&self) -> Result<()> {
+        self.log.validate()?;
+        self.runtime.validate()?;
+
+        if let Some(metrics) = &self.metrics {
+            metrics.validate()?;
+        }
+
         if self.database.is_empty() {
             return Err(Error::InvalidConfig(
                 "config MUST has at least one database config",
@@ -61,11 +266,279 @@ impl Config {
     }
 }
 
+// only the file name of a db may be a glob, with `*` and `?`.
+fn is_glob(db: &str) -> bool {
+    Path::new(db)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.contains(['*', '?']))
+}
+
+// returns the sorted paths of db files matching the glob. Directories, hidden
+// files(unless the glob starts with `.`) and the -wal, -shm and -journal files
+// of dbs are not matched.
+fn expand_db_glob(glob: &str) -> Result<Vec<String>> {
+    let path = Path::new(glob);
+    let pattern = path.file_name().and_then(|name| name.to_str()).unwrap();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(Error::InvalidConfig(format!(
+                "read dir of db glob {} fail: {:?}",
+                glob, e
+            )));
+        }
+    };
+
+    let mut dbs = vec![];
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name.starts_with('.') && !pattern.starts_with('.') {
+            continue;
+        }
+        if DB_SIDE_FILE_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+        {
+            continue;
+        }
+        if glob_match(pattern.as_bytes(), name.as_bytes()) {
+            dbs.push(dir.join(name).to_str().unwrap().to_string());
+        }
+    }
+    dbs.sort();
+
+    Ok(dbs)
+}
+
+// matches name against pattern, `*` matches any bytes and `?` any one byte.
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` in pattern and the name position it matched.
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            // let the last `*` match one more byte.
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, n));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+// expands env references in all string values of a toml value.
+fn expand_env_value(value: &mut toml::Value) -> Result<()> {
+    match value {
+        toml::Value::String(s) => *s = expand_env(s)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                expand_env_value(value)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                expand_env_value(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// expands `${VAR}` and `${VAR:-default}` with env vars, `$${` is kept as a
+// literal `${`. fails if a var is not set and has no default.
+fn expand_env(s: &str) -> Result<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("$${") {
+            result.push_str("${");
+            rest = &rest[3..];
+            continue;
+        }
+        if !rest.starts_with("${") {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        }
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => {
+                return Err(Error::ParseConfigFail(format!(
+                    "unclosed env reference in {}",
+                    s
+                )));
+            }
+        };
+        let reference = &rest[2..end];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        match (env::var(name), default) {
+            (Ok(value), _) => result.push_str(&value),
+            (Err(_), Some(default)) => result.push_str(default),
+            (Err(_), None) => {
+                return Err(Error::ParseConfigFail(format!(
+                    "env {} referenced in config is not set",
+                    name
+                )));
+            }
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Config for metrics http server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct MetricsConfig {
+    // address the metrics server listens on, e.g. "0.0.0.0:9090".
+    pub addr: String,
+}
+
+impl MetricsConfig {
+    fn validate(&self) -> Result<()> {
+        if self.addr.parse::<SocketAddr>().is_err() {
+            return Err(Error::InvalidConfig(format!(
+                "metrics addr {} is not a valid socket address",
+                self.addr
+            )));
+        }
+
+        Ok(())
+    }
+}
+
 /// Config for logging.
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
 pub struct LogConfig {
     pub level: LogLevel,
+
+    // directory of log files, not used if logs are only written to stdout.
+    #[serde(default = "default_log_dir")]
     pub dir: String,
+
+    // layout of log records.
+    #[serde(default)]
+    pub format: LogFormat,
+
+    // where log records are written to.
+    #[serde(default)]
+    pub target: LogTarget,
+
+    // size in bytes of a log file before rolling over to a new one.
+    #[serde(default = "default_log_rollover_size_bytes")]
+    pub rollover_size_bytes: usize,
+
+    // max number of log files kept, the oldest ones are removed.
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+fn default_log_dir() -> String {
+    DEFAULT_LOG_DIR.to_string()
+}
+
+fn default_log_rollover_size_bytes() -> usize {
+    DEFAULT_LOG_ROLLOVER_SIZE_BYTES
+}
+
+fn default_log_max_files() -> usize {
+    DEFAULT_LOG_MAX_FILES
+}
+
+impl LogConfig {
+    fn validate(&self) -> Result<()> {
+        if self.rollover_size_bytes == 0 {
+            return Err(Error::InvalidConfig(
+                "log rollover_size_bytes cannot be zero",
+            ));
+        }
+
+        if self.max_files == 0 {
+            return Err(Error::InvalidConfig("log max_files cannot be zero"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Config for runtimes.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct RuntimeConfig {
+    // worker threads of the dedicated runtime running database sync and
+    // replicate tasks, 0 means number of cpu cores.
+    #[serde(default)]
+    pub sync_worker_threads: usize,
+
+    // max number of databases syncing or snapshotting at the same time, the
+    // others wait for their turn, 0 means unlimited.
+    #[serde(default)]
+    pub max_concurrent_databases: usize,
+
+    // size in bytes of the buffer used to compress and decompress snapshots
+    // and wal segments.
+    #[serde(default = "default_compress_buffer_size")]
+    pub compress_buffer_size: usize,
+
+    // number of threads compressing and decompressing blocks of `Lz4Block`
+    // snapshots and wal segments, 0 means the number of cpus.
+    #[serde(default)]
+    pub compress_threads: usize,
+}
+
+fn default_compress_buffer_size() -> usize {
+    DEFAULT_COMPRESS_BUFFER_SIZE
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self {
+            sync_worker_threads: 0,
+            max_concurrent_databases: 0,
+            compress_buffer_size: default_compress_buffer_size(),
+            compress_threads: 0,
+        }
+    }
+}
+
+impl RuntimeConfig {
+    fn validate(&self) -> Result<()> {
+        if self.compress_buffer_size == 0 {
+            return Err(Error::InvalidConfig("compress_buffer_size cannot be zero"));
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
@@ -78,6 +551,24 @@ pub enum LogLevel {
     Trace,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    // one json object per record, with level, timestamp, target and message fields.
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+pub enum LogTarget {
+    // rolling files in log dir.
+    #[default]
+    File,
+    Stdout,
+    // both rolling files and stdout, with the same level.
+    Both,
+}
+
 impl From<LogLevel> for log::LevelFilter {
     fn from(level: LogLevel) -> Self {
         match &level {
@@ -95,14 +586,22 @@ impl Default for LogConfig {
     fn default() -> Self {
         Self {
             level: LogLevel::Info,
-            dir: "/var/log/replited".to_string(),
+            dir: default_log_dir(),
+            format: LogFormat::Text,
+            target: LogTarget::File,
+            rollover_size_bytes: default_log_rollover_size_bytes(),
+            max_files: default_log_max_files(),
         }
     }
 }
 
 impl Display for LogConfig {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "level={:?}, dir={}", self.level, self.dir)
+        write!(
+            f,
+            "level={:?}, dir={}, format={:?}, target={:?}",
+            self.level, self.dir, self.format, self.target
+        )
     }
 }
 
@@ -145,6 +644,65 @@ pub struct DbConfig {
     // better precision.
     #[serde(default = "default_checkpoint_interval_secs")]
     pub checkpoint_interval_secs: u64,
+
+    // Force a truncation checkpoint before snapshot, and fail the snapshot if
+    // the WAL cannot be fully checkpointed, so the snapshot is a standalone
+    // valid db file without anything left in the WAL.
+    #[serde(default)]
+    pub truncate_before_snapshot: bool,
+
+    // run VACUUM when a new generation starts, so free pages are dropped from
+    // its snapshot.
+    #[serde(default)]
+    pub vacuum_before_snapshot: bool,
+
+    // start a new generation when the db shrinks by at least this percent of
+    // its pages between syncs, e.g. after VACUUM, zero disables it.
+    #[serde(default = "default_shrink_percent")]
+    pub shrink_percent: u64,
+
+    // copy the db file before snapshot and compress the copy without
+    // blocking the db, needs free disk space of the db size in meta dir.
+    #[serde(default)]
+    pub copy_before_snapshot: bool,
+
+    // run a truncate checkpoint on shutdown after the last sync, so the WAL is
+    // left empty when replicate exits.
+    #[serde(default)]
+    pub truncate_on_shutdown: bool,
+
+    // Milliseconds between checking the WAL for changes to sync.
+    #[serde(default = "default_monitor_interval_ms")]
+    pub monitor_interval_ms: u64,
+
+    // Milliseconds the monitor interval grows up to, doubling after each
+    // check without WAL changes, zero disables the backoff.
+    #[serde(default)]
+    pub max_monitor_interval_ms: u64,
+
+    // Sync when the WAL file is modified instead of only polling it every
+    // `monitor_interval_ms`, which becomes a safety net.
+    #[serde(default)]
+    pub watch_wal: bool,
+
+    // Milliseconds to wait after a WAL modification, so modifications within
+    // it trigger only one sync.
+    #[serde(default = "default_watch_debounce_ms")]
+    pub watch_debounce_ms: u64,
+
+    // capacity of the command channels between the db and its replicates.
+    #[serde(default = "default_channel_capacity")]
+    pub channel_capacity: usize,
+
+    // Seconds to wait at startup for the db file to be created by the app,
+    // retrying with backoff. Zero opens(and creates if missing) db at once.
+    #[serde(default)]
+    pub wait_for_db_secs: u64,
+
+    // Seconds to retry opening db with backoff while another connection holds
+    // a lock which prevents switching it to WAL mode.
+    #[serde(default = "default_lock_timeout_secs")]
+    pub lock_timeout_secs: u64,
 }
 
 fn default_min_checkpoint_page_number() -> u64 {
@@ -163,6 +721,26 @@ fn default_checkpoint_interval_secs() -> u64 {
     DEFAULT_CHECKPOINT_INTERVAL_SECS
 }
 
+fn default_shrink_percent() -> u64 {
+    DEFAULT_SHRINK_PERCENT
+}
+
+fn default_monitor_interval_ms() -> u64 {
+    DEFAULT_MONITOR_INTERVAL_MS
+}
+
+fn default_watch_debounce_ms() -> u64 {
+    DEFAULT_WATCH_DEBOUNCE_MS
+}
+
+fn default_channel_capacity() -> usize {
+    DEFAULT_CHANNEL_CAPACITY
+}
+
+fn default_lock_timeout_secs() -> u64 {
+    DEFAULT_LOCK_TIMEOUT_SECS
+}
+
 impl Debug for DbConfig {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct("ReplicateDbConfig")
@@ -178,6 +756,18 @@ impl Debug for DbConfig {
             )
             .field("truncate_page_number", &self.truncate_page_number)
             .field("checkpoint_interval_secs", &self.checkpoint_interval_secs)
+            .field("truncate_before_snapshot", &self.truncate_before_snapshot)
+            .field("vacuum_before_snapshot", &self.vacuum_before_snapshot)
+            .field("shrink_percent", &self.shrink_percent)
+            .field("copy_before_snapshot", &self.copy_before_snapshot)
+            .field("truncate_on_shutdown", &self.truncate_on_shutdown)
+            .field("monitor_interval_ms", &self.monitor_interval_ms)
+            .field("max_monitor_interval_ms", &self.max_monitor_interval_ms)
+            .field("watch_wal", &self.watch_wal)
+            .field("watch_debounce_ms", &self.watch_debounce_ms)
+            .field("channel_capacity", &self.channel_capacity)
+            .field("wait_for_db_secs", &self.wait_for_db_secs)
+            .field("lock_timeout_secs", &self.lock_timeout_secs)
             .finish()
     }
 }
@@ -196,10 +786,76 @@ impl DbConfig {
             ));
         }
 
-        if self.min_checkpoint_page_number > self.max_checkpoint_page_number {
-            return Err(Error::InvalidConfig(
-                "min_checkpoint_page_number cannot bigger than max_checkpoint_page_number",
-            ));
+        if self.monitor_interval_ms == 0 {
+            return Err(Error::InvalidConfig("monitor_interval_ms cannot be zero"));
+        }
+
+        if self.max_monitor_interval_ms > 0
+            && self.max_monitor_interval_ms < self.monitor_interval_ms
+        {
+            return Err(Error::InvalidConfig(format!(
+                "max_monitor_interval_ms {} of db {} cannot be less than monitor_interval_ms {}",
+                self.max_monitor_interval_ms, self.db, self.monitor_interval_ms
+            )));
+        }
+
+        if self.channel_capacity == 0 {
+            return Err(Error::InvalidConfig("channel_capacity cannot be zero"));
+        }
+
+        if self.shrink_percent > 100 {
+            return Err(Error::InvalidConfig(format!(
+                "shrink_percent {} of db {} MUST be in range 0..=100",
+                self.shrink_percent, self.db
+            )));
+        }
+
+        // zero max_checkpoint_page_number and truncate_page_number disable them.
+        if self.max_checkpoint_page_number > 0
+            && self.min_checkpoint_page_number > self.max_checkpoint_page_number
+        {
+            return Err(Error::InvalidConfig(format!(
+                "min_checkpoint_page_number {} of db {} cannot be greater than max_checkpoint_page_number {}",
+                self.min_checkpoint_page_number, self.db, self.max_checkpoint_page_number
+            )));
+        }
+
+        let lower = max(
+            self.min_checkpoint_page_number,
+            self.max_checkpoint_page_number,
+        );
+        if self.truncate_page_number > 0 && lower > self.truncate_page_number {
+            return Err(Error::InvalidConfig(format!(
+                "truncate_page_number {} of db {} cannot be less than min_checkpoint_page_number or max_checkpoint_page_number {}",
+                self.truncate_page_number, self.db, lower
+            )));
+        }
+
+        for (name, pages) in [
+            (
+                "min_checkpoint_page_number",
+                self.min_checkpoint_page_number,
+            ),
+            (
+                "max_checkpoint_page_number",
+                self.max_checkpoint_page_number,
+            ),
+            ("truncate_page_number", self.truncate_page_number),
+        ] {
+            // WAL size of the threshold with the max page size must fit in u64.
+            let wal_size = (WAL_FRAME_HEADER_SIZE + MAX_PAGE_SIZE)
+                .checked_mul(pages)
+                .and_then(|size| size.checked_add(WAL_HEADER_SIZE));
+            if wal_size.is_none() {
+                return Err(Error::InvalidConfig(format!(
+                    "{} {} of db {} is too large",
+                    name, pages, self.db
+                )));
+            }
+        }
+
+        for replicate in &self.replicate {
+            replicate.validate()?;
         }
         Ok(())
     }
@@ -209,6 +865,145 @@ impl DbConfig {
 pub struct StorageConfig {
     pub name: String,
     pub params: StorageParams,
+
+    // prefix of the remote paths of dbs under the root of the replicate, so
+    // hosts replicating dbs of the same file name into the same root do not
+    // collide. `{hostname}` in it is replaced by the host name.
+    #[serde(default)]
+    pub prefix: String,
+
+    // Max number of objects removed in one batch when pruning remote objects.
+    // For backends which support batch delete it is the batch size, otherwise
+    // it is the number of concurrent delete requests.
+    #[serde(default = "default_delete_concurrency")]
+    pub delete_concurrency: usize,
+
+    // Seconds between writing the heartbeat object to the replicate, even if
+    // there is nothing to sync. Zero disables the heartbeat.
+    #[serde(default = "default_heartbeat_interval_secs")]
+    pub heartbeat_interval_secs: u64,
+
+    // Min seconds between writing the status object to the replicate after
+    // syncs, for monitoring without access to the host. Zero disables it.
+    #[serde(default = "default_status_interval_secs")]
+    pub status_interval_secs: u64,
+
+    // compression of snapshots and wal segments, `None` for data which is
+    // already compressed.
+    #[serde(default)]
+    pub compression: Compression,
+
+    // lz4 compression level of snapshots and wal segments, from 0(fast, the
+    // default) to 12(slow but smallest).
+    #[serde(default)]
+    pub compression_level: u32,
+
+    // client side encryption of snapshots and wal segments, disabled if None.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+
+    // Max age in seconds of the oldest wal segment after the latest snapshot,
+    // when exceeded take a new snapshot and remove wal segments before it.
+    // Zero disables the policy.
+    #[serde(default)]
+    pub max_wal_segment_age_secs: u64,
+
+    // retention policy of generations in the replicate.
+    #[serde(default)]
+    pub retention: RetentionConfig,
+
+    // compaction of small wal segments in the replicate.
+    #[serde(default)]
+    pub compaction: CompactionConfig,
+
+    // proxy of http based storage backends, `HTTP_PROXY`, `HTTPS_PROXY` and
+    // `NO_PROXY` envs are used if not set.
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+
+    // tuning of the http client of http based storage backends.
+    #[serde(default)]
+    pub http: HttpConfig,
+
+    // Max number of retries of storage operations failed with temporary errors,
+    // with jittered exponential backoff from `base_delay_ms` to `max_delay_ms`.
+    // Zero disables retry.
+    #[serde(default = "default_storage_max_retries")]
+    pub max_retries: usize,
+
+    #[serde(default = "default_storage_base_delay_ms")]
+    pub base_delay_ms: u64,
+
+    #[serde(default = "default_storage_max_delay_ms")]
+    pub max_delay_ms: u64,
+
+    // store a sha256 sidecar object of each snapshot and wal segment, and
+    // verify it when reading them back.
+    #[serde(default)]
+    pub checksum: bool,
+
+    // recompute checksums of wal frames read from the shadow wal before
+    // uploading them, costs some cpu.
+    #[serde(default)]
+    pub verify_wal_checksum: bool,
+
+    // path of the pem file of extra ca certificates trusted by http based
+    // storage backends, e.g. the private ca of self-hosted s3.
+    #[serde(default)]
+    pub ca_cert_path: String,
+
+    // allow insecure settings of the replicate, MUST be true to use plaintext
+    // endpoints like `http://` and `ftp://`, and the dangerous options below.
+    #[serde(default)]
+    pub allow_insecure: bool,
+
+    // skip certificate verification of http based storage backends.
+    #[serde(default)]
+    pub danger_accept_invalid_certs: bool,
+
+    // max bytes of snapshots and wal segments uploaded per second to the
+    // replicate, zero means unlimited.
+    #[serde(default)]
+    pub max_upload_bytes_per_sec: u64,
+
+    // probe the replicate when replicate starts, so misconfigurations like a
+    // wrong bucket or bad credentials fail fast. disable it for backends where
+    // listing the root is expensive.
+    #[serde(default = "default_verify_on_start")]
+    pub verify_on_start: bool,
+
+    // create the bucket of the replicate when replicate starts if it does
+    // not exist, only s3 and fs support it.
+    #[serde(default)]
+    pub create_bucket: bool,
+}
+
+fn default_delete_concurrency() -> usize {
+    DEFAULT_DELETE_CONCURRENCY
+}
+
+fn default_heartbeat_interval_secs() -> u64 {
+    DEFAULT_HEARTBEAT_INTERVAL_SECS
+}
+
+fn default_status_interval_secs() -> u64 {
+    DEFAULT_STATUS_INTERVAL_SECS
+}
+
+fn default_storage_max_retries() -> usize {
+    DEFAULT_STORAGE_MAX_RETRIES
+}
+
+fn default_storage_base_delay_ms() -> u64 {
+    DEFAULT_STORAGE_BASE_DELAY_MS
+}
+
+fn default_storage_max_delay_ms() -> u64 {
+    DEFAULT_STORAGE_MAX_DELAY_MS
+}
+
+fn default_verify_on_start() -> bool {
+    true
 }
 
 impl Debug for StorageConfig {
@@ -216,6 +1011,477 @@ impl Debug for StorageConfig {
         f.debug_struct("StorageS3Config")
             .field("name", &self.name)
             .field("params", &self.params)
+            .field("prefix", &self.prefix)
+            .field("delete_concurrency", &self.delete_concurrency)
+            .field("heartbeat_interval_secs", &self.heartbeat_interval_secs)
+            .field("status_interval_secs", &self.status_interval_secs)
+            .field("compression", &self.compression)
+            .field("compression_level", &self.compression_level)
+            .field("max_wal_segment_age_secs", &self.max_wal_segment_age_secs)
+            .field("encryption", &self.encryption)
+            .field("retention", &self.retention)
+            .field("compaction", &self.compaction)
+            .field("proxy", &self.proxy)
+            .field("http", &self.http)
+            .field("max_retries", &self.max_retries)
+            .field("base_delay_ms", &self.base_delay_ms)
+            .field("max_delay_ms", &self.max_delay_ms)
+            .field("checksum", &self.checksum)
+            .field("verify_wal_checksum", &self.verify_wal_checksum)
+            .field("ca_cert_path", &self.ca_cert_path)
+            .field("allow_insecure", &self.allow_insecure)
+            .field(
+                "danger_accept_invalid_certs",
+                &self.danger_accept_invalid_certs,
+            )
+            .field("max_upload_bytes_per_sec", &self.max_upload_bytes_per_sec)
+            .field("verify_on_start", &self.verify_on_start)
+            .field("create_bucket", &self.create_bucket)
+            .finish()
+    }
+}
+
+impl StorageConfig {
+    // returns the prefix with `{hostname}` replaced by the host name.
+    pub fn remote_prefix(&self) -> Result<String> {
+        if !self.prefix.contains(HOSTNAME_PLACEHOLDER) {
+            return Ok(self.prefix.clone());
+        }
+
+        let hostname = hostname::get()?.to_string_lossy().to_string();
+        Ok(self.prefix.replace(HOSTNAME_PLACEHOLDER, &hostname))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self
+            .prefix
+            .split('/')
+            .any(|component| component == "." || component == "..")
+        {
+            return Err(Error::InvalidConfig(format!(
+                "prefix {} of replicate {} cannot contain `.` or `..`",
+                self.prefix, self.name
+            )));
+        }
+
+        if self.delete_concurrency == 0 {
+            return Err(Error::InvalidConfig("delete_concurrency cannot be zero"));
+        }
+
+        if self.compression_level > MAX_COMPRESSION_LEVEL {
+            return Err(Error::InvalidConfig(format!(
+                "compression_level {} of replicate {} MUST be in range 0..={}",
+                self.compression_level, self.name, MAX_COMPRESSION_LEVEL
+            )));
+        }
+
+        if let Some(encryption) = &self.encryption {
+            encryption.load_key()?;
+            encryption.load_previous_keys()?;
+        }
+
+        if self.retention.is_enabled() && self.retention.check_interval_secs == 0 {
+            return Err(Error::InvalidConfig(
+                "retention check_interval_secs cannot be zero",
+            ));
+        }
+
+        if self.compaction.is_enabled() && self.compaction.target_size == 0 {
+            return Err(Error::InvalidConfig(
+                "compaction target_size cannot be zero",
+            ));
+        }
+
+        if let Some(endpoint) = self.params.insecure_endpoint() {
+            if !self.allow_insecure {
+                return Err(Error::InvalidConfig(format!(
+                    "endpoint {} of replicate {} is not encrypted, set allow_insecure to use it",
+                    endpoint, self.name
+                )));
+            }
+        }
+
+        if self.danger_accept_invalid_certs && !self.allow_insecure {
+            return Err(Error::InvalidConfig(format!(
+                "danger_accept_invalid_certs of replicate {} requires allow_insecure",
+                self.name
+            )));
+        }
+
+        if self.base_delay_ms > self.max_delay_ms {
+            return Err(Error::InvalidConfig(format!(
+                "base_delay_ms {} of replicate {} cannot be greater than max_delay_ms {}",
+                self.base_delay_ms, self.name, self.max_delay_ms
+            )));
+        }
+
+        self.http.validate(&self.name)?;
+
+        Ok(())
+    }
+}
+
+/// Retention policy of generations, generations beyond `max_generations` or
+/// older than `max_age_secs` are removed. The newest generation and the one
+/// being replicated are always kept.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct RetentionConfig {
+    // max number of generations kept, zero for no limit.
+    #[serde(default)]
+    pub max_generations: usize,
+
+    // max age in seconds of generations since they were created, zero for no limit.
+    #[serde(default)]
+    pub max_age_secs: u64,
+
+    // seconds between retention checks in replicate.
+    #[serde(default = "default_retention_check_interval_secs")]
+    pub check_interval_secs: u64,
+}
+
+fn default_retention_check_interval_secs() -> u64 {
+    DEFAULT_RETENTION_CHECK_INTERVAL_SECS
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_generations: 0,
+            max_age_secs: 0,
+            check_interval_secs: default_retention_check_interval_secs(),
+        }
+    }
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_generations > 0 || self.max_age_secs > 0
+    }
+}
+
+/// Compaction of wal segments, contiguous wal segments of the same index are
+/// merged into one object of at most `target_size` bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct CompactionConfig {
+    // seconds between compactions in replicate, zero disables compaction.
+    #[serde(default)]
+    pub interval_secs: u64,
+
+    // max compressed size in bytes of a merged wal segment.
+    #[serde(default = "default_compaction_target_size")]
+    pub target_size: u64,
+}
+
+fn default_compaction_target_size() -> u64 {
+    DEFAULT_COMPACTION_TARGET_SIZE
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            target_size: default_compaction_target_size(),
+        }
+    }
+}
+
+impl CompactionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.interval_secs > 0
+    }
+}
+
+/// Proxy of http based storage backends, overrides the proxy envs if any of
+/// `http_proxy` and `https_proxy` is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct ProxyConfig {
+    // proxy url of http requests, e.g. "http://proxy.example.com:3128".
+    #[serde(default)]
+    pub http_proxy: String,
+
+    // proxy url of https requests.
+    #[serde(default)]
+    pub https_proxy: String,
+
+    // comma separated hosts, domains and ip ranges which bypass the proxy,
+    // e.g. "localhost,.internal,10.0.0.0/8", `NO_PROXY` env is used if empty.
+    #[serde(default)]
+    pub no_proxy: String,
+}
+
+impl ProxyConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.http_proxy.is_empty() || !self.https_proxy.is_empty()
+    }
+}
+
+/// Tuning of the http client of http based storage backends, the
+/// `_LITESYNC_INTERNAL_*` envs are used for the items not set.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct HttpConfig {
+    // timeout in seconds of connecting to the storage.
+    #[serde(default)]
+    pub connect_timeout_secs: Option<u64>,
+
+    // max idle connections kept per host, zero disables the pool.
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+
+    // interval in seconds of tcp keepalive probes.
+    #[serde(default)]
+    pub tcp_keepalive_secs: Option<u64>,
+
+    // allow http2 negotiated with the storage, http1 only by default.
+    #[serde(default)]
+    pub http2: bool,
+}
+
+impl HttpConfig {
+    fn validate(&self, name: &str) -> Result<()> {
+        if self.connect_timeout_secs == Some(0) {
+            return Err(Error::InvalidConfig(format!(
+                "http.connect_timeout_secs of replicate {} cannot be zero",
+                name
+            )));
+        }
+
+        if self.tcp_keepalive_secs == Some(0) {
+            return Err(Error::InvalidConfig(format!(
+                "http.tcp_keepalive_secs of replicate {} cannot be zero",
+                name
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Config for AES-256-GCM encryption, exactly one of `key` and `key_file` MUST be set.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+pub struct EncryptionConfig {
+    // hex encoded 256 bits key.
+    #[serde(default)]
+    pub key: String,
+
+    // path of the file holding the hex encoded 256 bits key.
+    #[serde(default)]
+    pub key_file: String,
+
+    // keys replaced by `key`, kept to read generations whose data key is
+    // still wrapped by them until `rekey`.
+    #[serde(default)]
+    pub previous_keys: Vec<EncryptionKeyConfig>,
+}
+
+/// A previous encryption key, exactly one of `key` and `key_file` MUST be set.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+pub struct EncryptionKeyConfig {
+    #[serde(default)]
+    pub key: String,
+
+    #[serde(default)]
+    pub key_file: String,
+}
+
+impl EncryptionConfig {
+    // returns the decoded key.
+    pub fn load_key(&self) -> Result<Vec<u8>> {
+        load_key(&self.key, &self.key_file)
+    }
+
+    // returns the decoded previous keys.
+    pub fn load_previous_keys(&self) -> Result<Vec<Vec<u8>>> {
+        self.previous_keys
+            .iter()
+            .map(|previous| load_key(&previous.key, &previous.key_file))
+            .collect()
+    }
+}
+
+// returns the decoded key of exactly one of `key` and `key_file`.
+fn load_key(key: &str, key_file: &str) -> Result<Vec<u8>> {
+    let key = match (key.is_empty(), key_file.is_empty()) {
+        (false, true) => key.to_string(),
+        (true, false) => match fs::read_to_string(key_file) {
+            Ok(key) => key,
+            Err(e) => {
+                return Err(Error::InvalidConfig(format!(
+                    "read encryption key file {} fail: {:?}",
+                    key_file, e
+                )));
+            }
+        },
+        _ => {
+            return Err(Error::InvalidConfig(
+                "encryption MUST set exactly one of key and key_file",
+            ));
+        }
+    };
+
+    let key = match hex::decode(key.trim()) {
+        Ok(key) => key,
+        Err(e) => {
+            return Err(Error::InvalidConfig(format!(
+                "encryption key is not valid hex: {:?}",
+                e
+            )));
+        }
+    };
+    if key.len() != ENCRYPTION_KEY_LEN {
+        return Err(Error::InvalidConfig(format!(
+            "encryption key MUST be {} bytes, but got {} bytes",
+            ENCRYPTION_KEY_LEN,
+            key.len()
+        )));
+    }
+
+    Ok(key)
+}
+
+impl Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("key", &mask_string(&self.key, 3))
+            .field("key_file", &self.key_file)
+            .field("previous_keys", &self.previous_keys)
+            .finish()
+    }
+}
+
+impl Debug for EncryptionKeyConfig {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("EncryptionKeyConfig")
+            .field("key", &mask_string(&self.key, 3))
+            .field("key_file", &self.key_file)
             .finish()
     }
 }
+
+// config of a replicate to the fs backend under root, for tests.
+#[cfg(test)]
+pub fn fs_storage_config(name: &str, root: &str) -> StorageConfig {
+    toml::from_str(&format!(
+        "name = \"{}\"\nparams.type = \"Fs\"\nparams.root = \"{}\"",
+        name, root
+    ))
+    .unwrap()
+}
+
+// config of db replicated to the fs backend under each of roots, the
+// replicates are named `fs0`, `fs1` and so on, for tests.
+#[cfg(test)]
+pub fn fs_db_config(db: &str, roots: &[&str]) -> DbConfig {
+    let mut toml_str = format!("db = \"{}\"", db);
+    for (index, root) in roots.iter().enumerate() {
+        toml_str.push_str(&format!(
+            "\n[[replicate]]\nname = \"fs{}\"\nparams.type = \"Fs\"\nparams.root = \"{}\"",
+            index, root
+        ));
+    }
+    toml::from_str(&toml_str).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::expand_db_glob;
+    use super::expand_env;
+    use super::glob_match;
+    use super::Config;
+    use crate::error::Error;
+    use crate::error::Result;
+
+    #[test]
+    fn test_expand_env() -> Result<()> {
+        env::set_var("REPLITED_TEST_EXPAND_ENV", "secret");
+        env::remove_var("REPLITED_TEST_EXPAND_ENV_UNSET");
+
+        assert_eq!(expand_env("plain $HOME")?, "plain $HOME");
+        assert_eq!(expand_env("${REPLITED_TEST_EXPAND_ENV}")?, "secret");
+        assert_eq!(
+            expand_env("a-${REPLITED_TEST_EXPAND_ENV}-${REPLITED_TEST_EXPAND_ENV_UNSET:-b}")?,
+            "a-secret-b"
+        );
+        assert_eq!(
+            expand_env("$${REPLITED_TEST_EXPAND_ENV}")?,
+            "${REPLITED_TEST_EXPAND_ENV}"
+        );
+        assert!(expand_env("${REPLITED_TEST_EXPAND_ENV_UNSET}").is_err());
+        assert!(expand_env("${REPLITED_TEST_EXPAND_ENV").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*.db", b"test.db"));
+        assert!(glob_match(b"t?st*.db", b"test-1.db"));
+        assert!(glob_match(b"*", b""));
+        assert!(!glob_match(b"*.db", b"test.db-wal"));
+        assert!(!glob_match(b"t?st.db", b"tst.db"));
+    }
+
+    #[test]
+    fn test_expand_db_glob() -> Result<()> {
+        let dir = tempdir()?;
+        for name in [
+            "a.db",
+            "b.db",
+            "a.db-wal",
+            "a.db-shm",
+            ".hidden.db",
+            "c.txt",
+        ] {
+            fs::write(dir.path().join(name), "")?;
+        }
+        fs::create_dir(dir.path().join("d.db"))?;
+
+        let glob = format!("{}/*.db", dir.path().to_str().unwrap());
+        let dbs = expand_db_glob(&glob)?;
+        let names: Vec<&str> = dbs.iter().map(|db| &db[db.len() - 4..]).collect();
+        assert_eq!(names, vec!["a.db", "b.db"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_config_from_stdin_or_env() -> Result<()> {
+        let dir = tempdir()?;
+        let config_file = dir.path().join("replited.toml");
+        fs::write(&config_file, "from = \"file\"")?;
+        let config_file = config_file.to_str().unwrap();
+        let stdin = "from = \"stdin\"".as_bytes();
+        let env_config = Some("from = \"env\"".to_string());
+
+        // stdin takes precedence over env and file.
+        let (toml_str, source) = Config::read_config_from("-", stdin, env_config.clone())?;
+        assert_eq!(
+            (toml_str.as_str(), source.as_str()),
+            ("from = \"stdin\"", "stdin")
+        );
+
+        // env takes precedence over file.
+        let (toml_str, source) = Config::read_config_from(config_file, stdin, env_config)?;
+        assert_eq!(
+            (toml_str.as_str(), source.as_str()),
+            ("from = \"env\"", "env REPLITED_CONFIG")
+        );
+
+        // an empty env falls back to the file.
+        let (toml_str, source) =
+            Config::read_config_from(config_file, stdin, Some(" \n".to_string()))?;
+        assert_eq!(
+            (toml_str.as_str(), source),
+            ("from = \"file\"", config_file.to_string())
+        );
+
+        let err = Config::read_config_from("/not/exist/replited.toml", stdin, None).unwrap_err();
+        assert_eq!(err.code(), Error::READ_CONFIG_FAIL);
+
+        Ok(())
+    }
+}
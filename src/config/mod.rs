@@ -5,14 +5,40 @@ mod storage_params;
 
 pub use arg::Arg;
 pub use arg::ArgCommand;
+pub use arg::CheckConfigOptions;
+pub use arg::DiffOptions;
+pub use arg::FollowOptions;
+pub use arg::ListOptions;
+pub use arg::OutputFormat;
+pub use arg::PruneOptions;
+pub use arg::RekeyOptions;
+pub use arg::ReplicateOptions;
 pub use arg::RestoreOptions;
+pub use arg::StatusOptions;
+pub use arg::SyncOptions;
+pub use arg::ThawOptions;
+pub use arg::VerifyOptions;
+#[cfg(test)]
+pub use config::fs_db_config;
+#[cfg(test)]
+pub use config::fs_storage_config;
 pub use config::Config;
 pub use config::DbConfig;
 pub use config::LogConfig;
+pub use config::LogFormat;
+pub use config::LogTarget;
+pub use config::MetricsConfig;
+pub use config::ProxyConfig;
+pub use config::RetentionConfig;
 pub use config::StorageConfig;
 pub use storage_params::StorageAzblobConfig;
+pub use storage_params::StorageB2Config;
+pub use storage_params::StorageCosConfig;
 pub use storage_params::StorageFsConfig;
 pub use storage_params::StorageFtpConfig;
 pub use storage_params::StorageGcsConfig;
+pub use storage_params::StorageMemoryConfig;
+pub use storage_params::StorageOssConfig;
 pub use storage_params::StorageParams;
 pub use storage_params::StorageS3Config;
+pub use storage_params::StorageSftpConfig;
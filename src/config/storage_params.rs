@@ -1,33 +1,145 @@
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::fs;
 
 use serde::Deserialize;
 use serde::Serialize;
 
 use crate::base::mask_string;
+use crate::error::Error;
+use crate::error::Result;
 
 /// Storage params which contains the detailed storage info.
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum StorageParams {
     Azb(Box<StorageAzblobConfig>),
+    B2(Box<StorageB2Config>),
+    Cos(Box<StorageCosConfig>),
     Fs(Box<StorageFsConfig>),
     Ftp(Box<StorageFtpConfig>),
     Gcs(Box<StorageGcsConfig>),
+    Memory(Box<StorageMemoryConfig>),
+    Oss(Box<StorageOssConfig>),
     S3(Box<StorageS3Config>),
+    Sftp(Box<StorageSftpConfig>),
 }
 
 impl StorageParams {
     pub fn root(&self) -> String {
         match self {
             StorageParams::Azb(s) => s.root.clone(),
+            StorageParams::B2(s) => s.root.clone(),
+            StorageParams::Cos(s) => s.root.clone(),
             StorageParams::Fs(s) => s.root.clone(),
             StorageParams::Ftp(s) => s.root.clone(),
             StorageParams::Gcs(s) => s.root.clone(),
+            StorageParams::Memory(s) => s.root.clone(),
+            StorageParams::Oss(s) => s.root.clone(),
             StorageParams::S3(s) => s.root.clone(),
+            StorageParams::Sftp(s) => s.root.clone(),
         }
     }
+
+    // endpoint without transport encryption, e.g. `http://` of http based
+    // backends and `ftp://` of ftp.
+    pub fn insecure_endpoint(&self) -> Option<&str> {
+        let (endpoint, insecure_scheme) = match self {
+            StorageParams::Azb(s) => (&s.endpoint, "http://"),
+            StorageParams::Cos(s) => (&s.endpoint, "http://"),
+            StorageParams::Ftp(s) => (&s.endpoint, "ftp://"),
+            StorageParams::Gcs(s) => (&s.endpoint, "http://"),
+            StorageParams::Oss(s) => (&s.endpoint, "http://"),
+            StorageParams::S3(s) => (&s.endpoint, "http://"),
+            StorageParams::B2(_)
+            | StorageParams::Fs(_)
+            | StorageParams::Memory(_)
+            | StorageParams::Sftp(_) => return None,
+        };
+
+        if endpoint.to_lowercase().starts_with(insecure_scheme) {
+            Some(endpoint)
+        } else {
+            None
+        }
+    }
+
+    // read credentials from their `*_file` variants, e.g. docker or
+    // kubernetes secrets mounted as files.
+    pub fn load_credential_files(&mut self) -> Result<()> {
+        match self {
+            StorageParams::Azb(s) => {
+                load_credential_file("account_key", &mut s.account_key, &s.account_key_file)?;
+            }
+            StorageParams::B2(s) => {
+                load_credential_file(
+                    "application_key_id",
+                    &mut s.application_key_id,
+                    &s.application_key_id_file,
+                )?;
+                load_credential_file(
+                    "application_key",
+                    &mut s.application_key,
+                    &s.application_key_file,
+                )?;
+            }
+            StorageParams::Cos(s) => {
+                load_credential_file("secret_id", &mut s.secret_id, &s.secret_id_file)?;
+                load_credential_file("secret_key", &mut s.secret_key, &s.secret_key_file)?;
+            }
+            StorageParams::Ftp(s) => {
+                load_credential_file("password", &mut s.password, &s.password_file)?;
+            }
+            StorageParams::Gcs(s) => {
+                load_credential_file("credential", &mut s.credential, &s.credential_file)?;
+            }
+            StorageParams::Oss(s) => {
+                load_credential_file("access_key_id", &mut s.access_key_id, &s.access_key_id_file)?;
+                load_credential_file(
+                    "access_key_secret",
+                    &mut s.access_key_secret,
+                    &s.access_key_secret_file,
+                )?;
+            }
+            StorageParams::S3(s) => {
+                load_credential_file("access_key_id", &mut s.access_key_id, &s.access_key_id_file)?;
+                load_credential_file(
+                    "secret_access_key",
+                    &mut s.secret_access_key,
+                    &s.secret_access_key_file,
+                )?;
+            }
+            StorageParams::Fs(_) | StorageParams::Memory(_) | StorageParams::Sftp(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+// set `value` to the content of `file` without the trailing newline, if `file`
+// is not empty.
+fn load_credential_file(name: &str, value: &mut String, file: &str) -> Result<()> {
+    if file.is_empty() {
+        return Ok(());
+    }
+    if !value.is_empty() {
+        return Err(Error::InvalidConfig(format!(
+            "MUST set at most one of {} and {}_file",
+            name, name
+        )));
+    }
+
+    match fs::read_to_string(file) {
+        Ok(content) => {
+            *value = content.trim_end_matches(['\r', '\n']).to_string();
+            Ok(())
+        }
+        Err(e) => Err(Error::InvalidConfig(format!(
+            "read {}_file {} fail: {:?}",
+            name, file, e
+        ))),
+    }
 }
 
 /// StorageParams will be displayed by `{protocol}://{key1=value1},{key2=value2}`
@@ -39,6 +151,16 @@ impl Display for StorageParams {
                 "azblob | container={},root={},endpoint={}",
                 v.container, v.root, v.endpoint
             ),
+            StorageParams::B2(v) => write!(
+                f,
+                "b2 | bucket={},bucket_id={},root={}",
+                v.bucket, v.bucket_id, v.root
+            ),
+            StorageParams::Cos(v) => write!(
+                f,
+                "cos | bucket={},root={},endpoint={}",
+                v.bucket, v.root, v.endpoint
+            ),
             StorageParams::Fs(v) => write!(f, "fs | root={}", v.root),
             StorageParams::Ftp(v) => {
                 write!(f, "ftp | root={},endpoint={}", v.root, v.endpoint)
@@ -48,6 +170,12 @@ impl Display for StorageParams {
                 "gcs | bucket={},root={},endpoint={}",
                 v.bucket, v.root, v.endpoint
             ),
+            StorageParams::Memory(v) => write!(f, "memory | root={}", v.root),
+            StorageParams::Oss(v) => write!(
+                f,
+                "oss | bucket={},root={},endpoint={}",
+                v.bucket, v.root, v.endpoint
+            ),
             StorageParams::S3(v) => {
                 write!(
                     f,
@@ -55,6 +183,9 @@ impl Display for StorageParams {
                     v.bucket, v.root, v.endpoint
                 )
             }
+            StorageParams::Sftp(v) => {
+                write!(f, "sftp | root={},endpoint={}", v.root, v.endpoint)
+            }
         }
     }
 }
@@ -65,7 +196,11 @@ pub struct StorageAzblobConfig {
     pub endpoint: String,
     pub container: String,
     pub account_name: String,
+    #[serde(default)]
     pub account_key: String,
+    // path of the file holding `account_key`.
+    #[serde(default)]
+    pub account_key_file: String,
     pub root: String,
 }
 
@@ -77,6 +212,73 @@ impl Debug for StorageAzblobConfig {
             .field("root", &self.root)
             .field("account_name", &self.account_name)
             .field("account_key", &mask_string(&self.account_key, 3))
+            .field("account_key_file", &self.account_key_file)
+            .finish()
+    }
+}
+
+/// Config for storage backend b2.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageB2Config {
+    pub bucket: String,
+    pub bucket_id: String,
+    #[serde(default)]
+    pub application_key_id: String,
+    #[serde(default)]
+    pub application_key: String,
+    // paths of the files holding `application_key_id` and `application_key`.
+    #[serde(default)]
+    pub application_key_id_file: String,
+    #[serde(default)]
+    pub application_key_file: String,
+    pub root: String,
+}
+
+impl Debug for StorageB2Config {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("StorageB2Config")
+            .field("bucket", &self.bucket)
+            .field("bucket_id", &self.bucket_id)
+            .field("root", &self.root)
+            .field(
+                "application_key_id",
+                &mask_string(&self.application_key_id, 3),
+            )
+            .field("application_key", &mask_string(&self.application_key, 3))
+            .field("application_key_id_file", &self.application_key_id_file)
+            .field("application_key_file", &self.application_key_file)
+            .finish()
+    }
+}
+
+/// Config for storage backend cos, the Tencent cloud object storage.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageCosConfig {
+    // endpoint of the region of bucket, e.g. "https://cos.ap-guangzhou.myqcloud.com".
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub secret_id: String,
+    #[serde(default)]
+    pub secret_key: String,
+    // paths of the files holding `secret_id` and `secret_key`.
+    #[serde(default)]
+    pub secret_id_file: String,
+    #[serde(default)]
+    pub secret_key_file: String,
+    pub root: String,
+}
+
+impl Debug for StorageCosConfig {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("StorageCosConfig")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("root", &self.root)
+            .field("secret_id", &mask_string(&self.secret_id, 3))
+            .field("secret_key", &mask_string(&self.secret_key, 3))
+            .field("secret_id_file", &self.secret_id_file)
+            .field("secret_key_file", &self.secret_key_file)
             .finish()
     }
 }
@@ -88,7 +290,11 @@ pub struct StorageFtpConfig {
     pub endpoint: String,
     pub root: String,
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    // path of the file holding `password`.
+    #[serde(default)]
+    pub password_file: String,
 }
 
 impl Default for StorageFtpConfig {
@@ -97,6 +303,7 @@ impl Default for StorageFtpConfig {
             endpoint: STORAGE_FTP_DEFAULT_ENDPOINT.to_string(),
             username: "".to_string(),
             password: "".to_string(),
+            password_file: "".to_string(),
             root: "/".to_string(),
         }
     }
@@ -109,6 +316,7 @@ impl Debug for StorageFtpConfig {
             .field("root", &self.root)
             .field("username", &self.username)
             .field("password", &mask_string(self.password.as_str(), 3))
+            .field("password_file", &self.password_file)
             .finish()
     }
 }
@@ -127,6 +335,26 @@ impl Default for StorageFsConfig {
     }
 }
 
+/// Config for storage backend memory, objects are lost when the process exits.
+/// Replicates with the same root share objects within the process.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageMemoryConfig {
+    #[serde(default = "default_memory_root")]
+    pub root: String,
+}
+
+fn default_memory_root() -> String {
+    "/".to_string()
+}
+
+impl Default for StorageMemoryConfig {
+    fn default() -> Self {
+        Self {
+            root: default_memory_root(),
+        }
+    }
+}
+
 /// Config for storage backend GCS.
 pub static STORAGE_GCS_DEFAULT_ENDPOINT: &str = "https://storage.googleapis.com";
 
@@ -135,7 +363,11 @@ pub struct StorageGcsConfig {
     pub endpoint: String,
     pub bucket: String,
     pub root: String,
+    #[serde(default)]
     pub credential: String,
+    // path of the file holding `credential`.
+    #[serde(default)]
+    pub credential_file: String,
 }
 
 impl Default for StorageGcsConfig {
@@ -145,6 +377,7 @@ impl Default for StorageGcsConfig {
             bucket: String::new(),
             root: String::new(),
             credential: String::new(),
+            credential_file: String::new(),
         }
     }
 }
@@ -156,6 +389,42 @@ impl Debug for StorageGcsConfig {
             .field("bucket", &self.bucket)
             .field("root", &self.root)
             .field("credential", &mask_string(&self.credential, 3))
+            .field("credential_file", &self.credential_file)
+            .finish()
+    }
+}
+
+/// Config for storage backend oss, the Aliyun object storage service.
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageOssConfig {
+    // endpoint of the region of bucket, e.g. "https://oss-cn-hangzhou.aliyuncs.com".
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub access_key_id: String,
+    #[serde(default)]
+    pub access_key_secret: String,
+    // paths of the files holding `access_key_id` and `access_key_secret`.
+    #[serde(default)]
+    pub access_key_id_file: String,
+    #[serde(default)]
+    pub access_key_secret_file: String,
+    pub root: String,
+}
+
+impl Debug for StorageOssConfig {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("StorageOssConfig")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("root", &self.root)
+            .field("access_key_id", &mask_string(&self.access_key_id, 3))
+            .field(
+                "access_key_secret",
+                &mask_string(&self.access_key_secret, 3),
+            )
+            .field("access_key_id_file", &self.access_key_id_file)
+            .field("access_key_secret_file", &self.access_key_secret_file)
             .finish()
     }
 }
@@ -168,10 +437,42 @@ pub struct StorageS3Config {
     pub endpoint: String,
     pub region: String,
     pub bucket: String,
+    // not required with `use_credential_chain`.
+    #[serde(default)]
     pub access_key_id: String,
+    #[serde(default)]
     pub secret_access_key: String,
+    // paths of the files holding `access_key_id` and `secret_access_key`.
+    #[serde(default)]
+    pub access_key_id_file: String,
+    #[serde(default)]
+    pub secret_access_key_file: String,
+    // load credentials from the aws credential chain, e.g. env, profiles,
+    // web identity token, ecs task role and ec2 instance metadata, when
+    // `access_key_id` and `secret_access_key` are not set.
+    #[serde(default)]
+    pub use_credential_chain: bool,
+    // session token of temporary credentials.
+    #[serde(default)]
+    pub security_token: String,
+    // assume the role by sts if not empty, e.g. for cross-account buckets.
+    #[serde(default)]
+    pub role_arn: String,
+    // external id used to assume the role.
+    #[serde(default)]
+    pub external_id: String,
 
     pub root: String,
+
+    // server side encryption of written objects, e.g. "AES256" or "aws:kms".
+    #[serde(default)]
+    pub server_side_encryption: String,
+    // kms key id, only used when `server_side_encryption` is "aws:kms".
+    #[serde(default)]
+    pub server_side_encryption_aws_kms_key_id: String,
+    // storage class of written objects, e.g. "STANDARD_IA" or "GLACIER".
+    #[serde(default)]
+    pub storage_class: String,
 }
 
 impl Default for StorageS3Config {
@@ -182,7 +483,16 @@ impl Default for StorageS3Config {
             bucket: "".to_string(),
             access_key_id: "".to_string(),
             secret_access_key: "".to_string(),
+            access_key_id_file: "".to_string(),
+            secret_access_key_file: "".to_string(),
+            use_credential_chain: false,
+            security_token: "".to_string(),
+            role_arn: "".to_string(),
+            external_id: "".to_string(),
             root: "".to_string(),
+            server_side_encryption: "".to_string(),
+            server_side_encryption_aws_kms_key_id: "".to_string(),
+            storage_class: "".to_string(),
         }
     }
 }
@@ -199,6 +509,143 @@ impl Debug for StorageS3Config {
                 "secret_access_key",
                 &mask_string(&self.secret_access_key, 3),
             )
+            .field("access_key_id_file", &self.access_key_id_file)
+            .field("secret_access_key_file", &self.secret_access_key_file)
+            .field("use_credential_chain", &self.use_credential_chain)
+            .field("security_token", &mask_string(&self.security_token, 3))
+            .field("role_arn", &self.role_arn)
+            .field("external_id", &mask_string(&self.external_id, 3))
+            .field("server_side_encryption", &self.server_side_encryption)
+            .field(
+                "server_side_encryption_aws_kms_key_id",
+                &mask_string(&self.server_side_encryption_aws_kms_key_id, 3),
+            )
+            .field("storage_class", &self.storage_class)
             .finish()
     }
 }
+
+/// Config for storage backend sftp.
+pub const STORAGE_SFTP_DEFAULT_KNOWN_HOSTS_STRATEGY: &str = "Strict";
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageSftpConfig {
+    pub endpoint: String,
+    pub root: String,
+    pub user: String,
+    // path of the private key used to authenticate, the ssh agent and
+    // ssh config are used when empty.
+    pub key: String,
+    // one of "Strict", "Accept" and "Add".
+    pub known_hosts_strategy: String,
+}
+
+impl Default for StorageSftpConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: "".to_string(),
+            root: "/".to_string(),
+            user: "".to_string(),
+            key: "".to_string(),
+            known_hosts_strategy: STORAGE_SFTP_DEFAULT_KNOWN_HOSTS_STRATEGY.to_string(),
+        }
+    }
+}
+
+impl Debug for StorageSftpConfig {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("StorageSftpConfig")
+            .field("endpoint", &self.endpoint)
+            .field("root", &self.root)
+            .field("user", &self.user)
+            .field("key", &mask_string(&self.key, 3))
+            .field("known_hosts_strategy", &self.known_hosts_strategy)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::load_credential_file;
+    use super::StorageParams;
+    use crate::config::StorageConfig;
+    use crate::error::Error;
+    use crate::error::Result;
+
+    #[test]
+    fn test_load_credential_file() -> Result<()> {
+        let dir = tempdir()?;
+        let file = dir.path().join("secret");
+        fs::write(&file, "secret\r\n\n")?;
+        let file = file.to_str().unwrap();
+
+        // no file keeps the value.
+        let mut value = "value".to_string();
+        load_credential_file("password", &mut value, "")?;
+        assert_eq!(value, "value");
+
+        // trailing newlines are trimmed.
+        let mut value = String::new();
+        load_credential_file("password", &mut value, file)?;
+        assert_eq!(value, "secret");
+
+        // value and file are both set.
+        let mut value = "value".to_string();
+        let err = load_credential_file("password", &mut value, file).unwrap_err();
+        assert_eq!(err.code(), Error::INVALID_CONFIG);
+
+        // missing file.
+        let mut value = String::new();
+        let missing = dir.path().join("missing");
+        let err =
+            load_credential_file("password", &mut value, missing.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.code(), Error::INVALID_CONFIG);
+        assert!(value.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_credential_files() -> Result<()> {
+        let dir = tempdir()?;
+        let (id_file, secret_file) = (dir.path().join("id"), dir.path().join("secret"));
+        fs::write(&id_file, "access-key-id\n")?;
+        fs::write(&secret_file, "access-key-secret\n")?;
+
+        // only the files are set.
+        let mut config: StorageConfig = toml::from_str(&format!(
+            "name = \"oss\"\nparams.type = \"Oss\"\nparams.endpoint = \"https://oss-cn-hangzhou.aliyuncs.com\"\nparams.bucket = \"bucket\"\nparams.root = \"/replited\"\nparams.access_key_id_file = \"{}\"\nparams.access_key_secret_file = \"{}\"",
+            id_file.to_str().unwrap(),
+            secret_file.to_str().unwrap()
+        ))
+        .unwrap();
+        config.params.load_credential_files()?;
+        let oss = match &config.params {
+            StorageParams::Oss(oss) => oss,
+            _ => unreachable!(),
+        };
+        assert_eq!(oss.access_key_id, "access-key-id");
+        assert_eq!(oss.access_key_secret, "access-key-secret");
+
+        // masked debug output hides the loaded values.
+        let debug = format!("{:?}", config.params);
+        assert!(!debug.contains("access-key-id"));
+        assert!(!debug.contains("access-key-secret"));
+
+        // both the value and the file are set.
+        let mut config: StorageConfig = toml::from_str(&format!(
+            "name = \"ftp\"\nparams.type = \"Ftp\"\nparams.endpoint = \"ftps://127.0.0.1\"\nparams.root = \"/\"\nparams.username = \"user\"\nparams.password = \"password\"\nparams.password_file = \"{}\"",
+            secret_file.to_str().unwrap()
+        ))
+        .unwrap();
+        let err = config.params.load_credential_files().unwrap_err();
+        assert_eq!(err.code(), Error::INVALID_CONFIG);
+
+        Ok(())
+    }
+}
@@ -1,32 +1,49 @@
+use std::cmp::min;
+use std::collections::VecDeque;
 use std::fs;
 use std::fs::File;
 use std::fs::OpenOptions;
+use std::future::pending;
+use std::io;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
-use std::os::unix::fs::MetadataExt;
-use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::SystemTime;
 
 use log::debug;
 use log::error;
 use log::info;
+use log::warn;
 use rusqlite::Connection;
 use rusqlite::DropBehavior;
+use rusqlite::ErrorCode;
 use tempfile::tempfile;
 use tempfile::NamedTempFile;
 use tokio::select;
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::SendError;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio::sync::watch;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio::task::spawn_blocking;
 use tokio::task::JoinHandle;
 use tokio::time::sleep;
+use tokio::time::Instant;
 
+use super::process_lock::ProcessLock;
+use super::wal_watcher::wal_changed;
+use super::wal_watcher::WalWatcher;
 use crate::base::compress_file;
+use crate::base::copy_permissions;
 use crate::base::generation_dir;
 use crate::base::generation_file_path;
 use crate::base::local_generations_dir;
@@ -35,18 +52,23 @@ use crate::base::parse_wal_path;
 use crate::base::path_base;
 use crate::base::shadow_wal_dir;
 use crate::base::shadow_wal_file;
+use crate::base::Compression;
 use crate::base::Generation;
 use crate::config::DbConfig;
 use crate::error::Error;
 use crate::error::Result;
+use crate::metrics::register_db;
+use crate::metrics::DbStats;
 use crate::sqlite::align_frame;
 use crate::sqlite::checksum;
 use crate::sqlite::read_last_checksum;
 use crate::sqlite::CheckpointMode;
+use crate::sqlite::CheckpointResult;
 use crate::sqlite::WALFrame;
 use crate::sqlite::WALHeader;
 use crate::sqlite::WAL_FRAME_HEADER_SIZE;
 use crate::sqlite::WAL_HEADER_SIZE;
+use crate::storage::StorageClient;
 use crate::sync::Replicate;
 use crate::sync::ReplicateCommand;
 
@@ -56,9 +78,6 @@ const GENERATION_LEN: usize = 32;
 // If this index is reached then a new generation will be started.
 const MAX_WAL_INDEX: u64 = 0x7FFFFFFF;
 
-// Default DB settings.
-const DEFAULT_MONITOR_INTERVAL: Duration = Duration::from_secs(1);
-
 #[derive(Clone, Debug)]
 pub enum DbCommand {
     Snapshot(usize),
@@ -75,6 +94,9 @@ pub struct DatabaseInfo {
 pub struct Database {
     config: DbConfig,
 
+    // canonical path of db file, with symlinks resolved.
+    db_path: String,
+
     // Path to the database metadata.
     meta_dir: String,
 
@@ -82,19 +104,74 @@ pub struct Database {
     wal_file: String,
     page_size: u64,
 
+    // page count of db in the last sync, used to detect shrunk db.
+    last_page_count: Option<u64>,
+
+    // wal file state seen by the last full sync and when it ran, syncs are
+    // skipped while the wal file is unchanged.
+    last_wal_stat: Option<WalStat>,
+    last_full_sync: Instant,
+
     connection: Connection,
 
     // database connection for transaction, None if there if no tranction
     tx_connection: Option<Connection>,
 
+    stats: Arc<DbStats>,
+
     // for sync
     sync_notifiers: Vec<Sender<ReplicateCommand>>,
+    // replicates whose last db changed notification was dropped as their
+    // channel was full, they are notified again in the next sync.
+    notify_pending: Vec<bool>,
     sync_handle: Vec<JoinHandle<()>>,
     syncs: Vec<Replicate>,
+
+    // compressed snapshots of the current position, shared by replicates
+    // with the same compression config until the position changes.
+    snapshots: Vec<SharedSnapshot>,
+
+    // held until db is dropped, so only one process replicates the db.
+    _process_lock: ProcessLock,
+}
+
+// cheap fingerprint of the wal file. The header is included because a wal
+// restarted from the beginning may keep the same size, and mtime may not
+// change within the timestamp granularity of the file system.
+#[derive(Debug, PartialEq)]
+struct WalStat {
+    mod_time: SystemTime,
+    size: u64,
+    header: WALHeader,
+}
+
+impl WalStat {
+    fn read(wal_file: &str) -> Result<Self> {
+        let metadata = fs::metadata(wal_file)?;
+        Ok(Self {
+            mod_time: metadata.modified()?,
+            size: metadata.len(),
+            header: WALHeader::read(wal_file)?,
+        })
+    }
+}
+
+// result of the blocking part of a db command, sent to the replicate.
+enum PreparedCommand {
+    Snapshot(usize, File, WalGenerationPos),
+    // a copy of the db, compressed in background before sending.
+    SnapshotCopy(usize, NamedTempFile, WalGenerationPos),
+}
+
+struct SharedSnapshot {
+    pos: WalGenerationPos,
+    compression: Compression,
+    compression_level: u32,
+    file: NamedTempFile,
 }
 
 // position info of wal for a generation
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct WalGenerationPos {
     // generation name
     pub generation: Generation,
@@ -125,72 +202,65 @@ struct SyncInfo {
 }
 
 impl Database {
-    fn init_params(db: &str, connection: &Connection) -> Result<()> {
-        let max_try_num = 10;
-        // busy timeout
-        connection.busy_timeout(Duration::from_secs(1))?;
-
-        let mut try_num = 0;
-        while try_num < max_try_num {
-            try_num += 1;
-            // PRAGMA journal_mode = wal;
-            if let Err(e) =
-                connection.pragma_update_and_check(None, "journal_mode", "WAL", |_param| {
-                    // println!("journal_mode param: {:?}\n", param);
-                    Ok(())
-                })
-            {
-                error!("set journal_mode=wal error: {:?}", e);
-                continue;
+    // distinguishes a db locked by another connection, which is retried later,
+    // from a file which is not a database.
+    fn sqlite_error(db: &str, action: &str, e: rusqlite::Error) -> Error {
+        match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked) => {
+                Error::DbLockedError(format!(
+                    "{} for db {} failed, another connection holds a lock: {}",
+                    action, db, e
+                ))
             }
-            try_num = 0;
-            break;
-        }
-        if try_num >= max_try_num {
-            error!("try set journal_mode=wal failed");
-            return Err(Error::SqliteError(format!(
-                "set journal_mode=wal for db {} failed",
-                db,
-            )));
+            Some(ErrorCode::NotADatabase) => Error::SqliteInvalidDbHeaderError(format!(
+                "{} for db {} failed, file is not a database: {}",
+                action, db, e
+            )),
+            _ => Error::SqliteError(format!("{} for db {} failed: {}", action, db, e)),
         }
+    }
 
-        let mut try_num = 0;
-        while try_num < max_try_num {
-            try_num += 1;
-            // PRAGMA wal_autocheckpoint = 0;
-            if let Err(e) =
-                connection.pragma_update_and_check(None, "wal_autocheckpoint", "0", |_param| {
-                    // println!("wal_autocheckpoint param: {:?}\n", param);
-                    Ok(())
-                })
-            {
-                error!("set wal_autocheckpoint=0 error: {:?}", e);
-                continue;
-            }
-            try_num = 0;
-            break;
-        }
-        if try_num >= max_try_num {
-            error!("try set wal_autocheckpoint=0 failed");
-            return Err(Error::SqliteError(format!(
-                "set wal_autocheckpoint=0 for db {} failed",
-                db,
+    fn init_params(db: &str, connection: &Connection) -> Result<()> {
+        // busy timeout
+        connection
+            .busy_timeout(Duration::from_secs(1))
+            .map_err(|e| Database::sqlite_error(db, "set busy_timeout", e))?;
+
+        // PRAGMA journal_mode = wal;
+        let journal_mode: String = connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get(0))
+            .map_err(|e| Database::sqlite_error(db, "set journal_mode=wal", e))?;
+        if !journal_mode.eq_ignore_ascii_case("wal") {
+            // sqlite keeps the old mode instead of failing if it cannot
+            // switch, e.g. another connection holds a lock.
+            return Err(Error::DbLockedError(format!(
+                "set journal_mode=wal for db {} failed, journal_mode is still {}",
+                db, journal_mode
             )));
         }
 
+        // PRAGMA wal_autocheckpoint = 0;
+        connection
+            .pragma_update_and_check(None, "wal_autocheckpoint", "0", |_row| Ok(()))
+            .map_err(|e| Database::sqlite_error(db, "set wal_autocheckpoint=0", e))?;
+
         Ok(())
     }
 
-    fn create_internal_tables(connection: &Connection) -> Result<()> {
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS _replited_seq (id INTEGER PRIMARY KEY, seq INTEGER);",
-            (),
-        )?;
-
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS _replited_lock (id INTEGER);",
-            (),
-        )?;
+    fn create_internal_tables(db: &str, connection: &Connection) -> Result<()> {
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS _replited_seq (id INTEGER PRIMARY KEY, seq INTEGER);",
+                (),
+            )
+            .map_err(|e| Database::sqlite_error(db, "create _replited_seq", e))?;
+
+        connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS _replited_lock (id INTEGER);",
+                (),
+            )
+            .map_err(|e| Database::sqlite_error(db, "create _replited_lock", e))?;
         Ok(())
     }
 
@@ -217,11 +287,47 @@ impl Database {
     }
 
     // init replited directory
-    fn init_directory(config: &DbConfig) -> Result<String> {
-        let file_path = PathBuf::from(&config.db);
+    // resolve symlinks in db path, sqlite creates the wal file next to the
+    // real db file, so derived paths MUST base on the canonical path.
+    // canonical path of db, the db file may not be created yet.
+    fn canonical_db_path(db: &str) -> Result<String> {
+        let db_path = Path::new(db);
+        let path = if db_path.exists() {
+            fs::canonicalize(db_path)?
+        } else {
+            let file_name = match db_path.file_name() {
+                Some(file_name) => file_name,
+                None => {
+                    return Err(Error::InvalidPath(format!(
+                        "db path {} has no file name",
+                        db
+                    )));
+                }
+            };
+            let dir_path = match db_path.parent() {
+                Some(dir_path) if !dir_path.as_os_str().is_empty() => dir_path,
+                _ => Path::new("."),
+            };
+            fs::canonicalize(dir_path)?.join(file_name)
+        };
+        match path.to_str() {
+            Some(path) => Ok(path.to_string()),
+            None => Err(Error::InvalidPath(format!(
+                "db path {} is not valid unicode",
+                db
+            ))),
+        }
+    }
+
+    fn meta_dir(db_path: &str) -> String {
+        let file_path = PathBuf::from(db_path);
         let db_name = file_path.file_name().unwrap().to_str().unwrap();
         let dir_path = file_path.parent().unwrap_or_else(|| Path::new("."));
-        let meta_dir = format!("{}/.{}-replited/", dir_path.to_str().unwrap(), db_name,);
+        format!("{}/.{}-replited/", dir_path.to_str().unwrap(), db_name,)
+    }
+
+    fn init_directory(db_path: &str) -> Result<String> {
+        let meta_dir = Database::meta_dir(db_path);
         fs::create_dir_all(&meta_dir)?;
 
         Ok(meta_dir)
@@ -229,20 +335,23 @@ impl Database {
 
     fn try_create(config: DbConfig) -> Result<(Self, Receiver<DbCommand>)> {
         info!("start database with config: {:?}\n", config);
+        let db_path = Database::canonical_db_path(&config.db)?;
+        let wal_file = format!("{}-wal", db_path);
+
+        // init path, the db is not touched until the process lock is held.
+        let meta_dir = Database::init_directory(&db_path)?;
+        let process_lock = ProcessLock::acquire(&config.db, &meta_dir)?;
+
         let connection = Connection::open(&config.db)?;
 
         Database::init_params(&config.db, &connection)?;
 
-        Database::create_internal_tables(&connection)?;
+        Database::create_internal_tables(&config.db, &connection)?;
 
         let page_size = connection.pragma_query_value(None, "page_size", |row| row.get(0))?;
-        let wal_file = format!("{}-wal", config.db);
-
-        // init path
-        let meta_dir = Database::init_directory(&config)?;
 
         // init replicate
-        let (db_notifier, db_receiver) = mpsc::channel(16);
+        let (db_notifier, db_receiver) = mpsc::channel(config.channel_capacity);
         let mut sync_handle = Vec::with_capacity(config.replicate.len());
         let mut sync_notifiers = Vec::with_capacity(config.replicate.len());
         let mut syncs = Vec::with_capacity(config.replicate.len());
@@ -257,7 +366,7 @@ impl Database {
             .unwrap()
             .to_string();
         for (index, replicate) in config.replicate.iter().enumerate() {
-            let (sync_notifier, sync_receiver) = mpsc::channel(16);
+            let (sync_notifier, sync_receiver) = mpsc::channel(config.channel_capacity);
             let s = Replicate::new(
                 replicate.clone(),
                 db.clone(),
@@ -273,14 +382,22 @@ impl Database {
 
         let mut db = Self {
             config: config.clone(),
+            db_path,
             connection,
             meta_dir,
             wal_file,
             page_size,
+            last_page_count: None,
+            last_wal_stat: None,
+            last_full_sync: Instant::now(),
             tx_connection: None,
+            stats: register_db(&db),
+            notify_pending: vec![false; sync_notifiers.len()],
             sync_notifiers,
             sync_handle,
             syncs,
+            snapshots: Vec::new(),
+            _process_lock: process_lock,
         };
 
         db.acquire_read_lock()?;
@@ -386,17 +503,147 @@ impl Database {
         None
     }
 
-    // copy pending data from wal to shadow wal
-    async fn sync(&mut self) -> Result<()> {
+    // A db shrunk by VACUUM rewrites most pages of the db, start a new
+    // generation so that a fresh snapshot of the shrunk db is taken right
+    // away, instead of keeping a snapshot of the larger db file followed by wal
+    // segments rewriting the whole db. Small shrinks, e.g. of auto_vacuum,
+    // below `shrink_percent` of the pages keep the generation.
+    fn verify_not_shrunk(&mut self, info: &mut SyncInfo) -> Result<()> {
+        let page_count: u64 = self
+            .connection
+            .pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let last_page_count = self.last_page_count.replace(page_count);
+
+        let shrink_percent = self.config.shrink_percent;
+        if info.reason.is_some() || shrink_percent == 0 {
+            return Ok(());
+        }
+        if let Some(last_page_count) = last_page_count {
+            if page_count < last_page_count
+                && (last_page_count - page_count) * 100 >= last_page_count * shrink_percent
+            {
+                info.reason = Some(format!(
+                    "db shrunk from {} to {} pages",
+                    last_page_count, page_count
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // VACUUM the db before a new generation starts, so the snapshot of the
+    // generation carries no free pages. The pages rewritten by VACUUM are
+    // written to the WAL and copied into the new generation, they are
+    // consistent with the snapshot taken after them. `VACUUM INTO` a copy is
+    // not used: its page layout differs from the db the following wal
+    // segments apply to. Failing to vacuum, e.g. the db is busy, only logs.
+    fn vacuum(&mut self) {
+        let start = Instant::now();
+        if let Err(e) = self.connection.execute_batch("VACUUM;") {
+            warn!("db {} vacuum before snapshot failed: {}", self.config.db, e);
+            return;
+        }
+
+        // the shrunk db MUST not start another generation in the next sync.
+        match self
+            .connection
+            .pragma_query_value(None, "page_count", |row| row.get(0))
+        {
+            Ok(page_count) => self.last_page_count = Some(page_count),
+            Err(e) => warn!("db {} read page_count failed: {}", self.config.db, e),
+        }
+        info!(
+            "db {} vacuum before snapshot took {:?}",
+            self.config.db,
+            start.elapsed()
+        );
+    }
+
+    // returns if the wal file is unchanged since the last full sync and
+    // nothing else needs a full sync: pending notifications, or a checkpoint
+    // by `checkpoint_interval_secs`.
+    fn can_skip_sync(&self) -> bool {
+        if self.notify_pending.contains(&true) {
+            return false;
+        }
+        if self.config.checkpoint_interval_secs > 0
+            && self.last_full_sync.elapsed().as_secs() >= self.config.checkpoint_interval_secs
+        {
+            return false;
+        }
+
+        match (&self.last_wal_stat, WalStat::read(&self.wal_file)) {
+            (Some(last), Ok(stat)) => *last == stat,
+            _ => false,
+        }
+    }
+
+    // runs blocking `f` on the blocking pool with the database moved into it,
+    // so the task owning the database is not blocked by file and sqlite io.
+    // The database is returned with the result of `f`.
+    fn spawn_blocking<T, F>(self, f: F) -> JoinHandle<(Self, T)>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Self) -> T + Send + 'static,
+    {
+        spawn_blocking(move || {
+            let mut database = self;
+            let ret = f(&mut database);
+            (database, ret)
+        })
+    }
+
+    // the database is lost if `f` panicked.
+    async fn blocking<T, F>(self, f: F) -> Result<(Self, T)>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Self) -> T + Send + 'static,
+    {
+        self.spawn_blocking(f)
+            .await
+            .map_err(|e| Error::SpawnDatabaseTaskError(format!("db blocking task error: {:?}", e)))
+    }
+
+    // copy pending data from wal to shadow wal, returns if the shadow wal has
+    // been changed. It blocks on file and sqlite io, run it with `blocking`.
+    fn sync(&mut self) -> Result<bool> {
+        if self.can_skip_sync() {
+            return Ok(false);
+        }
         debug!("sync database: {}", self.config.db);
 
+        let changed = self.sync_wal_to_shadow()?;
+
+        // notify the database has been changed
+        if changed {
+            // snapshots of the old position will never be reused.
+            self.snapshots.clear();
+        }
+        if changed || self.notify_pending.contains(&true) {
+            self.notify_db_changed()?;
+        }
+
+        debug!("sync db {} ok", self.config.db);
+        Ok(changed)
+    }
+
+    // the blocking part of sync, returns if the shadow wal has been changed.
+    fn sync_wal_to_shadow(&mut self) -> Result<bool> {
         // make sure wal file has at least one frame in it
         self.ensure_wal_exists()?;
 
+        // stat before reading the wal, so writes during the sync are seen by
+        // the next one. Reset on error, the next sync must not be skipped.
+        self.last_wal_stat = None;
+        let wal_stat = WalStat::read(&self.wal_file)?;
+        self.last_full_sync = Instant::now();
+
         // Verify our last sync matches the current state of the WAL.
         // This ensures that we have an existing generation & that the last sync
         // position of the real WAL hasn't been overwritten by another process.
         let mut info = self.verify()?;
+        self.verify_not_shrunk(&mut info)?;
         debug!("db {} sync info: {:?}", self.config.db, info);
 
         // Track if anything in the shadow WAL changes and then notify at the end.
@@ -404,8 +651,12 @@ impl Database {
             info.wal_size != info.shadow_wal_size || info.restart || info.reason.is_some();
 
         if let Some(reason) = &info.reason {
+            if self.config.vacuum_before_snapshot {
+                self.vacuum();
+            }
+
             // Start new generation & notify user via log message.
-            info.generation = self.create_generation().await?;
+            info.generation = self.create_generation()?;
             info!(
                 "db {} sync new generation: {}, reason: {}",
                 self.config.db,
@@ -438,18 +689,181 @@ impl Database {
         // Clean up any old files.
         self.clean()?;
 
-        // notify the database has been changed
-        if changed {
-            let generation_pos = self.wal_generation_position()?;
-            self.sync_notifiers[0]
-                .send(ReplicateCommand::DbChanged(generation_pos))
-                .await?;
+        self.last_wal_stat = Some(wal_stat);
+        Ok(changed)
+    }
+
+    // notify all replicates the current wal position without waiting, so a
+    // stalled replicate cannot block the monitor loop. A replicate with a full
+    // channel still has commands to sync the shadow wal, it is notified again
+    // in the next sync in case the position has moved to a new generation.
+    fn notify_db_changed(&mut self) -> Result<()> {
+        let generation_pos = self.wal_generation_position()?;
+        for (index, notifier) in self.sync_notifiers.iter().enumerate() {
+            match notifier.try_send(ReplicateCommand::DbChanged(generation_pos.clone())) {
+                Ok(()) => self.notify_pending[index] = false,
+                Err(TrySendError::Full(_)) => {
+                    debug!(
+                        "db {} replicate {} channel is full, notify later",
+                        self.config.db, index
+                    );
+                    self.notify_pending[index] = true;
+                }
+                Err(TrySendError::Closed(cmd)) => return Err(SendError(cmd).into()),
+            }
         }
+        Ok(())
+    }
 
-        debug!("sync db {} ok", self.config.db);
+    // waits until the pending notifications are queued, used on shutdown so
+    // the replicates sync the last position before exiting.
+    async fn flush_pending_notify(&mut self) -> Result<()> {
+        if !self.notify_pending.contains(&true) {
+            return Ok(());
+        }
+
+        let generation_pos = self.wal_generation_position()?;
+        for (index, notifier) in self.sync_notifiers.iter().enumerate() {
+            if self.notify_pending[index] {
+                notifier
+                    .send(ReplicateCommand::DbChanged(generation_pos.clone()))
+                    .await?;
+                self.notify_pending[index] = false;
+            }
+        }
         Ok(())
     }
 
+    // sync the last changes, optionally truncate the wal, then wait until
+    // replicates have handled all pending commands.
+    async fn shutdown(self) {
+        info!("db {} shutting down", self.config.db);
+        let db = self.config.db.clone();
+        let mut database = match self.blocking(|database| database.sync_on_shutdown()).await {
+            Ok((database, ())) => database,
+            Err(e) => {
+                error!("db {} shutdown error: {:?}", db, e);
+                return;
+            }
+        };
+
+        if let Err(e) = database.flush_pending_notify().await {
+            error!("notify db {} changed error: {:?}", db, e);
+        }
+
+        // close notifiers, replicates exit after handling the queued commands.
+        database.sync_notifiers.clear();
+        for handle in database.sync_handle.drain(..) {
+            if let Err(e) = handle.await {
+                error!("db {} wait replicate exit error: {:?}", db, e);
+            }
+        }
+        info!("db {} shutdown", db);
+    }
+
+    // the blocking part of shutdown.
+    fn sync_on_shutdown(&mut self) {
+        if let Err(e) = self.sync() {
+            error!("sync db {} on shutdown error: {:?}", self.config.db, e);
+        }
+
+        if self.config.truncate_on_shutdown {
+            match self.checkpoint(CheckpointMode::Truncate) {
+                Ok(result) => {
+                    if !result.is_complete() {
+                        error!(
+                            "db {} truncate checkpoint on shutdown not complete: {:?}",
+                            self.config.db, result
+                        );
+                    }
+                    // frames copied into shadow wal by checkpoint need to be synced.
+                    if let Err(e) = self.notify_db_changed() {
+                        error!("notify db {} changed error: {:?}", self.config.db, e);
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "db {} truncate checkpoint on shutdown error: {:?}",
+                        self.config.db, e
+                    );
+                }
+            }
+        }
+    }
+
+    // notify all replicates the current position, then returns receivers of
+    // their replies once they have handled it.
+    async fn flush_replicates(&mut self) -> Result<Vec<oneshot::Receiver<Result<()>>>> {
+        let generation_pos = self.wal_generation_position()?;
+        let mut replies = Vec::with_capacity(self.sync_notifiers.len());
+        for notifier in &self.sync_notifiers {
+            notifier
+                .send(ReplicateCommand::DbChanged(generation_pos.clone()))
+                .await?;
+            let (reply, receiver) = oneshot::channel();
+            notifier.send(ReplicateCommand::Flush(reply)).await?;
+            replies.push(receiver);
+        }
+        self.notify_pending.fill(false);
+        Ok(replies)
+    }
+
+    // one round of one-shot sync, returns the last replicate error and if any
+    // db command has been handled, which means another round is needed. The
+    // database is returned unless a blocking task panicked.
+    async fn sync_round(
+        self,
+        db_receiver: &mut Receiver<DbCommand>,
+    ) -> Result<(Self, Result<(Result<()>, bool)>)> {
+        let (mut database, result) = self
+            .blocking(|database| database.sync_wal_to_shadow())
+            .await?;
+        if let Err(e) = result {
+            return Ok((database, Err(e)));
+        }
+        let replies = match database.flush_replicates().await {
+            Ok(replies) => replies,
+            Err(e) => return Ok((database, Err(e))),
+        };
+
+        let mut ret = Ok(());
+        let mut handled = false;
+        for mut reply in replies {
+            // replicates may request a snapshot before replying.
+            let result = loop {
+                select! {
+                    Some(cmd) = db_receiver.recv() => {
+                        let (db, result) = database.handle_db_command(cmd).await?;
+                        database = db;
+                        if let Err(e) = result {
+                            return Ok((database, Err(e)));
+                        }
+                        handled = true;
+                    }
+                    result = &mut reply => break result,
+                }
+            };
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    error!("db {} replicate error: {:?}", database.config.db, e);
+                    ret = Err(e);
+                }
+                Err(e) => return Ok((database, Err(e.into()))),
+            }
+        }
+
+        while let Ok(cmd) = db_receiver.try_recv() {
+            let (db, result) = database.handle_db_command(cmd).await?;
+            database = db;
+            if let Err(e) = result {
+                return Ok((database, Err(e)));
+            }
+            handled = true;
+        }
+        Ok((database, Ok((ret, handled))))
+    }
+
     pub fn wal_generation_position(&self) -> Result<WalGenerationPos> {
         let generation = Generation::try_create(&self.current_generation()?)?;
 
@@ -481,7 +895,7 @@ impl Database {
         Ok(WalGenerationPos {
             generation,
             index,
-            offset: align_frame(self.page_size, file_metadata.size()),
+            offset: align_frame(self.page_size, file_metadata.len()),
         })
     }
 
@@ -500,7 +914,7 @@ impl Database {
     // create_generation initiates a new generation by establishing the generation
     // directory, capturing snapshots for each replica, and refreshing the current
     // generation name.
-    async fn create_generation(&mut self) -> Result<Generation> {
+    fn create_generation(&mut self) -> Result<Generation> {
         let generation = Generation::new();
 
         // create a temp file to write new generation
@@ -650,8 +1064,7 @@ impl Database {
         }
 
         // create new shadow wal file
-        let db_file_metadata = fs::metadata(&self.config.db)?;
-        let mode = db_file_metadata.mode();
+        let db_file_metadata = fs::metadata(&self.db_path)?;
         let dir = parent_dir(shadow_wal);
         if let Some(dir) = dir {
             fs::create_dir_all(&dir)?;
@@ -659,14 +1072,7 @@ impl Database {
             debug!("db {} cannot find parent dir of shadow wal", self.config.db);
         }
         let mut shadow_wal_file = fs::File::create(shadow_wal)?;
-        let mut permissions = shadow_wal_file.metadata()?.permissions();
-        permissions.set_mode(mode);
-        shadow_wal_file.set_permissions(permissions)?;
-        std::os::unix::fs::chown(
-            shadow_wal,
-            Some(db_file_metadata.uid()),
-            Some(db_file_metadata.gid()),
-        )?;
+        copy_permissions(&db_file_metadata, shadow_wal)?;
         // write wal file header into shadow wal file
         shadow_wal_file.write_all(&wal_header.data)?;
         shadow_wal_file.flush()?;
@@ -686,10 +1092,10 @@ impl Database {
     fn copy_to_shadow_wal(&self, shadow_wal: &String) -> Result<(u64, u64)> {
         let wal_file_name = &self.wal_file;
         let wal_file_metadata = fs::metadata(wal_file_name)?;
-        let orig_wal_size = align_frame(self.page_size, wal_file_metadata.size());
+        let orig_wal_size = align_frame(self.page_size, wal_file_metadata.len());
 
         let shadow_wal_file_metadata = fs::metadata(shadow_wal)?;
-        let orig_shadow_wal_size = align_frame(self.page_size, shadow_wal_file_metadata.size());
+        let orig_shadow_wal_size = align_frame(self.page_size, shadow_wal_file_metadata.len());
         debug!(
             "copy_to_shadow_wal orig_wal_size: {},  orig_shadow_wal_size: {}",
             orig_wal_size, orig_shadow_wal_size
@@ -820,61 +1226,14 @@ impl Database {
 
     // current_shadow_index returns the current WAL index & total size.
     fn current_shadow_index(&self, generation: &str) -> Result<(u64, u64)> {
-        let wal_dir = shadow_wal_dir(&self.meta_dir, generation);
-        if !fs::exists(&wal_dir)? {
-            return Ok((0, 0));
-        }
-
-        let entries = fs::read_dir(&wal_dir)?;
-        let mut total_size = 0;
-        let mut index = 0;
-        for entry in entries.flatten() {
-            let file_type = entry.file_type()?;
-            let file_name = entry.file_name().into_string().unwrap();
-            let path = Path::new(&wal_dir).join(&file_name);
-            if !fs::exists(&path)? {
-                // file was deleted after os.ReadDir returned
-                debug!(
-                    "db {} shadow wal {:?} deleted after read_dir return",
-                    self.config.db, path
-                );
-                continue;
-            }
-            if !file_type.is_file() {
-                continue;
-            }
-            let metadata = entry.metadata()?;
-            total_size += metadata.size();
-            match parse_wal_path(&file_name) {
-                Err(e) => {
-                    debug!("invalid wal file {:?}, err:{:?}", file_name, e);
-                    continue;
-                }
-                Ok(i) => {
-                    if i > index {
-                        index = i;
-                    }
-                }
-            }
-        }
-
-        Ok((index, total_size))
+        shadow_index(&self.config.db, &self.meta_dir, generation)
     }
 
     // current_generation returns the name of the generation saved to the "generation"
     // file in the meta data directory.
     // Returns empty string if none exists.
     fn current_generation(&self) -> Result<String> {
-        let generation_file = generation_file_path(&self.meta_dir);
-        if !fs::exists(&generation_file)? {
-            return Ok("".to_string());
-        }
-        let generation = fs::read_to_string(&generation_file)?;
-        if generation.len() != GENERATION_LEN {
-            return Ok("".to_string());
-        }
-
-        Ok(generation)
+        read_generation(&self.meta_dir)
     }
 
     // verify ensures the current shadow WAL state matches where it left off from
@@ -891,7 +1250,7 @@ impl Database {
         }
         info.generation = Generation::try_create(&generation)?;
 
-        let db_file = fs::metadata(&self.config.db)?;
+        let db_file = fs::metadata(&self.db_path)?;
         if let Ok(db_mod_time) = db_file.modified() {
             info.db_mod_time = Some(db_mod_time);
         } else {
@@ -924,6 +1283,11 @@ impl Database {
         }
 
         if info.shadow_wal_size > info.wal_size {
+            warn!(
+                "db {} wal {} truncated by another process to {} bytes, shadow wal {} has {} bytes, start a new generation",
+                self.config.db, self.wal_file, info.wal_size, info.shadow_wal_file, info.shadow_wal_size
+            );
+            self.stats.inc_external_wal_change("truncated");
             info.reason = Some("wal truncated by another process".to_string());
             return Ok(info);
         }
@@ -961,6 +1325,11 @@ impl Database {
                     self.wal_file,
                     wal_last_frame.salt1
                 );
+                warn!(
+                    "db {} wal {} overwritten by another process at offset {}, start a new generation",
+                    self.config.db, self.wal_file, offset
+                );
+                self.stats.inc_external_wal_change("overwritten");
                 info.reason = Some("wal overwritten by another process".to_string());
                 return Ok(info);
             }
@@ -969,7 +1338,7 @@ impl Database {
         Ok(info)
     }
 
-    fn checkpoint(&mut self, mode: CheckpointMode) -> Result<()> {
+    fn checkpoint(&mut self, mode: CheckpointMode) -> Result<CheckpointResult> {
         let generation = self.current_generation()?;
 
         self.do_checkpoint(&generation, mode.as_str())
@@ -977,7 +1346,7 @@ impl Database {
 
     // checkpoint performs a checkpoint on the WAL file and initializes a
     // new shadow WAL file.
-    fn do_checkpoint(&mut self, generation: &str, mode: &str) -> Result<()> {
+    fn do_checkpoint(&mut self, generation: &str, mode: &str) -> Result<CheckpointResult> {
         // Try getting a checkpoint lock, will fail during snapshots.
 
         let shadow_wal_file = self.current_shadow_wal_file(generation)?;
@@ -994,7 +1363,37 @@ impl Database {
 
         // Execute checkpoint and immediately issue a write to the WAL to ensure
         // a new page is written.
-        self.exec_checkpoint(mode)?;
+        let result = match self.exec_checkpoint(mode) {
+            Ok(result) => result,
+            Err(e) if e.code() == Error::DB_LOCKED_ERROR => {
+                warn!(
+                    "db {} checkpoint({}) busy, retry in next sync: {}",
+                    self.config.db,
+                    mode,
+                    e.message()
+                );
+                return Ok(CheckpointResult {
+                    busy: true,
+                    ..Default::default()
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        self.stats.inc_checkpoint(mode);
+        debug!(
+            "db {} checkpoint({}) result: {:?}",
+            self.config.db, mode, result
+        );
+
+        // the WAL cannot be restarted by a blocked checkpoint, skip restarting
+        // the shadow WAL until a checkpoint in a later sync completes.
+        if result.busy {
+            warn!(
+                "db {} checkpoint({}) blocked, retry in next sync: {:?}",
+                self.config.db, mode, result
+            );
+            return Ok(result);
+        }
 
         self.connection.execute(
             "INSERT INTO _replited_seq (id, seq) VALUES (1, 1) ON CONFLICT (id) DO UPDATE SET seq = seq + 1;",
@@ -1004,7 +1403,7 @@ impl Database {
         // If WAL hasn't been restarted, exit.
         let wal_header2 = WALHeader::read(&self.wal_file)?;
         if wal_header1 == wal_header2 {
-            return Ok(());
+            return Ok(result);
         }
 
         // Start a transaction. This will be promoted immediately after.
@@ -1032,10 +1431,10 @@ impl Database {
         let new_shadow_wal_file = self.shadow_wal_file(generation, index + 1);
         self.init_shadow_wal_file(&new_shadow_wal_file)?;
 
-        Ok(())
+        Ok(result)
     }
 
-    fn exec_checkpoint(&mut self, mode: &str) -> Result<()> {
+    fn exec_checkpoint(&mut self, mode: &str) -> Result<CheckpointResult> {
         // Ensure the read lock has been removed before issuing a checkpoint.
         // We defer the re-acquire to ensure it occurs even on an early return.
         self.release_read_lock()?;
@@ -1048,26 +1447,132 @@ impl Database {
         // See: https://www.sqlite.org/pragma.html#pragma_wal_checkpoint
         let sql = format!("PRAGMA wal_checkpoint({})", mode);
 
-        let ret = self.connection.execute_batch(&sql);
+        let ret = self.connection.query_row(&sql, [], |row| {
+            Ok(CheckpointResult {
+                busy: row.get::<_, i64>(0)? != 0,
+                log: row.get(1)?,
+                checkpointed: row.get(2)?,
+            })
+        });
 
         // Reacquire the read lock immediately after the checkpoint.
         self.acquire_read_lock()?;
 
-        ret?;
+        ret.map_err(|e| {
+            Database::sqlite_error(&self.config.db, &format!("checkpoint({})", mode), e)
+        })
+    }
 
-        Ok(())
+    // handles a db command, its blocking part runs on the blocking pool. The
+    // database is returned unless the blocking task panicked.
+    async fn handle_db_command(self, cmd: DbCommand) -> Result<(Self, Result<()>)> {
+        let (mut database, prepared) = self
+            .blocking(move |database| database.prepare_db_command(cmd))
+            .await?;
+        let result = match prepared {
+            Ok(prepared) => database.send_prepared_command(prepared).await,
+            Err(e) => Err(e),
+        };
+        Ok((database, result))
     }
 
-    pub async fn handle_db_command(&mut self, cmd: DbCommand) -> Result<()> {
+    // the blocking part of db commands.
+    fn prepare_db_command(&mut self, cmd: DbCommand) -> Result<PreparedCommand> {
         match cmd {
-            DbCommand::Snapshot(i) => self.handle_db_snapshot_command(i).await?,
+            DbCommand::Snapshot(index) if self.config.copy_before_snapshot => {
+                let (copy, pos) = self.copy_snapshot()?;
+                debug!("db {} snapshot copy of pos {:?}", self.config.db, pos);
+                Ok(PreparedCommand::SnapshotCopy(index, copy, pos))
+            }
+            DbCommand::Snapshot(index) => {
+                let (file, pos) = self.snapshot(index)?;
+                debug!("db {} snapshot of pos {:?}", self.config.db, pos);
+                Ok(PreparedCommand::Snapshot(index, file, pos))
+            }
+        }
+    }
+
+    async fn send_prepared_command(&mut self, prepared: PreparedCommand) -> Result<()> {
+        match prepared {
+            PreparedCommand::Snapshot(index, file, pos) => {
+                self.sync_notifiers[index]
+                    .send(ReplicateCommand::Snapshot((pos, file)))
+                    .await?;
+            }
+            PreparedCommand::SnapshotCopy(index, copy, pos) => {
+                self.compress_snapshot_copy(index, copy, pos);
+            }
         }
         Ok(())
     }
 
-    fn snapshot(&mut self) -> Result<(Vec<u8>, WalGenerationPos)> {
-        // Issue a passive checkpoint to flush any pages to disk before snapshotting.
-        self.checkpoint(CheckpointMode::Passive)?;
+    // take a snapshot compressed with the compression config of replicate `index`.
+    // replicates requesting a snapshot of the same position, e.g. when a new
+    // generation starts, share the one compressed for the first of them.
+    fn snapshot(&mut self, index: usize) -> Result<(File, WalGenerationPos)> {
+        let config = &self.config.replicate[index];
+        let (compression, compression_level) = (config.compression, config.compression_level);
+
+        let pos = self.wal_generation_position()?;
+        if let Some(snapshot) = self.snapshots.iter().find(|snapshot| {
+            snapshot.pos == pos
+                && snapshot.compression == compression
+                && snapshot.compression_level == compression_level
+        }) {
+            debug!(
+                "db {} reuse snapshot of pos {:?} for replicate {}",
+                self.config.db, pos, index
+            );
+            return Ok((snapshot.file.reopen()?, pos));
+        }
+
+        let pos = self.prepare_snapshot()?;
+
+        // compress db file into a temp file in meta dir, which is uploaded by
+        // the replicate after the read lock is released. Only the db file is
+        // copied: pages not checkpointed yet are replicated as wal segments,
+        // and the -shm file is a wal-index rebuilt by sqlite, never restored.
+        let compressed_file = compress_file(
+            &self.db_path,
+            compression,
+            compression_level,
+            &self.meta_dir,
+        )?;
+        let file = compressed_file.reopen()?;
+
+        // snapshots of an old position are never reused, remove them.
+        self.snapshots.retain(|snapshot| snapshot.pos == pos);
+        self.snapshots.push(SharedSnapshot {
+            pos: pos.clone(),
+            compression,
+            compression_level,
+            file: compressed_file,
+        });
+
+        Ok((file, pos))
+    }
+
+    // checkpoints the db and takes the read lock before snapshot, so the db
+    // file is not changed while it is read. Returns the position of snapshot.
+    fn prepare_snapshot(&mut self) -> Result<WalGenerationPos> {
+        if self.config.truncate_before_snapshot {
+            // Issue a truncation checkpoint so that the db file alone is a valid
+            // copy of the database, refuse to snapshot if any page is left in WAL.
+            let result = self.checkpoint(CheckpointMode::Truncate)?;
+            if !result.is_complete() {
+                error!(
+                    "db {} truncate checkpoint before snapshot not complete: {:?}",
+                    self.config.db, result
+                );
+                return Err(Error::IncompleteCheckpointError(format!(
+                    "db {} truncate checkpoint before snapshot not complete: {:?}",
+                    self.config.db, result
+                )));
+            }
+        } else {
+            // Issue a passive checkpoint to flush any pages to disk before snapshotting.
+            self.checkpoint(CheckpointMode::Passive)?;
+        }
 
         // Prevent internal checkpoints during snapshot.
 
@@ -1080,27 +1585,55 @@ impl Database {
             return Err(Error::NoGenerationError("no generation"));
         }
 
-        // compress db file
-        let compressed_data = compress_file(&self.config.db)?;
+        Ok(pos)
+    }
+
+    // copies the db file into a temp file in meta dir, so the copy is
+    // compressed without blocking the db. `VACUUM INTO` is not used: its page
+    // layout differs from the db the following wal segments apply to.
+    fn copy_snapshot(&mut self) -> Result<(NamedTempFile, WalGenerationPos)> {
+        let pos = self.prepare_snapshot()?;
 
-        Ok((compressed_data.to_owned(), pos))
+        let copy = NamedTempFile::new_in(&self.meta_dir)?;
+        fs::copy(&self.db_path, copy.path())?;
+        Ok((copy, pos))
     }
 
-    async fn handle_db_snapshot_command(&mut self, index: usize) -> Result<()> {
-        let (compressed_data, generation_pos) = self.snapshot()?;
-        debug!(
-            "db {} snapshot {} data of pos {:?}",
-            self.config.db,
-            compressed_data.len(),
-            generation_pos
-        );
-        self.sync_notifiers[index]
-            .send(ReplicateCommand::Snapshot((
-                generation_pos,
-                compressed_data,
-            )))
-            .await?;
-        Ok(())
+    // compresses a copy of the db on the blocking pool and sends it to the
+    // replicate once done, the db keeps syncing and checkpointing meanwhile.
+    fn compress_snapshot_copy(&self, index: usize, copy: NamedTempFile, pos: WalGenerationPos) {
+        let config = &self.config.replicate[index];
+        let (compression, compression_level) = (config.compression, config.compression_level);
+        let meta_dir = self.meta_dir.clone();
+        let db = self.config.db.clone();
+        let notifier = self.sync_notifiers[index].clone();
+        tokio::spawn(async move {
+            let result = spawn_blocking(move || -> Result<File> {
+                let copy_path = copy.path().to_str().unwrap().to_string();
+                let compressed_file =
+                    compress_file(&copy_path, compression, compression_level, &meta_dir)?;
+                // the replicate reads the file from the start.
+                let mut file = compressed_file.into_file();
+                file.seek(SeekFrom::Start(0))?;
+                Ok(file)
+            })
+            .await;
+            let file = match result {
+                Ok(Ok(file)) => file,
+                Ok(Err(e)) => {
+                    error!("db {} compress snapshot copy error: {:?}", db, e);
+                    return;
+                }
+                Err(e) => {
+                    error!("db {} compress snapshot copy task error: {:?}", db, e);
+                    return;
+                }
+            };
+            let command = ReplicateCommand::Snapshot((pos, file));
+            if let Err(e) = notifier.send(command).await {
+                error!("db {} send snapshot copy error: {:?}", db, e);
+            }
+        });
     }
 }
 
@@ -1110,27 +1643,568 @@ impl Drop for Database {
     }
 }
 
-pub async fn run_database(config: DbConfig) -> Result<()> {
-    let (mut database, mut db_receiver) = match Database::try_create(config.clone()) {
-        Ok((db, receiver)) => (db, receiver),
+// returns the current shadow wal index & total size of a generation in meta dir.
+fn shadow_index(db: &str, meta_dir: &str, generation: &str) -> Result<(u64, u64)> {
+    let wal_dir = shadow_wal_dir(meta_dir, generation);
+    if !fs::exists(&wal_dir)? {
+        return Ok((0, 0));
+    }
+
+    let entries = fs::read_dir(&wal_dir)?;
+    let mut total_size = 0;
+    let mut index = 0;
+    for entry in entries.flatten() {
+        let file_type = entry.file_type()?;
+        let file_name = entry.file_name().into_string().unwrap();
+        let path = Path::new(&wal_dir).join(&file_name);
+        if !fs::exists(&path)? {
+            // file was deleted after os.ReadDir returned
+            debug!(
+                "db {} shadow wal {:?} deleted after read_dir return",
+                db, path
+            );
+            continue;
+        }
+        if !file_type.is_file() {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        total_size += metadata.len();
+        match parse_wal_path(&file_name) {
+            Err(e) => {
+                debug!("invalid wal file {:?}, err:{:?}", file_name, e);
+                continue;
+            }
+            Ok(i) => {
+                if i > index {
+                    index = i;
+                }
+            }
+        }
+    }
+
+    Ok((index, total_size))
+}
+
+// returns the generation saved to the "generation" file in meta dir, or
+// empty string if none exists.
+fn read_generation(meta_dir: &str) -> Result<String> {
+    let generation_file = generation_file_path(meta_dir);
+    if !fs::exists(&generation_file)? {
+        return Ok("".to_string());
+    }
+    let generation = fs::read_to_string(&generation_file)?;
+    if generation.len() != GENERATION_LEN {
+        return Ok("".to_string());
+    }
+
+    Ok(generation)
+}
+
+// returns the position of the shadow wal of db by only reading the meta dir,
+// without opening the db. returns None if there is no generation yet.
+pub fn local_wal_position(db: &str) -> Result<Option<WalGenerationPos>> {
+    let db_path = Database::canonical_db_path(db)?;
+    let meta_dir = Database::meta_dir(&db_path);
+    let generation = read_generation(&meta_dir)?;
+    if generation.is_empty() {
+        return Ok(None);
+    }
+    let generation = Generation::try_create(&generation)?;
+
+    let (index, _total_size) = shadow_index(db, &meta_dir, generation.as_str())?;
+    let shadow_wal = shadow_wal_file(&meta_dir, generation.as_str(), index);
+    if !fs::exists(&shadow_wal)? {
+        return Ok(Some(WalGenerationPos {
+            generation,
+            index,
+            offset: 0,
+        }));
+    }
+
+    let page_size = WALHeader::read(&shadow_wal)?.page_size;
+    let offset = align_frame(page_size, fs::metadata(&shadow_wal)?.len());
+    Ok(Some(WalGenerationPos {
+        generation,
+        index,
+        offset,
+    }))
+}
+
+// min and max delay between retries of opening a db.
+const OPEN_DB_MIN_DELAY: Duration = Duration::from_millis(100);
+const OPEN_DB_MAX_DELAY: Duration = Duration::from_secs(5);
+
+// opens db, retrying with backoff for up to `wait_for_db_secs` on any error
+// since the app may be creating it, and up to `lock_timeout_secs` if the db
+// is locked by another connection. returns None if shutdown meanwhile.
+async fn open_database(
+    config: &DbConfig,
+    shutdown: &mut watch::Receiver<bool>,
+) -> Result<Option<(Database, Receiver<DbCommand>)>> {
+    let start = Instant::now();
+    let wait_for_db = Duration::from_secs(config.wait_for_db_secs);
+    let lock_timeout = Duration::from_secs(config.lock_timeout_secs);
+    let mut delay = OPEN_DB_MIN_DELAY;
+    loop {
+        // opening a missing db creates it, leave it to the app if waiting.
+        let result = if config.wait_for_db_secs > 0 && !Path::new(&config.db).exists() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("db file {} not found", config.db),
+            )
+            .into())
+        } else {
+            Database::try_create(config.clone())
+        };
+        let e = match result {
+            Ok(created) => return Ok(Some(created)),
+            Err(e) => e,
+        };
+
+        let elapsed = start.elapsed();
+        let retry =
+            elapsed < wait_for_db || (e.code() == Error::DB_LOCKED_ERROR && elapsed < lock_timeout);
+        if !retry {
+            return Err(e);
+        }
+        info!(
+            "open db {}: {}, retry in {:?}",
+            config.db,
+            e.message(),
+            delay
+        );
+
+        select! {
+            _ = sleep(delay) => {}
+            _ = shutdown.changed() => return Ok(None),
+        }
+        delay = min(delay * 2, OPEN_DB_MAX_DELAY);
+    }
+}
+
+// creates the buckets of replicates with `create_bucket`, then probes the
+// replicates with `verify_on_start`, so misconfigured ones fail at start
+// instead of at the first upload.
+async fn verify_replicates(config: &DbConfig) -> Result<()> {
+    for replicate in &config.replicate {
+        if !replicate.verify_on_start && !replicate.create_bucket {
+            continue;
+        }
+
+        let client = StorageClient::try_create(config.db.clone(), replicate.clone())?;
+        if replicate.create_bucket {
+            client.ensure_bucket().await?;
+        }
+        if !replicate.verify_on_start {
+            continue;
+        }
+        client.check().await?;
+        info!("verified replicate {} of db {}", replicate.name, config.db);
+    }
+
+    Ok(())
+}
+
+// takes a permit of `limiter` if any, held while the db syncs.
+async fn acquire_sync_permit(limiter: &Option<Arc<Semaphore>>) -> Option<OwnedSemaphorePermit> {
+    match limiter {
+        Some(limiter) => limiter.clone().acquire_owned().await.ok(),
+        None => None,
+    }
+}
+
+// `limiter` bounds the number of dbs syncing or snapshotting at the same time.
+pub async fn run_database(
+    config: DbConfig,
+    mut shutdown: watch::Receiver<bool>,
+    limiter: Option<Arc<Semaphore>>,
+) -> Result<()> {
+    if let Err(e) = verify_replicates(&config).await {
+        error!("run_database for {:?} error: {:?}", config, e);
+        return Err(e);
+    }
+
+    let (database, mut db_receiver) = match open_database(&config, &mut shutdown).await {
+        Ok(Some((db, receiver))) => (db, receiver),
+        Ok(None) => return Ok(()),
         Err(e) => {
             error!("run_database for {:?} error: {:?}", config, e);
             return Err(e);
         }
     };
+    let monitor_interval = Duration::from_millis(config.monitor_interval_ms);
+    let watch_debounce = Duration::from_millis(config.watch_debounce_ms);
+    let mut wal_watcher = None;
+    if config.watch_wal {
+        match WalWatcher::try_create(&database.wal_file) {
+            Ok(watcher) => wal_watcher = Some(watcher),
+            Err(e) => {
+                error!("watch wal of db {} error: {:?}", config.db, e);
+                return Err(e);
+            }
+        }
+    }
+    // grows while the wal is idle, reset on the first change.
+    let mut idle_interval = monitor_interval;
+    // the db is moved into the blocking pool while a job is in flight, shutdown
+    // and db commands are still received meanwhile. Queued commands are
+    // handled in order once the db is back, before the next sync.
+    let mut database = Some(database);
+    let mut in_flight = None;
+    let mut commands = VecDeque::new();
+    let mut sync_requested = false;
+    let mut stopping = false;
     loop {
+        if let Some(db) = database.take() {
+            if stopping {
+                database = Some(db);
+                break;
+            }
+            if let Some(cmd) = commands.pop_front() {
+                let permit = acquire_sync_permit(&limiter).await;
+                let handle =
+                    db.spawn_blocking(move |db| DbJob::Command(db.prepare_db_command(cmd)));
+                in_flight = Some((handle, permit));
+            } else if sync_requested {
+                sync_requested = false;
+                let permit = acquire_sync_permit(&limiter).await;
+                let handle = db.spawn_blocking(|db| DbJob::Sync(db.sync()));
+                in_flight = Some((handle, permit));
+            } else {
+                database = Some(db);
+            }
+        }
+
+        let idle = database.is_some();
         select! {
-            cmd = db_receiver.recv() => {
-                 if let Some(cmd) = cmd { if let Err(e) = database.handle_db_command(cmd).await {
-                     error!("handle_db_command of db {} error: {:?}", database.config.db, e);
+            joined = join_db_job(&mut in_flight) => {
+                let (mut db, job, _permit) = match joined {
+                    Ok(joined) => joined,
+                    Err(e) => {
+                        error!("run_database for {} error: {:?}", config.db, e);
+                        return Err(e);
+                    }
+                };
+                match job {
+                    DbJob::Sync(Ok(false)) => {
+                        idle_interval = next_monitor_interval(&config, idle_interval);
+                    }
+                    DbJob::Sync(Ok(true)) => idle_interval = monitor_interval,
+                    DbJob::Sync(Err(e)) => {
+                        idle_interval = monitor_interval;
+                        error!("sync db {} error: {:?}", config.db, e);
+                    }
+                    DbJob::Command(prepared) => {
+                        let result = match prepared {
+                            Ok(prepared) => db.send_prepared_command(prepared).await,
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = result {
+                            error!("handle_db_command of db {} error: {:?}", config.db, e);
+                        }
                     }
                 }
+                database = Some(db);
             }
-            _ = sleep(DEFAULT_MONITOR_INTERVAL) => {
-                if let Err(e) = database.sync().await {
-                    error!("sync db {} error: {:?}", database.config.db, e);
+            cmd = db_receiver.recv() => {
+                if let Some(cmd) = cmd {
+                    commands.push_back(cmd);
                 }
             }
+            _ = sleep(idle_interval), if idle => sync_requested = true,
+            _ = wal_changed(&mut wal_watcher, watch_debounce) => {
+                idle_interval = monitor_interval;
+                sync_requested = true;
+            }
+            _ = shutdown.changed(), if !stopping => stopping = true,
         }
     }
+
+    if let Some(database) = database {
+        database.shutdown().await;
+    }
+    Ok(())
+}
+
+// blocking work of a db running on the blocking pool.
+enum DbJob {
+    Sync(Result<bool>),
+    Command(Result<PreparedCommand>),
+}
+
+type DbJobHandle = (JoinHandle<(Database, DbJob)>, Option<OwnedSemaphorePermit>);
+
+// waits for the db job in flight, pending if there is none. Returns the db
+// with the job result and the permit held by the job.
+async fn join_db_job(
+    in_flight: &mut Option<DbJobHandle>,
+) -> Result<(Database, DbJob, Option<OwnedSemaphorePermit>)> {
+    let Some((handle, _)) = in_flight.as_mut() else {
+        return pending().await;
+    };
+    let result = handle.await;
+    let (_, permit) = in_flight.take().unwrap();
+    let (database, job) = result
+        .map_err(|e| Error::SpawnDatabaseTaskError(format!("db blocking task error: {:?}", e)))?;
+    Ok((database, job, permit))
+}
+
+// doubles the monitor interval after a check without changes, up to
+// `max_monitor_interval_ms`.
+fn next_monitor_interval(config: &DbConfig, interval: Duration) -> Duration {
+    let max_interval = Duration::from_millis(config.max_monitor_interval_ms);
+    if max_interval <= interval {
+        return interval;
+    }
+    min(interval * 2, max_interval)
+}
+
+// max rounds of one-shot sync, more than one round is needed only when a
+// replicate requests a snapshot of a new generation.
+const SYNC_ONCE_MAX_ROUNDS: usize = 3;
+
+// syncs the db once and waits until all replicates have uploaded the changes,
+// returns the last replicate error.
+pub async fn sync_database(config: DbConfig) -> Result<()> {
+    let (mut database, mut db_receiver) = Database::try_create(config.clone())?;
+
+    let mut ret = Ok(());
+    for _ in 0..SYNC_ONCE_MAX_ROUNDS {
+        let (db, result) = database.sync_round(&mut db_receiver).await?;
+        database = db;
+        match result {
+            Ok((result, handled)) => {
+                ret = result;
+                if !handled {
+                    break;
+                }
+            }
+            Err(e) => {
+                ret = Err(e);
+                break;
+            }
+        }
+    }
+
+    database.shutdown().await;
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
+
+    use clap::Parser;
+    use rusqlite::Connection;
+    use tempfile::tempdir;
+    use tokio::sync::mpsc;
+    use tokio::sync::mpsc::Receiver;
+
+    use super::Database;
+    use super::DbCommand;
+    use super::ProcessLock;
+    use crate::config::fs_db_config;
+    use crate::config::RestoreOptions;
+    use crate::error::Error;
+    use crate::error::Result;
+    use crate::sync::run_restore;
+    use crate::sync::ReplicateCommand;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_init_directory_of_symlink_db() -> Result<()> {
+        let dir = tempdir()?;
+        let real_dir = dir.path().join("real");
+        let link_dir = dir.path().join("link");
+        fs::create_dir_all(&real_dir)?;
+        fs::create_dir_all(&link_dir)?;
+
+        let real_db = real_dir.join("test.db");
+        let link_db = link_dir.join("test.db");
+        fs::write(&real_db, b"")?;
+        symlink(&real_db, &link_db)?;
+
+        let db_path = Database::canonical_db_path(link_db.to_str().unwrap())?;
+        assert_eq!(
+            fs::canonicalize(&real_db)?.to_str().unwrap(),
+            db_path.as_str()
+        );
+
+        let meta_dir = Database::init_directory(&db_path)?;
+        let real_dir = fs::canonicalize(&real_dir)?;
+        assert_eq!(
+            format!("{}/.test.db-replited/", real_dir.to_str().unwrap()),
+            meta_dir
+        );
+        assert!(fs::exists(&meta_dir)?);
+        assert!(!fs::exists(link_dir.join(".test.db-replited"))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_init_params_of_invalid_db() -> Result<()> {
+        let dir = tempdir()?;
+        let db = dir.path().join("test.db");
+        fs::write(&db, b"not a sqlite database, just some text".repeat(100))?;
+        let db = db.to_str().unwrap();
+
+        let connection = Connection::open(db)?;
+        let e = Database::init_params(db, &connection).unwrap_err();
+        assert_eq!(e.code(), Error::SQLITE_INVALID_DB_HEADER_ERROR);
+
+        Ok(())
+    }
+
+    // syncs rounds until no db command is handled, like `sync_database`.
+    async fn sync_rounds(
+        database: Database,
+        db_receiver: &mut Receiver<DbCommand>,
+    ) -> Result<Database> {
+        let mut database = database;
+        loop {
+            let (db, result) = database.sync_round(db_receiver).await?;
+            database = db;
+            let (result, handled) = result?;
+            result?;
+            if !handled {
+                return Ok(database);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_vacuum_between_syncs() -> Result<()> {
+        let dir = tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let db = path("test.db");
+        let connection = Connection::open(&db)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL; CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);",
+        )?;
+        let insert = |range: std::ops::Range<i64>| -> Result<()> {
+            for i in range {
+                connection.execute(
+                    "INSERT INTO test (id, value) VALUES (?1, ?2)",
+                    (i, "x".repeat(1000)),
+                )?;
+            }
+            Ok(())
+        };
+        insert(0..1000)?;
+
+        let config = fs_db_config(&db, &[&path("replica")]);
+        let (database, mut db_receiver) = Database::try_create(config.clone())?;
+        let database = sync_rounds(database, &mut db_receiver).await?;
+        let generation = database.current_generation()?;
+
+        // a small shrink keeps the generation.
+        connection.execute("DELETE FROM test WHERE id < 100", ())?;
+        connection.execute_batch("VACUUM;")?;
+        let database = sync_rounds(database, &mut db_receiver).await?;
+        assert_eq!(database.current_generation()?, generation);
+
+        // VACUUM after deleting most rows starts a new generation.
+        connection.execute("DELETE FROM test WHERE id < 900", ())?;
+        connection.execute_batch("VACUUM;")?;
+        let database = sync_rounds(database, &mut db_receiver).await?;
+        assert_ne!(database.current_generation()?, generation);
+
+        insert(1000..1010)?;
+        let database = sync_rounds(database, &mut db_receiver).await?;
+        database.shutdown().await;
+
+        let output = path("restored.db");
+        let options = RestoreOptions::parse_from(["restore", "--db", &db, "--output", &output]);
+        run_restore(&config, &options).await?;
+        let count = |db: &str| -> Result<(i64, i64)> {
+            let connection = Connection::open(db)?;
+            Ok(
+                connection.query_row("SELECT count(*), sum(id) FROM test", (), |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?,
+            )
+        };
+        assert_eq!(count(&output)?, (110, count(&db)?.1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_lock_before_open() -> Result<()> {
+        let dir = tempdir()?;
+        let db = dir.path().join("test.db");
+        let meta_dir =
+            Database::init_directory(&Database::canonical_db_path(db.to_str().unwrap())?)?;
+        let _lock = ProcessLock::acquire("test.db", &meta_dir)?;
+
+        // the db is not created by a process failing to take the lock.
+        let config = fs_db_config(
+            db.to_str().unwrap(),
+            &[dir.path().join("replica").to_str().unwrap()],
+        );
+        let e = Database::try_create(config).err().unwrap();
+        assert_eq!(e.code(), Error::DB_IN_USE_ERROR);
+        assert!(!fs::exists(&db)?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_notify_every_replicate() -> Result<()> {
+        let dir = tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let db = path("test.db");
+        let connection = Connection::open(&db)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL; CREATE TABLE test (id INTEGER PRIMARY KEY);",
+        )?;
+
+        let config = fs_db_config(&db, &[&path("a"), &path("b")]);
+        let (mut database, _db_receiver) = Database::try_create(config)?;
+
+        // replace the notifiers of replicates to see what they receive, the
+        // second one has a full channel.
+        let (first, mut first_receiver) = mpsc::channel(8);
+        let (second, mut second_receiver) = mpsc::channel(1);
+        database.sync_notifiers = vec![first, second.clone()];
+        database.notify_pending = vec![false; 2];
+
+        connection.execute("INSERT INTO test (id) VALUES (1)", ())?;
+        database.sync()?;
+        let pos = database.wal_generation_position()?;
+        match first_receiver.try_recv() {
+            Ok(ReplicateCommand::DbChanged(changed)) => assert_eq!(changed, pos),
+            _ => panic!("first replicate is not notified"),
+        }
+        match second_receiver.try_recv() {
+            Ok(ReplicateCommand::DbChanged(changed)) => assert_eq!(changed, pos),
+            _ => panic!("second replicate is not notified"),
+        }
+
+        // a full channel does not block others, and is notified again later.
+        second
+            .try_send(ReplicateCommand::DbChanged(pos.clone()))
+            .unwrap();
+        connection.execute("INSERT INTO test (id) VALUES (2)", ())?;
+        database.sync()?;
+        assert!(matches!(
+            first_receiver.try_recv(),
+            Ok(ReplicateCommand::DbChanged(_))
+        ));
+        assert_eq!(database.notify_pending, vec![false, true]);
+
+        second_receiver.try_recv().unwrap();
+        database.sync()?;
+        assert!(matches!(
+            second_receiver.try_recv(),
+            Ok(ReplicateCommand::DbChanged(_))
+        ));
+        assert_eq!(database.notify_pending, vec![false, false]);
+
+        Ok(())
+    }
 }
@@ -1,7 +1,11 @@
 #[allow(clippy::module_inception)]
 mod database;
+mod process_lock;
+mod wal_watcher;
 
+pub use database::local_wal_position;
 pub use database::run_database;
+pub use database::sync_database;
 pub use database::DatabaseInfo;
 pub use database::DbCommand;
 pub use database::WalGenerationPos;
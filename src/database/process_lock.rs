@@ -0,0 +1,89 @@
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::process;
+
+use log::error;
+use log::info;
+
+use crate::base::open_lock_file;
+use crate::base::process_lock_file;
+use crate::error::Error;
+use crate::error::Result;
+
+// lock of the meta dir held by the replited process replicating the db, so a
+// second process fails fast instead of racing on the shadow wal. The pid of
+// the holder is recorded in the lock file, the lock is released on drop.
+pub struct ProcessLock {
+    _file: File,
+}
+
+impl ProcessLock {
+    pub fn acquire(db: &str, meta_dir: &str) -> Result<Self> {
+        let path = process_lock_file(meta_dir);
+        let mut file = match open_lock_file(&path)? {
+            Some(file) => file,
+            None => {
+                let holder = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|content| content.trim().parse::<u32>().ok());
+                let msg = match holder {
+                    Some(pid) => format!(
+                        "db {} is replicated by another replited process {}, lock file: {}",
+                        db, pid, path
+                    ),
+                    None => format!(
+                        "db {} is replicated by another replited process, lock file: {}",
+                        db, path
+                    ),
+                };
+                error!("{}", msg);
+                return Err(Error::DbInUseError(msg));
+            }
+        };
+
+        // the lock is released when its holder exits or crashes, so a pid left
+        // in the file is of a process which no longer holds it.
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        if let Ok(pid) = content.trim().parse::<u32>() {
+            if pid != process::id() {
+                info!("db {} take over lock file left by process {}", db, pid);
+            }
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", process::id())?;
+        file.flush()?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::ProcessLock;
+    use crate::error::Error;
+    use crate::error::Result;
+
+    #[test]
+    fn test_process_lock() -> Result<()> {
+        let dir = tempdir()?;
+        let meta_dir = dir.path().to_str().unwrap();
+
+        let lock = ProcessLock::acquire("test.db", meta_dir)?;
+        let result = ProcessLock::acquire("test.db", meta_dir);
+        assert_eq!(result.err().unwrap().code(), Error::DB_IN_USE_ERROR);
+
+        drop(lock);
+        let _lock = ProcessLock::acquire("test.db", meta_dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::future::pending;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::error;
+use notify::recommended_watcher;
+use notify::Event;
+use notify::EventKind;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::sleep;
+
+use crate::error::Result;
+
+// watch modifications of the wal file of db.
+//
+// The directory of the wal file is watched instead of the file itself, so the
+// watch survives the wal file being removed and recreated.
+pub struct WalWatcher {
+    // events are sent to receiver as long as the watcher is alive.
+    _watcher: RecommendedWatcher,
+    receiver: Receiver<()>,
+}
+
+impl WalWatcher {
+    pub fn try_create(wal_file: &str) -> Result<Self> {
+        let wal_file = PathBuf::from(wal_file);
+        let dir = wal_file
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .to_path_buf();
+
+        // a full channel means a sync is already pending, so drop the event.
+        let (sender, receiver) = mpsc::channel(1);
+        let mut watcher = recommended_watcher(move |event: notify::Result<Event>| match event {
+            Ok(event) => {
+                if !matches!(event.kind, EventKind::Access(_))
+                    && event.paths.iter().any(|path| path == &wal_file)
+                {
+                    let _ = sender.try_send(());
+                }
+            }
+            Err(e) => error!("watch wal file error: {:?}", e),
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            receiver,
+        })
+    }
+
+    // wait until wal file changed, changes within `debounce` are merged.
+    pub async fn changed(&mut self, debounce: Duration) {
+        if self.receiver.recv().await.is_none() {
+            pending::<()>().await;
+        }
+
+        sleep(debounce).await;
+        while self.receiver.try_recv().is_ok() {}
+    }
+}
+
+// wait until wal file changed, never returns if there is no watcher.
+pub async fn wal_changed(watcher: &mut Option<WalWatcher>, debounce: Duration) {
+    match watcher {
+        Some(watcher) => watcher.changed(debounce).await,
+        None => pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+    use tokio::time::timeout;
+
+    use super::WalWatcher;
+    use crate::error::Result;
+
+    #[tokio::test]
+    async fn test_wal_watcher_survives_recreate() -> Result<()> {
+        let dir = tempdir()?;
+        let wal_file = dir.path().join("test.db-wal");
+        fs::write(&wal_file, b"")?;
+        let mut watcher = WalWatcher::try_create(wal_file.to_str().unwrap())?;
+        let debounce = Duration::from_millis(10);
+        let wait = Duration::from_secs(5);
+
+        fs::write(&wal_file, b"frame")?;
+        assert!(timeout(wait, watcher.changed(debounce)).await.is_ok());
+
+        // changes of other files are ignored.
+        fs::write(dir.path().join("test.db"), b"page")?;
+        assert!(timeout(debounce * 10, watcher.changed(debounce))
+            .await
+            .is_err());
+
+        fs::remove_file(&wal_file)?;
+        fs::write(&wal_file, b"frame")?;
+        assert!(timeout(wait, watcher.changed(debounce)).await.is_ok());
+
+        Ok(())
+    }
+}
@@ -62,6 +62,14 @@ build_error! {
     StoragePermissionDenied(52),
     StorageOther(53),
     InvalidPath(54),
+    DecryptError(55),
+    ObjectArchived(56),
+    ChecksumMismatch(57),
+    IncompatibleGeneration(58),
+    EncryptionKeyRequired(59),
+    StorageUnsupported(60),
+    StorageRateLimited(61),
+    InvalidCompressedBlock(62),
 
     // database error
     SpawnDatabaseTaskError(80),
@@ -70,11 +78,20 @@ build_error! {
     WalReaderOffsetTooHighError(83),
     InvalidWalSegmentError(84),
     MismatchWalHeaderError(85),
+    RestoredDbSizeMismatchError(86),
+    IncompleteCheckpointError(87),
+    RestoredDbVerifyError(88),
+    ReplicateSyncError(89),
+    DbLockedError(90),
+    DbInUseError(91),
+    PageSizeMismatchError(92),
 
     // 3rd crate error
     TokioError(100),
     OpenDalError(101),
     UUIDError(102),
+    NotifyError(103),
+    HttpError(104),
 
     // sqlite error
     SqliteError(120),
@@ -85,6 +102,7 @@ build_error! {
     NoSnapshotError(125),
     NoWalsegmentError(126),
     BadShadowWalError(127),
+    SqliteInvalidDbHeaderError(128),
 
     // other error
     PanicError(140),
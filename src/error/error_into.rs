@@ -83,7 +83,28 @@ impl From<std::num::ParseIntError> for Error {
 
 impl From<opendal::Error> for Error {
     fn from(e: opendal::Error) -> Error {
-        Error::OpenDalError(format!("opendal error: {:?}", e.to_string()))
+        use opendal::ErrorKind;
+
+        let msg = format!("opendal error: {:?}", e.to_string());
+
+        match e.kind() {
+            ErrorKind::NotFound => Error::StorageNotFound(msg),
+            ErrorKind::PermissionDenied => Error::StoragePermissionDenied(msg),
+            ErrorKind::RateLimited => Error::StorageRateLimited(msg),
+            _ => Error::OpenDalError(msg),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        Error::HttpError(format!("http error: {:?}", e.to_string()))
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Error {
+        Error::from_std_error(e)
     }
 }
 
@@ -93,6 +114,12 @@ impl From<uuid::Error> for Error {
     }
 }
 
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Error {
+        Error::NotifyError(format!("notify error: {:?}", e.to_string()))
+    }
+}
+
 impl From<tokio::sync::mpsc::error::SendError<ReplicateCommand>> for Error {
     fn from(e: tokio::sync::mpsc::error::SendError<ReplicateCommand>) -> Error {
         Error::TokioError(format!(
@@ -114,6 +141,12 @@ impl From<tokio::sync::broadcast::error::RecvError> for Error {
     }
 }
 
+impl From<tokio::sync::oneshot::error::RecvError> for Error {
+    fn from(e: tokio::sync::oneshot::error::RecvError) -> Error {
+        Error::TokioError(format!("tokio oneshot recv error: {:?}", e.to_string()))
+    }
+}
+
 impl From<SystemTimeError> for Error {
     fn from(e: SystemTimeError) -> Error {
         Error::from_std_error(e)
@@ -2,33 +2,47 @@ use log::LevelFilter;
 use logforth::append::rolling_file::NonBlockingBuilder;
 use logforth::append::rolling_file::RollingFileWriter;
 use logforth::append::RollingFile;
+use logforth::append::Stdout;
+use logforth::layout::JsonLayout;
 use logforth::layout::TextLayout;
 use logforth::Dispatch;
 use logforth::Logger;
 
 use crate::config::LogConfig;
+use crate::config::LogFormat;
+use crate::config::LogTarget;
 use crate::error::Result;
 
-pub fn init_log(log_config: LogConfig) -> Result<()> {
-    let level: LevelFilter = log_config.level.into();
-
+fn rolling_file(log_config: &LogConfig) -> Result<RollingFile> {
     let rolling = RollingFileWriter::builder()
-        .max_file_size(1024 * 4096) // bytes
-        .max_log_files(9)
+        .max_file_size(log_config.rollover_size_bytes)
+        .max_log_files(log_config.max_files)
         .filename_prefix("replited")
         .filename_suffix("log")
-        .build(log_config.dir)?;
+        .build(&log_config.dir)?;
     let (writer, guard) = NonBlockingBuilder::default().finish(rolling);
     std::mem::forget(guard);
 
-    Logger::new()
-        .dispatch(
-            Dispatch::new()
-                .filter(level)
-                .layout(TextLayout::default().no_color())
-                .append(RollingFile::new(writer)),
-        )
-        .apply()?;
+    Ok(RollingFile::new(writer))
+}
+
+pub fn init_log(log_config: LogConfig) -> Result<()> {
+    let level: LevelFilter = log_config.level.clone().into();
+
+    let dispatch = Dispatch::new().filter(level);
+    let dispatch = match log_config.format {
+        LogFormat::Text => dispatch.layout(TextLayout::default().no_color()),
+        LogFormat::Json => dispatch.layout(JsonLayout::default()),
+    };
+    let dispatch = match log_config.target {
+        LogTarget::File => dispatch.append(rolling_file(&log_config)?),
+        LogTarget::Stdout => dispatch.append(Stdout::default()),
+        LogTarget::Both => dispatch
+            .append(rolling_file(&log_config)?)
+            .append(Stdout::default()),
+    };
+
+    Logger::new().dispatch(dispatch).apply()?;
 
     Ok(())
 }
@@ -7,6 +7,8 @@ mod config;
 mod database;
 mod error;
 mod log;
+mod metrics;
+mod runtime;
 mod sqlite;
 mod storage;
 mod sync;
@@ -15,15 +17,17 @@ use clap::Parser;
 use config::Arg;
 
 use crate::cmd::command;
+use crate::runtime::Runtime;
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     let arg = Arg::parse();
-    println!("arg: {:?}\n", arg);
 
-    let mut cmd = command(arg)?;
+    let runtime = Runtime::with_worker_threads("main", arg.worker_threads)?;
+    runtime.block_on(async move {
+        let mut cmd = command(arg)?;
 
-    cmd.run().await?;
+        cmd.run().await?;
 
-    Ok(())
+        Ok(())
+    })
 }
@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::LazyLock;
+
+use parking_lot::Mutex;
+use parking_lot::RwLock;
+
+use crate::database::WalGenerationPos;
+
+// global registry of db and replicate stats, rendered by the metrics server.
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+// stats of a db, shared between the db task and the metrics server.
+#[derive(Debug, Default)]
+pub struct DbStats {
+    // number of checkpoints by checkpoint mode.
+    checkpoints: Mutex<BTreeMap<String, u64>>,
+    // number of wal changes by another process by kind, e.g. truncated.
+    external_wal_changes: Mutex<BTreeMap<String, u64>>,
+}
+
+impl DbStats {
+    pub fn inc_checkpoint(&self, mode: &str) {
+        *self.checkpoints.lock().entry(mode.to_string()).or_default() += 1;
+    }
+
+    pub fn inc_external_wal_change(&self, kind: &str) {
+        *self
+            .external_wal_changes
+            .lock()
+            .entry(kind.to_string())
+            .or_default() += 1;
+    }
+}
+
+// stats of a replicate, shared between the replicate task and the metrics server.
+#[derive(Debug, Default)]
+pub struct ReplicateStats {
+    // bytes of snapshots and wal segments uploaded.
+    pub uploaded_bytes: AtomicU64,
+    pub snapshots: AtomicU64,
+    pub sync_success: AtomicU64,
+    pub sync_failure: AtomicU64,
+    // unix timestamp in seconds of the last successful sync, zero if never.
+    pub last_sync_timestamp: AtomicI64,
+}
+
+// name, type, help and value of a replicate metric.
+type ReplicateMetric = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&ReplicateEntry) -> String,
+);
+
+struct DbEntry {
+    db: String,
+    stats: Arc<DbStats>,
+}
+
+struct ReplicateEntry {
+    db: String,
+    replicate: String,
+    position: Arc<RwLock<WalGenerationPos>>,
+    stats: Arc<ReplicateStats>,
+}
+
+#[derive(Default)]
+struct Metrics {
+    dbs: RwLock<Vec<DbEntry>>,
+    replicates: RwLock<Vec<ReplicateEntry>>,
+    // number of storage retries by (db, replicate), counted by the storage
+    // client which is also used by commands other than `replicate`.
+    storage_retries: Mutex<BTreeMap<(String, String), u64>>,
+}
+
+pub fn register_db(db: &str) -> Arc<DbStats> {
+    METRICS.register_db(db)
+}
+
+pub fn register_replicate(
+    db: &str,
+    replicate: &str,
+    position: Arc<RwLock<WalGenerationPos>>,
+) -> Arc<ReplicateStats> {
+    METRICS.register_replicate(db, replicate, position)
+}
+
+// removes the stats of a db and its replicates, once the db is stopped.
+pub fn unregister_db(db: &str) {
+    METRICS.unregister_db(db)
+}
+
+pub fn inc_storage_retry(db: &str, replicate: &str) {
+    METRICS.inc_storage_retry(db, replicate)
+}
+
+// returns all metrics in prometheus text format.
+pub fn render() -> String {
+    METRICS.render()
+}
+
+// escapes a label value of the prometheus text format, db paths and replicate
+// names may contain any character.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    fn register_db(&self, db: &str) -> Arc<DbStats> {
+        let stats = Arc::new(DbStats::default());
+        self.dbs.write().push(DbEntry {
+            db: db.to_string(),
+            stats: stats.clone(),
+        });
+        stats
+    }
+
+    fn register_replicate(
+        &self,
+        db: &str,
+        replicate: &str,
+        position: Arc<RwLock<WalGenerationPos>>,
+    ) -> Arc<ReplicateStats> {
+        let stats = Arc::new(ReplicateStats::default());
+        self.replicates.write().push(ReplicateEntry {
+            db: db.to_string(),
+            replicate: replicate.to_string(),
+            position,
+            stats: stats.clone(),
+        });
+        stats
+    }
+
+    fn unregister_db(&self, db: &str) {
+        self.dbs.write().retain(|entry| entry.db != db);
+        self.replicates.write().retain(|entry| entry.db != db);
+        self.storage_retries
+            .lock()
+            .retain(|(entry_db, _), _| entry_db != db);
+    }
+
+    fn inc_storage_retry(&self, db: &str, replicate: &str) {
+        *self
+            .storage_retries
+            .lock()
+            .entry((db.to_string(), replicate.to_string()))
+            .or_default() += 1;
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        let dbs = self.dbs.read();
+        header(
+            &mut out,
+            "replited_checkpoint_total",
+            "counter",
+            "Number of checkpoints by mode.",
+        );
+        for entry in dbs.iter() {
+            for (mode, count) in entry.stats.checkpoints.lock().iter() {
+                let _ = writeln!(
+                    out,
+                    "replited_checkpoint_total{{db=\"{}\",mode=\"{}\"}} {}",
+                    label(&entry.db),
+                    label(mode),
+                    count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "replited_external_wal_change_total",
+            "counter",
+            "Number of wal truncations or overwrites by another process.",
+        );
+        for entry in dbs.iter() {
+            for (kind, count) in entry.stats.external_wal_changes.lock().iter() {
+                let _ = writeln!(
+                    out,
+                    "replited_external_wal_change_total{{db=\"{}\",kind=\"{}\"}} {}",
+                    label(&entry.db),
+                    label(kind),
+                    count
+                );
+            }
+        }
+
+        let replicates = self.replicates.read();
+        let metrics: [ReplicateMetric; 7] = [
+            (
+                "replited_uploaded_bytes_total",
+                "counter",
+                "Bytes of snapshots and wal segments uploaded.",
+                |e| e.stats.uploaded_bytes.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "replited_snapshot_total",
+                "counter",
+                "Number of snapshots uploaded.",
+                |e| e.stats.snapshots.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "replited_last_sync_timestamp_seconds",
+                "gauge",
+                "Unix timestamp of the last successful sync.",
+                |e| {
+                    e.stats
+                        .last_sync_timestamp
+                        .load(Ordering::Relaxed)
+                        .to_string()
+                },
+            ),
+            (
+                "replited_wal_index",
+                "gauge",
+                "Wal index of the replicate position.",
+                |e| e.position.read().index.to_string(),
+            ),
+            (
+                "replited_wal_offset",
+                "gauge",
+                "Wal offset of the replicate position.",
+                |e| e.position.read().offset.to_string(),
+            ),
+            (
+                "replited_sync_success_total",
+                "counter",
+                "Number of successful syncs.",
+                |e| e.stats.sync_success.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "replited_sync_failure_total",
+                "counter",
+                "Number of failed syncs.",
+                |e| e.stats.sync_failure.load(Ordering::Relaxed).to_string(),
+            ),
+        ];
+        for (name, kind, help, value) in metrics {
+            header(&mut out, name, kind, help);
+            for entry in replicates.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{db=\"{}\",replicate=\"{}\"}} {}",
+                    name,
+                    label(&entry.db),
+                    label(&entry.replicate),
+                    value(entry)
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "replited_storage_retry_total",
+            "counter",
+            "Number of retries of storage operations.",
+        );
+        for ((db, replicate), count) in self.storage_retries.lock().iter() {
+            let _ = writeln!(
+                out,
+                "replited_storage_retry_total{{db=\"{}\",replicate=\"{}\"}} {}",
+                label(db),
+                label(replicate),
+                count
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+
+    use parking_lot::RwLock;
+
+    use super::Metrics;
+    use crate::database::WalGenerationPos;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        let db_stats = metrics.register_db("test.db");
+        db_stats.inc_checkpoint("PASSIVE");
+        db_stats.inc_checkpoint("PASSIVE");
+        db_stats.inc_external_wal_change("truncated");
+
+        let position = Arc::new(RwLock::new(WalGenerationPos {
+            index: 3,
+            offset: 4152,
+            ..Default::default()
+        }));
+        let stats = metrics.register_replicate("test.db", "s3", position);
+        stats.uploaded_bytes.fetch_add(1024, Ordering::Relaxed);
+        metrics.inc_storage_retry("test.db", "s3");
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE replited_checkpoint_total counter\n"));
+        assert!(out.contains("replited_checkpoint_total{db=\"test.db\",mode=\"PASSIVE\"} 2\n"));
+        assert!(out
+            .contains("replited_external_wal_change_total{db=\"test.db\",kind=\"truncated\"} 1\n"));
+        assert!(
+            out.contains("replited_uploaded_bytes_total{db=\"test.db\",replicate=\"s3\"} 1024\n")
+        );
+        assert!(out.contains("replited_wal_index{db=\"test.db\",replicate=\"s3\"} 3\n"));
+        assert!(out.contains("replited_wal_offset{db=\"test.db\",replicate=\"s3\"} 4152\n"));
+        assert!(out.contains("replited_storage_retry_total{db=\"test.db\",replicate=\"s3\"} 1\n"));
+    }
+
+    #[test]
+    fn test_render_escaped_labels() {
+        let metrics = Metrics::default();
+        let position = Arc::new(RwLock::new(WalGenerationPos::default()));
+        metrics.register_replicate("C:\\data\\\"test\".db", "s3\nbackup", position);
+
+        let out = metrics.render();
+        assert!(out.contains(
+            "replited_wal_index{db=\"C:\\\\data\\\\\\\"test\\\".db\",replicate=\"s3\\nbackup\"} 0\n"
+        ));
+    }
+}
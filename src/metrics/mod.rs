@@ -0,0 +1,11 @@
+#[allow(clippy::module_inception)]
+mod metrics;
+mod server;
+
+pub use metrics::inc_storage_retry;
+pub use metrics::register_db;
+pub use metrics::register_replicate;
+pub use metrics::unregister_db;
+pub use metrics::DbStats;
+pub use metrics::ReplicateStats;
+pub use server::start_metrics_server;
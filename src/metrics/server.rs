@@ -0,0 +1,70 @@
+use log::error;
+use log::info;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+
+use super::metrics::render;
+use crate::config::MetricsConfig;
+use crate::error::Result;
+
+const METRICS_PATH: &str = "/metrics";
+const MAX_REQUEST_SIZE: usize = 8192;
+
+// start a http server serving metrics in prometheus text format at `/metrics`.
+pub async fn start_metrics_server(config: &MetricsConfig) -> Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(&config.addr).await?;
+    info!("metrics server listening on {}", config.addr);
+
+    let handle = tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("metrics server accept error: {:?}", e);
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream).await {
+                    error!("metrics server handle connection error: {:?}", e);
+                }
+            });
+        }
+    });
+
+    Ok(handle)
+}
+
+async fn handle_connection(mut stream: TcpStream) -> Result<()> {
+    // read until the end of request headers, request body is ignored.
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.split_whitespace();
+    let response = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(METRICS_PATH)) => {
+            let body = render();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::future::Future;
+
+use log::info;
+use tokio::runtime::Builder;
+use tokio::task::JoinHandle;
+
+use crate::error::Result;
+
+// A dedicated tokio runtime, so heavy io and compression tasks running on it
+// cannot starve tasks on the main runtime.
+pub struct Runtime {
+    name: String,
+
+    // always Some until dropped
+    runtime: Option<tokio::runtime::Runtime>,
+}
+
+impl Runtime {
+    // create a multi thread runtime, use tokio default worker threads number
+    // (number of cpu cores) if `worker_threads` is 0.
+    pub fn with_worker_threads(name: &str, worker_threads: usize) -> Result<Self> {
+        let mut builder = Builder::new_multi_thread();
+        if worker_threads > 0 {
+            builder.worker_threads(worker_threads);
+        }
+        let runtime = builder
+            .thread_name(format!("replited-{}", name))
+            .enable_all()
+            .build()?;
+
+        info!(
+            "create runtime {} with worker threads: {}",
+            name, worker_threads
+        );
+        Ok(Self {
+            name: name.to_string(),
+            runtime: Some(runtime),
+        })
+    }
+
+    // runs the future to completion on the runtime, MUST NOT be called in
+    // async context.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.as_ref().unwrap().block_on(future)
+    }
+
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime.as_ref().unwrap().spawn(future)
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        // dropping a runtime in async context panics, shutdown in background instead.
+        if let Some(runtime) = self.runtime.take() {
+            info!("shutdown runtime {}", self.name);
+            runtime.shutdown_background();
+        }
+    }
+}
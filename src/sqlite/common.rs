@@ -1,16 +1,22 @@
 use std::fs::OpenOptions;
-use std::os::unix::fs::FileExt;
-use std::os::unix::fs::MetadataExt;
 
+use crate::base::is_power_of_two;
+use crate::base::read_at;
 use crate::error::Error;
 use crate::error::Result;
 
 pub const WAL_FRAME_HEADER_SIZE: u64 = 24;
 pub const WAL_HEADER_SIZE: u64 = 32;
+// max page size of sqlite db.
+pub const MAX_PAGE_SIZE: u64 = 65536;
 
 static WAL_HEADER_CHECKSUM_OFFSET: u64 = 24;
 static WAL_FRAME_HEADER_CHECKSUM_OFFSET: u64 = 16;
 
+pub const DB_HEADER_SIZE: usize = 100;
+static DB_HEADER_MAGIC: &[u8] = b"SQLite format 3\0";
+static DB_HEADER_PAGE_SIZE_OFFSET: usize = 16;
+
 pub const WAL_HEADER_BIG_ENDIAN_MAGIC: [u8; 4] = [0x37, 0x7f, 0x06, 0x83];
 pub const WAL_HEADER_LITTLE_ENDIAN_MAGIC: [u8; 4] = [0x37, 0x7f, 0x06, 0x82];
 
@@ -39,6 +45,25 @@ impl CheckpointMode {
     }
 }
 
+// result of `PRAGMA wal_checkpoint`.
+// See: https://www.sqlite.org/pragma.html#pragma_wal_checkpoint
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CheckpointResult {
+    // true if the checkpoint was blocked from completing.
+    pub busy: bool,
+    // number of modified pages written to the WAL file.
+    pub log: i64,
+    // number of pages in the WAL file moved back into the db file.
+    pub checkpointed: i64,
+}
+
+impl CheckpointResult {
+    // returns true if every page in the WAL has been moved back into the db file.
+    pub fn is_complete(&self) -> bool {
+        !self.busy && self.log == self.checkpointed
+    }
+}
+
 // implementation of sqlite check algorithm
 pub fn checksum(data: &[u8], s1: u32, s2: u32, is_big_endian: bool) -> (u32, u32) {
     let mut i = 0;
@@ -69,18 +94,27 @@ pub fn checksum(data: &[u8], s1: u32, s2: u32, is_big_endian: bool) -> (u32, u32
 }
 
 pub fn read_last_checksum(file_name: &str, page_size: u64) -> Result<(u32, u32)> {
+    let fsize = OpenOptions::new()
+        .read(true)
+        .open(file_name)?
+        .metadata()?
+        .len();
+
+    read_checksum_before(file_name, page_size, align_frame(page_size, fsize))
+}
+
+// returns the checksum of the frame ending at frame-aligned `offset`, or the
+// wal header checksum if there is no frame before offset.
+pub fn read_checksum_before(file_name: &str, page_size: u64, offset: u64) -> Result<(u32, u32)> {
     let file = OpenOptions::new().read(true).open(file_name)?;
-    let metadata = file.metadata()?;
-    let fsize = metadata.size();
-    let offset = if fsize > WAL_HEADER_SIZE {
-        let sz = align_frame(page_size, fsize);
-        sz - page_size - WAL_FRAME_HEADER_SIZE + WAL_FRAME_HEADER_CHECKSUM_OFFSET
+    let offset = if offset > WAL_HEADER_SIZE {
+        offset - page_size - WAL_FRAME_HEADER_SIZE + WAL_FRAME_HEADER_CHECKSUM_OFFSET
     } else {
         WAL_HEADER_CHECKSUM_OFFSET
     };
 
     let mut buf = [0u8; 8];
-    let n = file.read_at(&mut buf, offset)?;
+    let n = read_at(&file, &mut buf, offset)?;
     if n != buf.len() {
         return Err(Error::UnexpectedEofError(
             "UnexpectedEOFError when read last checksum".to_string(),
@@ -108,6 +142,24 @@ pub fn align_frame(page_size: u64, offset: u64) -> u64 {
     (frame_num * frame_size) as u64 + WAL_HEADER_SIZE
 }
 
+// returns page size from the header of sqlite db file data.
+// See: https://www.sqlite.org/fileformat.html#the_database_header
+pub fn db_page_size(data: &[u8]) -> Result<u64> {
+    if data.len() < DB_HEADER_SIZE || &data[..DB_HEADER_MAGIC.len()] != DB_HEADER_MAGIC {
+        return Err(Error::SqliteInvalidDbHeaderError("Invalid db header"));
+    }
+
+    let offset = DB_HEADER_PAGE_SIZE_OFFSET;
+    let page_size = u16::from_be_bytes([data[offset], data[offset + 1]]) as u64;
+    // value 1 represents a page size of 65536.
+    let page_size = if page_size == 1 { 65536 } else { page_size };
+    if !is_power_of_two(page_size) || page_size < 512 {
+        return Err(Error::SqliteInvalidDbHeaderError("Invalid page size"));
+    }
+
+    Ok(page_size)
+}
+
 pub(crate) fn from_be_bytes_at(data: &[u8], offset: usize) -> Result<u32> {
     let p = &data[offset..offset + 4];
     Ok(u32::from_be_bytes(p.try_into()?))
@@ -115,7 +167,13 @@ pub(crate) fn from_be_bytes_at(data: &[u8], offset: usize) -> Result<u32> {
 
 #[cfg(test)]
 mod tests {
+    use std::io::Write;
+
+    use tempfile::NamedTempFile;
+
     use super::align_frame;
+    use super::read_checksum_before;
+    use super::read_last_checksum;
     use crate::error::Result;
 
     #[test]
@@ -124,4 +182,30 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_read_checksum_before() -> Result<()> {
+        let page_size = 1024;
+        // wal header with checksum (1, 2), then one frame with checksum (3, 4).
+        let mut data = vec![0u8; 32 + 24 + page_size];
+        data[24..28].copy_from_slice(&1u32.to_be_bytes());
+        data[28..32].copy_from_slice(&2u32.to_be_bytes());
+        data[48..52].copy_from_slice(&3u32.to_be_bytes());
+        data[52..56].copy_from_slice(&4u32.to_be_bytes());
+
+        let mut file = NamedTempFile::new()?;
+        file.write_all(&data)?;
+        let file_name = file.path().to_str().unwrap();
+
+        let page_size = page_size as u64;
+        assert_eq!((1, 2), read_checksum_before(file_name, page_size, 0)?);
+        assert_eq!((1, 2), read_checksum_before(file_name, page_size, 32)?);
+        assert_eq!(
+            (3, 4),
+            read_checksum_before(file_name, page_size, data.len() as u64)?
+        );
+        assert_eq!((3, 4), read_last_checksum(file_name, page_size)?);
+
+        Ok(())
+    }
 }
@@ -4,9 +4,14 @@ mod wal_header;
 
 pub use common::align_frame;
 pub use common::checksum;
+pub use common::db_page_size;
 pub(crate) use common::from_be_bytes_at;
+pub use common::read_checksum_before;
 pub use common::read_last_checksum;
 pub use common::CheckpointMode;
+pub use common::CheckpointResult;
+pub use common::DB_HEADER_SIZE;
+pub use common::MAX_PAGE_SIZE;
 pub use common::WAL_FRAME_HEADER_SIZE;
 pub use common::WAL_HEADER_BIG_ENDIAN_MAGIC;
 pub use common::WAL_HEADER_LITTLE_ENDIAN_MAGIC;
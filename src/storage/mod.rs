@@ -1,9 +1,17 @@
 mod operator;
+mod rate_limiter;
+mod s3_restore;
 mod storage_client;
 
 pub(crate) use operator::init_operator;
+pub(crate) use operator::new_storage_http_client_builder;
+pub(crate) use operator::with_retry;
+pub(crate) use rate_limiter::RateLimiter;
+pub use s3_restore::RestoreStatus;
+pub(crate) use s3_restore::S3RestoreClient;
+pub use storage_client::OrphanedWalSegments;
+pub use storage_client::RestorableWindow;
 pub use storage_client::RestoreInfo;
 pub use storage_client::RestoreWalSegments;
 pub use storage_client::SnapshotInfo;
 pub use storage_client::StorageClient;
-pub use storage_client::WalSegmentInfo;
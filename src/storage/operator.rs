@@ -1,34 +1,76 @@
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::LazyLock;
 use std::time::Duration;
 
 use log::warn;
+use opendal::layers::RetryInterceptor;
+use opendal::layers::RetryLayer;
 use opendal::raw::HttpClient;
 use opendal::services;
+use opendal::services::S3Config;
 use opendal::Builder;
+use opendal::Configurator;
 use opendal::Operator;
+use parking_lot::Mutex;
+use reqwest::Certificate;
+use reqwest::NoProxy;
+use reqwest::Proxy;
 use reqwest_hickory_resolver::HickoryResolver;
 
+use crate::config::ProxyConfig;
 use crate::config::StorageAzblobConfig;
+use crate::config::StorageB2Config;
+use crate::config::StorageConfig;
+use crate::config::StorageCosConfig;
 use crate::config::StorageFsConfig;
 use crate::config::StorageFtpConfig;
 use crate::config::StorageGcsConfig;
+use crate::config::StorageMemoryConfig;
+use crate::config::StorageOssConfig;
 use crate::config::StorageParams;
 use crate::config::StorageS3Config;
+use crate::config::StorageSftpConfig;
+use crate::error::Error;
 use crate::error::Result;
+use crate::metrics::inc_storage_retry;
 
 /// The global dns resolver for opendal.
 static GLOBAL_HICKORY_RESOLVER: LazyLock<Arc<HickoryResolver>> =
     LazyLock::new(|| Arc::new(HickoryResolver::default()));
 
-pub fn init_operator(cfg: &StorageParams) -> Result<Operator> {
-    let op = match cfg {
-        StorageParams::Azb(cfg) => build_operator(init_azblob_operator(cfg)?)?,
-        StorageParams::Fs(cfg) => build_operator(init_fs_operator(cfg)?)?,
-        StorageParams::Ftp(cfg) => build_operator(init_ftp_operator(cfg)?)?,
-        StorageParams::Gcs(cfg) => build_operator(init_gcs_operator(cfg)?)?,
-        StorageParams::S3(cfg) => build_operator(init_s3_operator(cfg)?)?,
+// memory operators by root, so that clients of replicates with the same root
+// share objects like clients of other backends do.
+static MEMORY_OPERATORS: LazyLock<Mutex<HashMap<String, Operator>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn init_operator(cfg: &StorageConfig) -> Result<Operator> {
+    let op = match &cfg.params {
+        StorageParams::Azb(params) => {
+            build_operator(init_azblob_operator(params, new_storage_http_client(cfg)?)?)?
+        }
+        StorageParams::B2(params) => {
+            build_operator(init_b2_operator(params, new_storage_http_client(cfg)?)?)?
+        }
+        StorageParams::Cos(params) => {
+            build_operator(init_cos_operator(params, new_storage_http_client(cfg)?)?)?
+        }
+        StorageParams::Fs(params) => build_operator(init_fs_operator(params)?)?,
+        StorageParams::Ftp(params) => build_operator(init_ftp_operator(params)?)?,
+        StorageParams::Gcs(params) => {
+            build_operator(init_gcs_operator(params, new_storage_http_client(cfg)?)?)?
+        }
+        StorageParams::Memory(params) => init_memory_operator(params)?,
+        StorageParams::Oss(params) => {
+            build_operator(init_oss_operator(params, new_storage_http_client(cfg)?)?)?
+        }
+        StorageParams::S3(params) => {
+            build_operator(init_s3_operator(params, new_storage_http_client(cfg)?)?)?
+        }
+        StorageParams::Sftp(params) => build_operator(init_sftp_operator(params)?)?,
     };
 
     Ok(op)
@@ -40,8 +82,47 @@ pub fn build_operator<B: Builder>(builder: B) -> Result<Operator> {
     Ok(op.finish())
 }
 
+// logs and counts retries of storage operations.
+struct StorageRetryInterceptor {
+    db: String,
+    replicate: String,
+}
+
+impl RetryInterceptor for StorageRetryInterceptor {
+    fn intercept(&self, err: &opendal::Error, dur: Duration) {
+        warn!(
+            "db {} replicate {} retry storage operation after {:?}, error: {}",
+            self.db, self.replicate, dur, err
+        );
+        inc_storage_retry(&self.db, &self.replicate);
+    }
+}
+
+/// with_retry will retry operations of `op` failed with temporary errors
+/// (e.g. 429, 503, connection reset) with jittered exponential backoff.
+pub fn with_retry(op: Operator, cfg: &StorageConfig, db: &str) -> Operator {
+    if cfg.max_retries == 0 {
+        return op;
+    }
+
+    op.layer(
+        RetryLayer::new()
+            .with_max_times(cfg.max_retries)
+            .with_min_delay(Duration::from_millis(cfg.base_delay_ms))
+            .with_max_delay(Duration::from_millis(cfg.max_delay_ms))
+            .with_jitter()
+            .with_notify(StorageRetryInterceptor {
+                db: db.to_string(),
+                replicate: cfg.name.clone(),
+            }),
+    )
+}
+
 /// init_azblob_operator will init an opendal azblob operator.
-pub fn init_azblob_operator(cfg: &StorageAzblobConfig) -> Result<impl Builder> {
+pub fn init_azblob_operator(
+    cfg: &StorageAzblobConfig,
+    http_client: HttpClient,
+) -> Result<impl Builder> {
     let builder = services::Azblob::default()
         // Endpoint
         .endpoint(&cfg.endpoint)
@@ -52,19 +133,58 @@ pub fn init_azblob_operator(cfg: &StorageAzblobConfig) -> Result<impl Builder> {
         // Credential
         .account_name(&cfg.account_name)
         .account_key(&cfg.account_key)
-        .http_client(new_storage_http_client()?);
+        .http_client(http_client);
+
+    Ok(builder)
+}
+
+/// init_b2_operator will init a opendal b2 operator.
+fn init_b2_operator(cfg: &StorageB2Config, http_client: HttpClient) -> Result<impl Builder> {
+    let builder = services::B2::default()
+        .bucket(&cfg.bucket)
+        .bucket_id(&cfg.bucket_id)
+        .root(&cfg.root)
+        .application_key_id(&cfg.application_key_id)
+        .application_key(&cfg.application_key)
+        .http_client(http_client);
+
+    Ok(builder)
+}
+
+/// init_cos_operator will init a opendal cos operator.
+fn init_cos_operator(cfg: &StorageCosConfig, http_client: HttpClient) -> Result<impl Builder> {
+    let builder = services::Cos::default()
+        .endpoint(&cfg.endpoint)
+        .bucket(&cfg.bucket)
+        .root(&cfg.root)
+        .secret_id(&cfg.secret_id)
+        .secret_key(&cfg.secret_key)
+        .http_client(http_client);
 
     Ok(builder)
 }
 
 /// init_gcs_operator will init a opendal gcs operator.
-fn init_gcs_operator(cfg: &StorageGcsConfig) -> Result<impl Builder> {
+fn init_gcs_operator(cfg: &StorageGcsConfig, http_client: HttpClient) -> Result<impl Builder> {
     let builder = services::Gcs::default()
         .endpoint(&cfg.endpoint)
         .bucket(&cfg.bucket)
         .root(&cfg.root)
         .credential(&cfg.credential)
-        .http_client(new_storage_http_client()?);
+        .http_client(http_client);
+
+    Ok(builder)
+}
+
+/// init_oss_operator will init a opendal oss operator.
+fn init_oss_operator(cfg: &StorageOssConfig, http_client: HttpClient) -> Result<impl Builder> {
+    let builder = services::Oss::default()
+        .endpoint(&cfg.endpoint)
+        .bucket(&cfg.bucket)
+        .root(&cfg.root)
+        .access_key_id(&cfg.access_key_id)
+        .access_key_secret(&cfg.access_key_secret)
+        .http_client(http_client);
 
     Ok(builder)
 }
@@ -82,6 +202,18 @@ fn init_fs_operator(cfg: &StorageFsConfig) -> Result<impl Builder> {
     Ok(builder)
 }
 
+/// init_memory_operator will return the opendal memory operator of root.
+fn init_memory_operator(cfg: &StorageMemoryConfig) -> Result<Operator> {
+    let mut operators = MEMORY_OPERATORS.lock();
+    if let Some(op) = operators.get(&cfg.root) {
+        return Ok(op.clone());
+    }
+
+    let op = build_operator(services::Memory::default().root(&cfg.root))?;
+    operators.insert(cfg.root.clone(), op.clone());
+    Ok(op)
+}
+
 /// init_ftp_operator will init a opendal ftp operator.
 fn init_ftp_operator(cfg: &StorageFtpConfig) -> Result<impl Builder> {
     let builder = services::Ftp::default()
@@ -93,76 +225,218 @@ fn init_ftp_operator(cfg: &StorageFtpConfig) -> Result<impl Builder> {
     Ok(builder)
 }
 
+/// init_sftp_operator will init a opendal sftp operator.
+fn init_sftp_operator(cfg: &StorageSftpConfig) -> Result<impl Builder> {
+    let mut builder = services::Sftp::default()
+        .endpoint(&cfg.endpoint)
+        .root(&cfg.root)
+        .user(&cfg.user)
+        .known_hosts_strategy(&cfg.known_hosts_strategy);
+
+    if !cfg.key.is_empty() {
+        builder = builder.key(&cfg.key);
+    }
+
+    Ok(builder)
+}
+
 /// Create a new http client for storage.
-fn new_storage_http_client() -> Result<HttpClient> {
+fn new_storage_http_client(cfg: &StorageConfig) -> Result<HttpClient> {
+    Ok(HttpClient::build(new_storage_http_client_builder(cfg)?)?)
+}
+
+/// Create a new reqwest client builder for storage, shared by opendal and the
+/// requests issued directly like s3 `RestoreObject`.
+pub fn new_storage_http_client_builder(cfg: &StorageConfig) -> Result<reqwest::ClientBuilder> {
     let mut builder = reqwest::ClientBuilder::new();
 
-    // Disable http2 for better performance.
-    builder = builder.http1_only();
+    // Disable http2 for better performance, unless it is enabled in config,
+    // e.g. for storages where multiplexing the many small requests helps.
+    if !cfg.http.http2 {
+        builder = builder.http1_only();
+    }
 
     // Set dns resolver.
     builder = builder.dns_resolver(GLOBAL_HICKORY_RESOLVER.clone());
 
     // Pool max idle per host controls connection pool size.
     // Default to no limit, set to `0` for disable it.
-    let pool_max_idle_per_host = env::var("_LITESYNC_INTERNAL_POOL_MAX_IDLE_PER_HOST")
-        .ok()
-        .and_then(|v| v.parse::<usize>().ok())
+    let pool_max_idle_per_host = cfg
+        .http
+        .pool_max_idle_per_host
+        .or_else(|| env_value("_LITESYNC_INTERNAL_POOL_MAX_IDLE_PER_HOST"))
         .unwrap_or(usize::MAX);
     builder = builder.pool_max_idle_per_host(pool_max_idle_per_host);
 
     // Connect timeout default to 30s.
-    let connect_timeout = env::var("_LITESYNC_INTERNAL_CONNECT_TIMEOUT")
-        .ok()
-        .and_then(|v| v.parse::<u64>().ok())
+    let connect_timeout = cfg
+        .http
+        .connect_timeout_secs
+        .or_else(|| env_value("_LITESYNC_INTERNAL_CONNECT_TIMEOUT"))
         .unwrap_or(30);
     builder = builder.connect_timeout(Duration::from_secs(connect_timeout));
 
     // Enable TCP keepalive if set.
-    if let Ok(v) = env::var("_LITESYNC_INTERNAL_TCP_KEEPALIVE") {
-        if let Ok(v) = v.parse::<u64>() {
-            builder = builder.tcp_keepalive(Duration::from_secs(v));
+    let tcp_keepalive = cfg
+        .http
+        .tcp_keepalive_secs
+        .or_else(|| env_value("_LITESYNC_INTERNAL_TCP_KEEPALIVE"));
+    if let Some(v) = tcp_keepalive {
+        builder = builder.tcp_keepalive(Duration::from_secs(v));
+    }
+
+    // Proxy, reqwest uses the proxy envs unless proxies are set explicitly.
+    if cfg.proxy.is_enabled() {
+        builder = with_proxy(builder, &cfg.proxy)?;
+    }
+
+    // Extra trusted ca certificates.
+    if !cfg.ca_cert_path.is_empty() {
+        let pem = match fs::read(&cfg.ca_cert_path) {
+            Ok(pem) => pem,
+            Err(e) => {
+                return Err(Error::InvalidConfig(format!(
+                    "read ca_cert_path {} of replicate {} fail: {:?}",
+                    cfg.ca_cert_path, cfg.name, e
+                )));
+            }
+        };
+        builder = builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+
+    // Certificate verification, only disabled with `allow_insecure`.
+    if cfg.allow_insecure && cfg.danger_accept_invalid_certs {
+        warn!(
+            "CERTIFICATE VERIFICATION IS DISABLED for replicate {}, connections to it are open to man-in-the-middle attacks!",
+            cfg.name
+        );
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    Ok(builder)
+}
+
+// parses the value of env `name`, None if it is not set or invalid.
+fn env_value<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|v| v.parse::<T>().ok())
+}
+
+fn with_proxy(
+    mut builder: reqwest::ClientBuilder,
+    cfg: &ProxyConfig,
+) -> Result<reqwest::ClientBuilder> {
+    let no_proxy = || {
+        if cfg.no_proxy.is_empty() {
+            NoProxy::from_env()
+        } else {
+            NoProxy::from_string(&cfg.no_proxy)
         }
+    };
+
+    if !cfg.http_proxy.is_empty() {
+        builder = builder.proxy(Proxy::http(&cfg.http_proxy)?.no_proxy(no_proxy()));
+    }
+    if !cfg.https_proxy.is_empty() {
+        builder = builder.proxy(Proxy::https(&cfg.https_proxy)?.no_proxy(no_proxy()));
     }
 
-    Ok(HttpClient::build(builder)?)
+    Ok(builder)
 }
 
 /// init_s3_operator will init a opendal s3 operator with input s3 config.
-fn init_s3_operator(cfg: &StorageS3Config) -> Result<impl Builder> {
-    let mut builder = services::S3::default()
-        // Endpoint.
-        .endpoint(&cfg.endpoint)
-        // Bucket.
-        .bucket(&cfg.bucket);
+fn init_s3_operator(cfg: &StorageS3Config, http_client: HttpClient) -> Result<impl Builder> {
+    Ok(init_s3_config(cfg).into_builder().http_client(http_client))
+}
+
+fn init_s3_config(cfg: &StorageS3Config) -> S3Config {
+    let mut config = S3Config::default();
+    // Endpoint.
+    config.endpoint = non_empty(cfg.endpoint.trim_end_matches('/'));
+    // Bucket.
+    config.bucket = cfg.bucket.clone();
 
     // Region
     if !cfg.region.is_empty() {
-        builder = builder.region(&cfg.region);
+        config.region = Some(cfg.region.clone());
     } else if let Ok(region) = env::var("AWS_REGION") {
         // Try to load region from env if not set.
-        builder = builder.region(&region);
+        config.region = Some(region);
     } else {
         // FIXME: we should return error here but keep those logic for compatibility.
         warn!(
             "Region is not specified for S3 storage, we will attempt to load it from profiles. If it is still not found, we will use the default region of `us-east-1`."
         );
-        builder = builder.region("us-east-1");
+        config.region = Some("us-east-1".to_string());
     }
 
     // Credential.
-    builder = builder
-        .access_key_id(&cfg.access_key_id)
-        .secret_access_key(&cfg.secret_access_key)
-        // It's safe to allow anonymous since opendal will perform the check first.
-        .allow_anonymous()
-        // Root.
-        .root(&cfg.root);
+    config.access_key_id = non_empty(&cfg.access_key_id);
+    config.secret_access_key = non_empty(&cfg.secret_access_key);
+    config.session_token = non_empty(&cfg.security_token);
+    // It's safe to allow anonymous since opendal will perform the check first.
+    config.allow_anonymous = true;
+    // Root.
+    config.root = non_empty(&cfg.root);
 
-    // Disable credential loader
-    builder = builder.disable_config_load().disable_ec2_metadata();
+    // Assume role.
+    if !cfg.role_arn.is_empty() {
+        config.role_arn = Some(cfg.role_arn.clone());
+        config.external_id = non_empty(&cfg.external_id);
+    }
 
-    builder = builder.http_client(new_storage_http_client()?);
+    // Server side encryption.
+    config.server_side_encryption = non_empty(&cfg.server_side_encryption);
+    config.server_side_encryption_aws_kms_key_id =
+        non_empty(&cfg.server_side_encryption_aws_kms_key_id);
 
-    Ok(builder)
+    // Storage class.
+    config.default_storage_class = non_empty(&cfg.storage_class);
+
+    // Disable credential loader, unless credentials are allowed to be loaded
+    // from env, profiles and instance metadata.
+    config.disable_config_load = !cfg.use_credential_chain;
+    config.disable_ec2_metadata = !cfg.use_credential_chain;
+
+    config
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::init_s3_config;
+    use crate::config::StorageConfig;
+    use crate::config::StorageParams;
+
+    #[test]
+    fn test_s3_credential_chain() {
+        // access_key_id and secret_access_key are not required with the
+        // credential chain.
+        let config: StorageConfig = toml::from_str(
+            "name = \"s3\"\nparams.type = \"S3\"\nparams.endpoint = \"https://s3.amazonaws.com\"\nparams.region = \"us-east-1\"\nparams.bucket = \"bucket\"\nparams.root = \"/replited\"\nparams.use_credential_chain = true",
+        )
+        .unwrap();
+        let mut s3 = match config.params {
+            StorageParams::S3(s3) => s3,
+            _ => unreachable!(),
+        };
+        assert!(s3.access_key_id.is_empty());
+        assert!(s3.secret_access_key.is_empty());
+
+        let config = init_s3_config(&s3);
+        assert!(config.access_key_id.is_none());
+        assert!(!config.disable_config_load);
+        assert!(!config.disable_ec2_metadata);
+
+        s3.use_credential_chain = false;
+        let config = init_s3_config(&s3);
+        assert!(config.disable_config_load);
+        assert!(config.disable_ec2_metadata);
+    }
 }
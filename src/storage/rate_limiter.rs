@@ -0,0 +1,82 @@
+use std::time::Duration;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+
+// token bucket limiting the bytes uploaded per second, the bucket holds at
+// most one second of bytes. A chunk larger than the available bytes is let
+// through after waiting for the missing bytes, so chunks of any size work.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    // may be negative when a chunk is let through on credit.
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            state: Mutex::new(BucketState {
+                available: bytes_per_sec as f64,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    // waits until `bytes` can be uploaded.
+    pub async fn acquire(&self, bytes: usize) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // takes `bytes` from the bucket, returns how long to wait before using them.
+    fn reserve(&self, bytes: usize, now: Instant) -> Duration {
+        let mut state = self.state.lock();
+        let elapsed = now.saturating_duration_since(state.last_refill);
+        state.available =
+            (state.available + elapsed.as_secs_f64() * self.bytes_per_sec).min(self.bytes_per_sec);
+        state.last_refill = now;
+
+        state.available -= bytes as f64;
+        if state.available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.available / self.bytes_per_sec)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::RateLimiter;
+
+    #[test]
+    fn test_rate_limiter_reserve() {
+        let limiter = RateLimiter::new(1000);
+        let now = Instant::now();
+
+        // the bucket starts full.
+        assert_eq!(limiter.reserve(1000, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(500, now), Duration::from_millis(500));
+
+        // the debt is paid off after 500ms, and the bucket refills afterwards.
+        let now = now + Duration::from_millis(1000);
+        assert_eq!(limiter.reserve(500, now), Duration::ZERO);
+
+        // chunks larger than the bucket wait for the missing bytes.
+        let now = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(3000, now), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,254 @@
+use std::env;
+
+use log::debug;
+use reqsign::AwsAssumeRoleLoader;
+use reqsign::AwsConfig;
+use reqsign::AwsCredential;
+use reqsign::AwsCredentialLoad;
+use reqsign::AwsDefaultLoader;
+use reqsign::AwsV4Signer;
+use reqwest::StatusCode;
+
+use crate::config::StorageS3Config;
+use crate::error::Error;
+use crate::error::Result;
+
+// status of the restore request of an archived object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RestoreStatus {
+    // restore request has been initiated.
+    Initiated,
+    // a previous restore request is still in progress.
+    InProgress,
+    // object has been restored and is readable now.
+    Restored,
+    // object is not in an archive storage class.
+    NotArchived,
+}
+
+// opendal has no api of s3 `RestoreObject` and `CreateBucket`, so issue the
+// requests directly.
+pub struct S3RestoreClient {
+    client: reqwest::Client,
+    signer: AwsV4Signer,
+    credential: AwsCredential,
+    // loads credentials from the aws credential chain or by assuming role.
+    loader: Option<Box<dyn AwsCredentialLoad>>,
+    region: String,
+    endpoint: String,
+    bucket: String,
+    root: String,
+}
+
+impl S3RestoreClient {
+    pub fn new(cfg: &StorageS3Config, client: reqwest::Client) -> Result<Self> {
+        // use the same region as `init_s3_operator`.
+        let region = if !cfg.region.is_empty() {
+            cfg.region.clone()
+        } else {
+            env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_string())
+        };
+
+        let session_token = if cfg.security_token.is_empty() {
+            None
+        } else {
+            Some(cfg.security_token.clone())
+        };
+        let loader: Option<Box<dyn AwsCredentialLoad>> =
+            if cfg.use_credential_chain || !cfg.role_arn.is_empty() {
+                let mut config = if cfg.use_credential_chain {
+                    AwsConfig::default().from_profile().from_env()
+                } else {
+                    AwsConfig {
+                        ec2_metadata_disabled: true,
+                        ..Default::default()
+                    }
+                };
+                config.region = Some(region.clone());
+                // keys in config take precedence over the chain, like opendal.
+                if !cfg.access_key_id.is_empty() {
+                    config.access_key_id = Some(cfg.access_key_id.clone());
+                    config.secret_access_key = Some(cfg.secret_access_key.clone());
+                    config.session_token = session_token.clone();
+                }
+
+                let default_loader = AwsDefaultLoader::new(client.clone(), config.clone());
+                if cfg.role_arn.is_empty() {
+                    Some(Box::new(default_loader))
+                } else {
+                    config.role_arn = Some(cfg.role_arn.clone());
+                    if !cfg.external_id.is_empty() {
+                        config.external_id = Some(cfg.external_id.clone());
+                    }
+                    Some(Box::new(AwsAssumeRoleLoader::new(
+                        client.clone(),
+                        config,
+                        Box::new(default_loader),
+                    )?))
+                }
+            } else {
+                None
+            };
+
+        Ok(Self {
+            client,
+            signer: AwsV4Signer::new("s3", &region),
+            credential: AwsCredential {
+                access_key_id: cfg.access_key_id.clone(),
+                secret_access_key: cfg.secret_access_key.clone(),
+                session_token,
+                ..Default::default()
+            },
+            loader,
+            region,
+            endpoint: cfg.endpoint.trim_end_matches('/').to_string(),
+            bucket: cfg.bucket.clone(),
+            root: cfg.root.trim_matches('/').to_string(),
+        })
+    }
+
+    // key of the object in bucket, `path` is relative to root like opendal.
+    fn object_key(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        if self.root.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.root, path)
+        }
+    }
+
+    // static credential in config, or the one loaded from the credential chain.
+    async fn load_credential(&self) -> Result<AwsCredential> {
+        let loader = match &self.loader {
+            Some(loader) => loader,
+            None => return Ok(self.credential.clone()),
+        };
+
+        match loader.load_credential(self.client.clone()).await? {
+            Some(credential) => Ok(credential),
+            None => Err(Error::HttpError(
+                "no credential found to sign the request".to_string(),
+            )),
+        }
+    }
+
+    // request to restore object of `path` for `days` days with retrieval `tier`.
+    pub async fn restore_object(&self, path: &str, days: u32, tier: &str) -> Result<RestoreStatus> {
+        let key = self.object_key(path);
+        let url = format!("{}/{}/{}?restore", self.endpoint, self.bucket, key);
+        let body = format!(
+            "<RestoreRequest><Days>{}</Days><GlacierJobParameters><Tier>{}</Tier></GlacierJobParameters></RestoreRequest>",
+            days, tier
+        );
+
+        let mut req = self
+            .client
+            .post(&url)
+            .header("content-type", "application/xml")
+            .body(body)
+            .build()?;
+        let credential = self.load_credential().await?;
+        self.signer.sign(&mut req, &credential)?;
+
+        let resp = self.client.execute(req).await?;
+        let status = resp.status();
+        debug!("restore object {} status: {}", key, status);
+        if status == StatusCode::ACCEPTED {
+            return Ok(RestoreStatus::Initiated);
+        }
+        if status == StatusCode::OK {
+            return Ok(RestoreStatus::Restored);
+        }
+
+        let text = resp.text().await?;
+        if status == StatusCode::CONFLICT && text.contains("RestoreAlreadyInProgress") {
+            return Ok(RestoreStatus::InProgress);
+        }
+        if status == StatusCode::FORBIDDEN && text.contains("ObjectAlreadyInActiveTierError") {
+            return Ok(RestoreStatus::NotArchived);
+        }
+
+        Err(Error::HttpError(format!(
+            "restore object {} fail, status: {}, response: {}",
+            key, status, text
+        )))
+    }
+
+    // creates the bucket if it does not exist, returns if it has been created.
+    pub async fn ensure_bucket(&self) -> Result<bool> {
+        let url = format!("{}/{}", self.endpoint, self.bucket);
+        let credential = self.load_credential().await?;
+
+        let mut req = self.client.head(&url).build()?;
+        self.signer.sign(&mut req, &credential)?;
+        let status = self.client.execute(req).await?.status();
+        debug!("head bucket {} status: {}", self.bucket, status);
+        if status.is_success() {
+            return Ok(false);
+        }
+        if status != StatusCode::NOT_FOUND {
+            return Err(Error::HttpError(format!(
+                "head bucket {} fail, status: {}",
+                self.bucket, status
+            )));
+        }
+
+        // buckets of us-east-1 must be created without location constraint.
+        let body = if self.region == "us-east-1" {
+            String::new()
+        } else {
+            format!(
+                "<CreateBucketConfiguration><LocationConstraint>{}</LocationConstraint></CreateBucketConfiguration>",
+                self.region
+            )
+        };
+        let mut req = self
+            .client
+            .put(&url)
+            .header("content-type", "application/xml")
+            .body(body)
+            .build()?;
+        self.signer.sign(&mut req, &credential)?;
+
+        let resp = self.client.execute(req).await?;
+        let status = resp.status();
+        debug!("create bucket {} status: {}", self.bucket, status);
+        if status.is_success() {
+            return Ok(true);
+        }
+
+        // created by someone else meanwhile.
+        let text = resp.text().await?;
+        if status == StatusCode::CONFLICT && text.contains("BucketAlreadyOwnedByYou") {
+            return Ok(false);
+        }
+
+        Err(Error::HttpError(format!(
+            "create bucket {} fail, status: {}, response: {}",
+            self.bucket, status, text
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::S3RestoreClient;
+    use crate::config::StorageS3Config;
+    use crate::error::Result;
+
+    #[test]
+    fn test_object_key() -> Result<()> {
+        let mut cfg = StorageS3Config::default();
+        let client = S3RestoreClient::new(&cfg, reqwest::Client::new())?;
+        assert_eq!(client.object_key("db/generations/a"), "db/generations/a");
+
+        cfg.root = "/backup/".to_string();
+        let client = S3RestoreClient::new(&cfg, reqwest::Client::new())?;
+        assert_eq!(
+            client.object_key("/db/generations/a"),
+            "backup/db/generations/a"
+        );
+
+        Ok(())
+    }
+}
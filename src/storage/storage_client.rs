@@ -1,34 +1,113 @@
+use std::cmp::min;
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
 
 use chrono::DateTime;
 use chrono::Utc;
 use log::debug;
 use log::error;
+use log::info;
+use log::warn;
 use opendal::Metakey;
 use opendal::Operator;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde::Serialize;
+use sha2::Digest;
+use sha2::Sha256;
+use tempfile::tempfile;
+use tokio::io::AsyncReadExt;
 
 use super::init_operator;
+use super::new_storage_http_client_builder;
+use super::with_retry;
+use super::RateLimiter;
+use super::RestoreStatus;
+use super::S3RestoreClient;
+use crate::base::checksum_file;
+use crate::base::compress_buffer;
+use crate::base::decompressed_data;
+use crate::base::generate_data_key;
+use crate::base::generation_dir;
+use crate::base::heartbeat_file;
+use crate::base::is_checksum_file;
+use crate::base::is_encrypted;
+use crate::base::manifest_file;
 use crate::base::parent_dir;
 use crate::base::parse_snapshot_path;
 use crate::base::parse_wal_segment_path;
 use crate::base::path_base;
+use crate::base::remote_db_dir;
 use crate::base::remote_generations_dir;
 use crate::base::snapshot_file;
 use crate::base::snapshots_dir;
+use crate::base::status_file;
 use crate::base::walsegment_file;
 use crate::base::walsegments_dir;
+use crate::base::Cipher;
+use crate::base::Compression;
 use crate::base::Generation;
+use crate::base::KeyRing;
+use crate::base::ENCRYPTED_MAGIC;
+use crate::config::RestoreOptions;
+use crate::config::RetentionConfig;
 use crate::config::StorageConfig;
+use crate::config::StorageParams;
 use crate::database::WalGenerationPos;
 use crate::error::Error;
 use crate::error::Result;
 
+// s3 returns `InvalidObjectState` when reading an object that is archived.
+fn is_archived_error(e: &opendal::Error) -> bool {
+    e.kind() == opendal::ErrorKind::PermissionDenied && e.to_string().contains("InvalidObjectState")
+}
+
 #[derive(Debug, Clone)]
 pub struct StorageClient {
     operator: Operator,
     root: String,
-    db_path: String,
     db_name: String,
+    // remote directory of db, the db name under the prefix of the replicate.
+    db_dir: String,
+    delete_concurrency: usize,
+    compression: Compression,
+    compression_level: u32,
+    keys: Option<KeyRing>,
+    // data keys of generations by generation, loaded from their manifests or
+    // generated for new generations.
+    data_keys: Arc<Mutex<BTreeMap<String, DataKey>>>,
+    params: StorageParams,
+    // client of the requests issued directly, only s3 `RestoreObject` now.
+    http_client: Option<reqwest::Client>,
+    checksum: bool,
+    // limits the bytes of snapshots and wal segments uploaded per second.
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+// key encrypting the objects of a generation.
+#[derive(Debug, Clone)]
+struct DataKey {
+    cipher: Cipher,
+    // key id of the master key and the wrapped data key recorded in the
+    // manifest, None for generations encrypted by the master key directly.
+    wrapped: Option<(String, String)>,
+}
+
+// generations whose data key is wrapped by the current key after rekey.
+#[derive(Debug, Default)]
+pub struct RekeyResult {
+    pub rekeyed: Vec<Generation>,
+    // generations already wrapped by the current key.
+    pub unchanged: Vec<Generation>,
+    // generations without a data key, encrypted by the master key directly
+    // or not encrypted, which cannot be rekeyed.
+    pub skipped: Vec<Generation>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -37,36 +116,306 @@ pub struct SnapshotInfo {
     pub index: u64,
     pub size: u64,
     pub created_at: DateTime<Utc>,
+    pub compression: Compression,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct WalSegmentInfo {
     pub generation: Generation,
     pub index: u64,
     pub offset: u64,
     pub size: u64,
+    pub created_at: DateTime<Utc>,
+    pub compression: Compression,
+}
+
+#[derive(Debug, Serialize)]
+struct Heartbeat {
+    timestamp: DateTime<Utc>,
+    host: String,
+}
+
+// status object of a replicate, polled by monitoring systems.
+#[derive(Debug, Serialize)]
+struct RemoteStatus {
+    generation: String,
+    index: u64,
+    offset: u64,
+    last_sync_at: DateTime<Utc>,
+    host: String,
+    version: &'static str,
+}
+
+// wal segments of a generation which cannot be restored, as there is no
+// snapshot before them.
+#[derive(Debug)]
+pub struct OrphanedWalSegments {
+    pub generation: Generation,
+    // the generation has no snapshot at all, it is removed as a whole.
+    pub no_snapshot: bool,
+    pub wal_segments: Vec<WalSegmentInfo>,
+}
+
+// time range db can be restored to, from the oldest snapshot to the newest
+// snapshot or wal segment across generations.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestorableWindow {
+    pub earliest: DateTime<Utc>,
+    pub latest: DateTime<Utc>,
+}
+
+// manifest of a generation for external tools, written with the first
+// snapshot of the generation. Fields may be added in later versions, unknown
+// fields are ignored when reading it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerationManifest {
+    pub generation: String,
+    pub page_size: u64,
+    pub created_at: DateTime<Utc>,
+    // replited version which started the generation.
+    pub version: String,
+    // compression codec of snapshots and wal segments, kept as string so an
+    // unknown codec of a newer version can be reported.
+    pub compression: String,
+    // index of the first snapshot.
+    pub snapshot_index: u64,
+    // id of the master key wrapping the data key of an encrypted generation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+    // hex encoded data key encrypting snapshots and wal segments of the
+    // generation, wrapped by the master key of `key_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapped_key: Option<String>,
 }
 
-// restore wal_segments formats: vector<index, vector<offsets in order>>
-pub type RestoreWalSegments = Vec<(u64, Vec<u64>)>;
+// restore wal_segments formats: vector<index, vector<wal segments in offset order>>
+pub type RestoreWalSegments = Vec<(u64, Vec<WalSegmentInfo>)>;
 
 #[derive(Debug)]
 pub struct RestoreInfo {
     pub snapshot: SnapshotInfo,
 
     pub wal_segments: RestoreWalSegments,
+
+    // page size recorded in the manifest of the generation, if any.
+    pub page_size: Option<u64>,
+}
+
+// chunk size of streaming uploads and downloads, also the part size of
+// multipart uploads which must be at least 5MiB for s3.
+const STREAM_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+// associated data authenticated with encrypted snapshot.
+fn snapshot_aad(generation: &str, index: u64) -> String {
+    format!("snapshot/{}/{}", generation, index)
+}
+
+// associated data authenticated with encrypted wal segment.
+fn walsegment_aad(generation: &str, index: u64, offset: u64) -> String {
+    format!("wal/{}/{}/{}", generation, index, offset)
+}
+
+// chains wal segments after snapshot, grouped by index.
+// a gap is a missing index from the snapshot index upward, an index not
+// starting from offset 0, or an out of order offset. In best effort mode wal
+// segments are truncated at the first gap, otherwise the gap is returned as
+// error. if `timestamp` is set, stop at the first wal segment created after it.
+fn chain_wal_segments(
+    snapshot: &SnapshotInfo,
+    mut wal_segments: Vec<WalSegmentInfo>,
+    best_effort: bool,
+    timestamp: Option<DateTime<Utc>>,
+) -> Result<RestoreWalSegments> {
+    // sort wal segments first by index, then offset
+    wal_segments.sort_by_key(|wal_segment| (wal_segment.index, wal_segment.offset));
+
+    let mut restore_wal_segments: BTreeMap<u64, Vec<WalSegmentInfo>> = BTreeMap::new();
+
+    for wal_segment in wal_segments {
+        if wal_segment.index < snapshot.index {
+            continue;
+        }
+
+        if let Some(timestamp) = timestamp {
+            if wal_segment.created_at > timestamp {
+                debug!(
+                    "stop at wal segment {:?} created after {}",
+                    wal_segment, timestamp
+                );
+                break;
+            }
+        }
+
+        let gap = match restore_wal_segments.last_key_value() {
+            Some((index, segments)) if *index == wal_segment.index => {
+                if segments.last().unwrap().offset >= wal_segment.offset {
+                    Some("wal segment out of order".to_string())
+                } else {
+                    None
+                }
+            }
+            Some((index, _)) if *index + 1 != wal_segment.index => {
+                Some(format!("missing wal segment index {}", *index + 1))
+            }
+            None if wal_segment.index != snapshot.index => {
+                Some(format!("missing wal segment index {}", snapshot.index))
+            }
+            _ if wal_segment.offset != 0 => Some("missing initial wal segment".to_string()),
+            _ => None,
+        };
+
+        if let Some(gap) = gap {
+            let msg = format!(
+                "{}, generation: {:?}, index: {}, offset: {}",
+                gap,
+                snapshot.generation.as_str(),
+                wal_segment.index,
+                wal_segment.offset
+            );
+            if !best_effort {
+                error!("{}", msg);
+                return Err(Error::InvalidWalSegmentError(msg));
+            }
+            warn!("{}, truncate restore at the gap", msg);
+            break;
+        }
+
+        restore_wal_segments
+            .entry(wal_segment.index)
+            .or_default()
+            .push(wal_segment);
+    }
+
+    Ok(restore_wal_segments.into_iter().collect())
 }
 
 impl StorageClient {
     pub fn try_create(db_path: String, config: StorageConfig) -> Result<Self> {
+        let keys = match &config.encryption {
+            Some(encryption) => Some(KeyRing::try_create(
+                &encryption.load_key()?,
+                &encryption.load_previous_keys()?,
+            )?),
+            None => None,
+        };
+
+        let db_name = path_base(&db_path)?;
+        let db_dir = remote_db_dir(&config.remote_prefix()?, &db_name);
+        let operator = with_retry(init_operator(&config)?, &config, &db_name);
+        let http_client = match &config.params {
+            StorageParams::S3(_) => Some(new_storage_http_client_builder(&config)?.build()?),
+            _ => None,
+        };
+
         Ok(Self {
             root: config.params.root(),
-            operator: init_operator(&config.params)?,
-            db_name: path_base(&db_path)?,
-            db_path,
+            operator,
+            db_name,
+            db_dir,
+            delete_concurrency: config.delete_concurrency,
+            compression: config.compression,
+            compression_level: config.compression_level,
+            keys,
+            data_keys: Arc::new(Mutex::new(BTreeMap::new())),
+            params: config.params,
+            http_client,
+            checksum: config.checksum,
+            rate_limiter: (config.max_upload_bytes_per_sec > 0)
+                .then(|| Arc::new(RateLimiter::new(config.max_upload_bytes_per_sec))),
         })
     }
 
+    // returns the data key of generation if encryption is enabled: the one
+    // wrapped in its manifest, or the master key for generations with a
+    // manifest but no data key. A new one is only generated for generations
+    // without manifest yet if `create` is true, i.e. by `write_snapshot`.
+    async fn data_key(&self, generation: &Generation, create: bool) -> Result<Option<DataKey>> {
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => return Ok(None),
+        };
+        if let Some(data_key) = self.data_keys.lock().get(generation.as_str()) {
+            return Ok(Some(data_key.clone()));
+        }
+
+        let data_key = match self.manifest(generation).await? {
+            Some(GenerationManifest {
+                key_id: Some(key_id),
+                wrapped_key: Some(wrapped_key),
+                ..
+            }) => {
+                let key = keys.unwrap(generation.as_str(), &key_id, &wrapped_key)?;
+                DataKey {
+                    cipher: Cipher::try_create(&key)?,
+                    wrapped: Some((key_id, wrapped_key)),
+                }
+            }
+            Some(_) => DataKey {
+                cipher: keys.cipher().clone(),
+                wrapped: None,
+            },
+            None if create => {
+                let key = generate_data_key();
+                DataKey {
+                    cipher: Cipher::try_create(&key)?,
+                    wrapped: Some((
+                        keys.key_id().to_string(),
+                        keys.wrap(generation.as_str(), &key)?,
+                    )),
+                }
+            }
+            None => {
+                let msg = format!(
+                    "manifest of generation {} of db {} is missing, cannot get its data key",
+                    generation.as_str(),
+                    self.db_name
+                );
+                error!("{}", msg);
+                return Err(Error::StorageNotFound(msg));
+            }
+        };
+
+        // another task may have loaded or generated it meanwhile, keep the first.
+        let mut data_keys = self.data_keys.lock();
+        let data_key = data_keys
+            .entry(generation.as_str().to_string())
+            .or_insert(data_key);
+        Ok(Some(data_key.clone()))
+    }
+
+    // encrypt data if encryption is enabled, `aad` is authenticated too.
+    async fn encrypt(&self, generation: &Generation, data: Vec<u8>, aad: &str) -> Result<Vec<u8>> {
+        match self.data_key(generation, false).await? {
+            Some(data_key) => data_key.cipher.encrypt(&data, aad.as_bytes()),
+            None => Ok(data),
+        }
+    }
+
+    // decrypt data if encryption is enabled, `aad` MUST be the same as encrypt.
+    async fn decrypt(&self, generation: &Generation, data: Vec<u8>, aad: &str) -> Result<Vec<u8>> {
+        match self.data_key(generation, false).await? {
+            // generations without a data key may hold objects written before
+            // encryption was turned on.
+            Some(data_key) if data_key.wrapped.is_none() && !is_encrypted(&data) => Ok(data),
+            Some(data_key) => data_key.cipher.decrypt(&data, aad.as_bytes()),
+            None => {
+                self.check_not_encrypted(&data, aad)?;
+                Ok(data)
+            }
+        }
+    }
+
+    // encrypted objects cannot be read without the key, fail clearly instead
+    // of failing to decompress them.
+    fn check_not_encrypted(&self, data: &[u8], name: &str) -> Result<()> {
+        if is_encrypted(data) {
+            let msg = format!("{} is an encrypted object, encryption key required", name);
+            error!("{}", msg);
+            return Err(Error::EncryptionKeyRequired(msg));
+        }
+        Ok(())
+    }
+
     async fn ensure_parent_exist(&self, path: &str) -> Result<()> {
         let base = format!("{}/", parent_dir(path).unwrap());
 
@@ -94,50 +443,487 @@ impl StorageClient {
         compressed_data: Vec<u8>,
     ) -> Result<()> {
         let file = walsegment_file(
-            &self.db_path,
+            &self.db_dir,
             pos.generation.as_str(),
             pos.index,
             pos.offset,
+            self.compression,
         );
 
+        let aad = walsegment_aad(pos.generation.as_str(), pos.index, pos.offset);
+        let data = self.encrypt(&pos.generation, compressed_data, &aad).await?;
+
         self.ensure_parent_exist(&file).await?;
 
-        self.operator.write(&file, compressed_data).await?;
+        self.write_object(&file, data).await?;
 
         Ok(())
     }
 
+    // uploads the compressed snapshot file, its size is counted while streaming.
+    // the manifest of the generation is written with its first snapshot.
     pub async fn write_snapshot(
         &self,
         pos: &WalGenerationPos,
-        compressed_data: Vec<u8>,
+        page_size: u64,
+        compressed_file: File,
     ) -> Result<SnapshotInfo> {
-        let snapshot_file = snapshot_file(&self.db_name, pos.generation.as_str(), pos.index);
-        let snapshot_info = SnapshotInfo {
+        let snapshot_file = snapshot_file(
+            &self.db_dir,
+            pos.generation.as_str(),
+            pos.index,
+            self.compression,
+        );
+
+        self.ensure_parent_exist(&snapshot_file).await?;
+
+        // the manifest holds the data key of an encrypted generation, write
+        // it before any snapshot encrypted by the data key.
+        let manifest_file = manifest_file(&self.db_dir, pos.generation.as_str());
+        if !self.operator.exists(&manifest_file).await? {
+            let (key_id, wrapped_key) = match self.data_key(&pos.generation, true).await? {
+                Some(DataKey {
+                    wrapped: Some((key_id, wrapped_key)),
+                    ..
+                }) => (Some(key_id), Some(wrapped_key)),
+                _ => (None, None),
+            };
+            let manifest = GenerationManifest {
+                generation: pos.generation.as_str().to_string(),
+                page_size,
+                created_at: Utc::now(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+                compression: self.compression.name().to_string(),
+                snapshot_index: pos.index,
+                key_id,
+                wrapped_key,
+            };
+            self.operator
+                .write(&manifest_file, serde_json::to_vec(&manifest)?)
+                .await?;
+        }
+
+        let size = match &self.keys {
+            // aes-gcm encrypts and authenticates the snapshot as a whole, so it
+            // has to be buffered in memory.
+            Some(_) => {
+                let mut compressed_file = compressed_file;
+                let mut compressed_data = Vec::new();
+                compressed_file.read_to_end(&mut compressed_data)?;
+                let aad = snapshot_aad(pos.generation.as_str(), pos.index);
+                let data = self.encrypt(&pos.generation, compressed_data, &aad).await?;
+                // size of the object, the same as listed from the replicate.
+                let size = data.len() as u64;
+                self.write_object(&snapshot_file, data).await?;
+                size
+            }
+            None => self.write_file(&snapshot_file, compressed_file).await?,
+        };
+
+        Ok(SnapshotInfo {
             generation: pos.generation.clone(),
             index: pos.index,
-            size: compressed_data.len() as u64,
+            size,
             created_at: Utc::now(),
+            compression: self.compression,
+        })
+    }
+
+    // streams file to path in chunks, backends supporting multipart upload
+    // upload each chunk as a part. returns the number of bytes written.
+    async fn write_file(&self, path: &str, file: File) -> Result<u64> {
+        let mut file = tokio::fs::File::from_std(file);
+        let mut writer = self
+            .operator
+            .writer_with(path)
+            .chunk(STREAM_CHUNK_SIZE)
+            .await?;
+
+        let mut hasher = self.checksum.then(Sha256::new);
+        let result: Result<u64> = async {
+            let mut size = 0;
+            loop {
+                let mut buffer = Vec::with_capacity(STREAM_CHUNK_SIZE);
+                let n = (&mut file)
+                    .take(STREAM_CHUNK_SIZE as u64)
+                    .read_to_end(&mut buffer)
+                    .await?;
+                if n == 0 {
+                    break;
+                }
+                size += n as u64;
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&buffer);
+                }
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.acquire(n).await;
+                }
+                writer.write(buffer).await?;
+            }
+            writer.close().await?;
+            Ok(size)
+        }
+        .await;
+
+        if result.is_err() {
+            if let Err(e) = writer.abort().await {
+                error!("abort writing {} error: {:?}", path, e);
+            }
+            return result;
+        }
+
+        if let Some(hasher) = hasher {
+            self.write_checksum(path, hex::encode(hasher.finalize()))
+                .await?;
+        }
+        result
+    }
+
+    // writes object and its checksum sidecar if checksum is enabled.
+    async fn write_object(&self, path: &str, data: Vec<u8>) -> Result<()> {
+        let checksum = self.checksum.then(|| hex::encode(Sha256::digest(&data)));
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(data.len()).await;
+        }
+        self.operator.write(path, data).await?;
+
+        if let Some(checksum) = checksum {
+            self.write_checksum(path, checksum).await?;
+        }
+        Ok(())
+    }
+
+    // the sidecar is written after the object, so an object may lack it if
+    // the upload is interrupted in between.
+    async fn write_checksum(&self, path: &str, checksum: String) -> Result<()> {
+        self.operator.write(&checksum_file(path), checksum).await?;
+        Ok(())
+    }
+
+    // compares checksum of the object at path with its sidecar if checksum is
+    // enabled, objects without sidecar(e.g. written before checksum is
+    // enabled) are not verified.
+    async fn verify_checksum(&self, path: &str, checksum: &str) -> Result<()> {
+        let checksum_path = checksum_file(path);
+        let expected = match self.operator.read(&checksum_path).await {
+            Ok(data) => String::from_utf8_lossy(&data.to_vec()).trim().to_string(),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => {
+                debug!("object {} has no checksum, skip verifying", path);
+                return Ok(());
+            }
+            Err(e) => return Err(self.read_error(&checksum_path, e)),
         };
 
-        self.ensure_parent_exist(&snapshot_file).await?;
+        if expected != checksum {
+            let msg = format!(
+                "object {} checksum {} mismatch with expected {}",
+                path, checksum, expected
+            );
+            error!("{}", msg);
+            return Err(Error::ChecksumMismatch(msg));
+        }
+        Ok(())
+    }
+
+    // cheap probe of the storage by listing its root, to find misconfigured
+    // bucket, endpoint or credentials.
+    pub async fn check(&self) -> Result<()> {
+        if let Err(e) = self.operator.check().await {
+            let e: Error = e.into();
+            return Err(Error::from_error_code(
+                e.code(),
+                format!(
+                    "check storage {} of db {} fail: {}",
+                    self.params,
+                    self.db_name,
+                    e.message()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    // creates the bucket, or the root directory of fs, if it does not exist.
+    pub async fn ensure_bucket(&self) -> Result<()> {
+        let created = match (&self.params, &self.http_client) {
+            (StorageParams::S3(cfg), Some(http_client)) => {
+                S3RestoreClient::new(cfg, http_client.clone())?
+                    .ensure_bucket()
+                    .await?
+            }
+            (StorageParams::Fs(_), _) => {
+                let exist = self.operator.is_exist("/").await?;
+                if !exist {
+                    self.operator.create_dir("/").await?;
+                }
+                !exist
+            }
+            _ => {
+                return Err(Error::StorageUnsupported(format!(
+                    "storage {} of db {} does not support creating bucket",
+                    self.params, self.db_name
+                )));
+            }
+        };
+
+        if created {
+            info!(
+                "created bucket of storage {} for db {}",
+                self.params, self.db_name
+            );
+        } else {
+            info!(
+                "found bucket of storage {} for db {}",
+                self.params, self.db_name
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn write_heartbeat(&self) -> Result<()> {
+        let heartbeat = Heartbeat {
+            timestamp: Utc::now(),
+            host: hostname::get()?.to_string_lossy().to_string(),
+        };
+        let data = serde_json::to_vec(&heartbeat)?;
+        let file = heartbeat_file(&self.db_dir);
+
+        self.ensure_parent_exist(&file).await?;
+
+        self.write_object(&file, data).await?;
+
+        Ok(())
+    }
+
+    // writes the status object with the position synced to the replicate.
+    pub async fn write_status(&self, pos: &WalGenerationPos) -> Result<()> {
+        let status = RemoteStatus {
+            generation: pos.generation.as_str().to_string(),
+            index: pos.index,
+            offset: pos.offset,
+            last_sync_at: Utc::now(),
+            host: hostname::get()?.to_string_lossy().to_string(),
+            version: env!("CARGO_PKG_VERSION"),
+        };
+        let data = serde_json::to_vec(&status)?;
+        let file = status_file(&self.db_dir);
+
+        self.ensure_parent_exist(&file).await?;
+
+        self.write_object(&file, data).await?;
+
+        Ok(())
+    }
+
+    // returns the manifest of generation, None for generations written by
+    // versions without manifest.
+    pub async fn manifest(&self, generation: &Generation) -> Result<Option<GenerationManifest>> {
+        let path = manifest_file(&self.db_dir, generation.as_str());
+        match self.operator.read(&path).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data.to_vec())?)),
+            Err(e) if e.kind() == opendal::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(self.read_error(&path, e)),
+        }
+    }
+
+    // wraps the data key of every generation with the current key, only
+    // manifests are rewritten, snapshots and wal segments are untouched.
+    pub async fn rekey(&self) -> Result<RekeyResult> {
+        let keys = match &self.keys {
+            Some(keys) => keys,
+            None => {
+                return Err(Error::InvalidConfig(format!(
+                    "db {} replicate has no encryption to rekey",
+                    self.db_name
+                )));
+            }
+        };
+
+        let mut result = RekeyResult::default();
+        for generation in self.generations().await? {
+            let mut manifest = match self.manifest(&generation).await? {
+                Some(manifest) => manifest,
+                None => {
+                    result.skipped.push(generation);
+                    continue;
+                }
+            };
+            let (key_id, wrapped_key) = match (&manifest.key_id, &manifest.wrapped_key) {
+                (Some(key_id), Some(wrapped_key)) => (key_id, wrapped_key),
+                _ => {
+                    result.skipped.push(generation);
+                    continue;
+                }
+            };
+            if key_id == keys.key_id() {
+                result.unchanged.push(generation);
+                continue;
+            }
 
-        self.operator.write(&snapshot_file, compressed_data).await?;
+            let data_key = keys.unwrap(generation.as_str(), key_id, wrapped_key)?;
+            manifest.key_id = Some(keys.key_id().to_string());
+            manifest.wrapped_key = Some(keys.wrap(generation.as_str(), &data_key)?);
+            let path = manifest_file(&self.db_dir, generation.as_str());
+            self.operator
+                .write(&path, serde_json::to_vec(&manifest)?)
+                .await?;
+            info!(
+                "db {} generation {} data key rewrapped by key {}",
+                self.db_name,
+                generation.as_str(),
+                keys.key_id()
+            );
+            result.rekeyed.push(generation);
+        }
+
+        Ok(result)
+    }
+
+    // refuses to restore a generation this build cannot read, returns its
+    // manifest if any.
+    async fn check_compatible(
+        &self,
+        generation: &Generation,
+    ) -> Result<Option<GenerationManifest>> {
+        let manifest = match self.manifest(generation).await? {
+            Some(manifest) => manifest,
+            None => return Ok(None),
+        };
 
-        Ok(snapshot_info)
+        if Compression::from_name(&manifest.compression).is_none() {
+            let msg = format!(
+                "generation {} of db {} is compressed with {} by replited {}, which is not supported",
+                generation.as_str(),
+                self.db_name,
+                manifest.compression,
+                manifest.version
+            );
+            error!("{}", msg);
+            return Err(Error::IncompatibleGeneration(msg));
+        }
+        Ok(Some(manifest))
+    }
+
+    // archived objects(e.g. in the GLACIER storage class) can not be read
+    // until they are restored, report them with a clear error.
+    fn read_error(&self, path: &str, e: opendal::Error) -> Error {
+        if !is_archived_error(&e) {
+            return e.into();
+        }
+
+        let msg = format!(
+            "object {} is archived and not yet retrievable, run `replited thaw` to restore it first",
+            path
+        );
+        error!("{}", msg);
+        Error::ObjectArchived(msg)
+    }
+
+    async fn read_object(&self, path: &str) -> Result<Vec<u8>> {
+        let data = match self.operator.read(path).await {
+            Ok(data) => data.to_vec(),
+            Err(e) => return Err(self.read_error(path, e)),
+        };
+
+        if self.checksum {
+            self.verify_checksum(path, &hex::encode(Sha256::digest(&data)))
+                .await?;
+        }
+        Ok(data)
+    }
+
+    // streams object of path into file in chunks, returns the number of bytes read.
+    // `progress` is called with the number of bytes read so far.
+    async fn read_file(
+        &self,
+        path: &str,
+        file: &mut File,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<u64> {
+        let size = self.operator.stat(path).await?.content_length();
+        let reader = self.operator.reader(path).await?;
+
+        let mut hasher = self.checksum.then(Sha256::new);
+        let mut offset = 0;
+        while offset < size {
+            let end = min(offset + STREAM_CHUNK_SIZE as u64, size);
+            let buffer = reader
+                .read(offset..end)
+                .await
+                .map_err(|e| self.read_error(path, e))?
+                .to_vec();
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&buffer);
+            }
+            file.write_all(&buffer)?;
+            offset = end;
+            progress(offset);
+        }
+
+        if let Some(hasher) = hasher {
+            self.verify_checksum(path, &hex::encode(hasher.finalize()))
+                .await?;
+        }
+        Ok(size)
     }
 
     pub async fn read_snapshot(&self, info: &SnapshotInfo) -> Result<Vec<u8>> {
-        let snapshot_file = snapshot_file(&self.db_name, info.generation.as_str(), info.index);
+        let snapshot_file = snapshot_file(
+            &self.db_dir,
+            info.generation.as_str(),
+            info.index,
+            info.compression,
+        );
+
+        let data = self.read_object(&snapshot_file).await?;
+
+        let aad = snapshot_aad(info.generation.as_str(), info.index);
+        self.decrypt(&info.generation, data, &aad).await
+    }
 
-        let data = self.operator.read(&snapshot_file).await?;
+    // downloads the snapshot into an anonymous temp file without buffering it
+    // in memory, returns the temp file of compressed data rewound to the start.
+    // `progress` is called with the number of bytes downloaded so far.
+    pub async fn download_snapshot(
+        &self,
+        info: &SnapshotInfo,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<File> {
+        let mut temp_file = tempfile()?;
+        match &self.keys {
+            // aes-gcm decrypts and authenticates the snapshot as a whole.
+            Some(_) => {
+                let data = self.read_snapshot(info).await?;
+                temp_file.write_all(&data)?;
+                progress(info.size);
+            }
+            None => {
+                let snapshot_file = snapshot_file(
+                    &self.db_dir,
+                    info.generation.as_str(),
+                    info.index,
+                    info.compression,
+                );
+                self.read_file(&snapshot_file, &mut temp_file, progress)
+                    .await?;
+
+                let mut magic = Vec::with_capacity(ENCRYPTED_MAGIC.len());
+                temp_file.seek(SeekFrom::Start(0))?;
+                (&mut temp_file)
+                    .take(ENCRYPTED_MAGIC.len() as u64)
+                    .read_to_end(&mut magic)?;
+                self.check_not_encrypted(&magic, &snapshot_file)?;
+            }
+        }
 
-        Ok(data.to_vec())
+        temp_file.seek(SeekFrom::Start(0))?;
+        Ok(temp_file)
     }
 
     pub async fn snapshots(&self, generation: &str) -> Result<Vec<SnapshotInfo>> {
         let generation = Generation::try_create(generation)?;
-        let snapshots_dir = snapshots_dir(&self.db_name, generation.as_str());
+        let snapshots_dir = snapshots_dir(&self.db_dir, generation.as_str());
         let entries = match self
             .operator
             .list_with(&snapshots_dir)
@@ -165,7 +951,7 @@ impl StorageClient {
         let mut snapshots = vec![];
         for entry in entries {
             let metadata = entry.metadata();
-            if !metadata.is_file() {
+            if !metadata.is_file() || is_checksum_file(entry.name()) {
                 continue;
             }
             let index = parse_snapshot_path(entry.name())?;
@@ -174,15 +960,16 @@ impl StorageClient {
                 index,
                 size: metadata.content_length(),
                 created_at: metadata.last_modified().unwrap(),
+                compression: Compression::from_path(entry.name()),
             })
         }
 
         Ok(snapshots)
     }
 
-    async fn max_snapshot(&self, generation: &str) -> Result<Option<SnapshotInfo>> {
+    pub async fn max_snapshot(&self, generation: &str) -> Result<Option<SnapshotInfo>> {
         let generation = Generation::try_create(generation)?;
-        let snapshots_dir = snapshots_dir(&self.db_name, generation.as_str());
+        let snapshots_dir = snapshots_dir(&self.db_dir, generation.as_str());
         let entries = self
             .operator
             .list_with(&snapshots_dir)
@@ -194,7 +981,7 @@ impl StorageClient {
         let mut max_index = None;
         for entry in entries {
             let metadata = entry.metadata();
-            if !metadata.is_file() {
+            if !metadata.is_file() || is_checksum_file(entry.name()) {
                 continue;
             }
             let index = parse_snapshot_path(entry.name())?;
@@ -220,6 +1007,7 @@ impl StorageClient {
                 index,
                 size: metadata.content_length(),
                 created_at: metadata.last_modified().unwrap(),
+                compression: Compression::from_path(entry.name()),
             });
         }
 
@@ -228,17 +1016,18 @@ impl StorageClient {
 
     pub async fn wal_segments(&self, generation: &str) -> Result<Vec<WalSegmentInfo>> {
         let generation = Generation::try_create(generation)?;
-        let walsegments_dir = walsegments_dir(&self.db_name, generation.as_str());
+        let walsegments_dir = walsegments_dir(&self.db_dir, generation.as_str());
         let entries = self
             .operator
             .list_with(&walsegments_dir)
             .metakey(Metakey::ContentLength)
+            .metakey(Metakey::LastModified)
             .await?;
 
         let mut wal_segments = vec![];
         for entry in entries {
             let metadata = entry.metadata();
-            if !metadata.is_file() {
+            if !metadata.is_file() || is_checksum_file(entry.name()) {
                 continue;
             }
             let (index, offset) = parse_wal_segment_path(entry.name())?;
@@ -246,81 +1035,338 @@ impl StorageClient {
                 generation: generation.clone(),
                 index,
                 offset,
-                size: entry.metadata().content_length(),
+                size: metadata.content_length(),
+                created_at: metadata.last_modified().unwrap_or_default(),
+                compression: Compression::from_path(entry.name()),
             })
         }
 
         Ok(wal_segments)
     }
 
+    // returns the position of the end of the last wal segment in generation,
+    // or of the last snapshot if there is no wal segment.
+    pub async fn generation_position(&self, generation: &str) -> Result<WalGenerationPos> {
+        // Fetch last snapshot. Return error if no snapshots exist.
+        let snapshot = match self.max_snapshot(generation).await? {
+            Some(snapshot) => snapshot,
+            None => return Err(Error::NoSnapshotError(generation)),
+        };
+
+        // Determine last WAL segment available, use snapshot if none exist.
+        let wal_segments = self.wal_segments(generation).await?;
+        let segment = match wal_segments.iter().max_by_key(|w| (w.index, w.offset)) {
+            Some(segment) => segment,
+            None => {
+                return Ok(WalGenerationPos {
+                    generation: snapshot.generation,
+                    index: snapshot.index,
+                    offset: 0,
+                });
+            }
+        };
+
+        let compressed_data = self.read_wal_segment(segment).await?;
+        let decompressed_data = decompressed_data(compressed_data, segment.compression)?;
+
+        Ok(WalGenerationPos {
+            generation: segment.generation.clone(),
+            index: segment.index,
+            offset: segment.offset + decompressed_data.len() as u64,
+        })
+    }
+
     pub async fn read_wal_segment(&self, info: &WalSegmentInfo) -> Result<Vec<u8>> {
         let generation = &info.generation;
         let index = info.index;
         let offset = info.offset;
 
-        let wal_segment_file = walsegment_file(&self.db_name, generation.as_str(), index, offset);
-        let bytes = self.operator.read(&wal_segment_file).await?.to_vec();
-        Ok(bytes)
+        let wal_segment_file = walsegment_file(
+            &self.db_dir,
+            generation.as_str(),
+            index,
+            offset,
+            info.compression,
+        );
+        let bytes = self.read_object(&wal_segment_file).await?;
+
+        let aad = walsegment_aad(generation.as_str(), index, offset);
+        self.decrypt(generation, bytes, &aad).await
     }
 
-    async fn restore_wal_segments_of(&self, snapshot: &SnapshotInfo) -> Result<RestoreWalSegments> {
-        let mut wal_segments = self.wal_segments(snapshot.generation.as_str()).await?;
+    // removes objects in batches and returns the number of removed objects.
+    // `delete_concurrency` is used as the batch size for backends which support
+    // batch delete, or the number of concurrent deletes otherwise.
+    pub async fn remove_objects(&self, paths: Vec<String>) -> Result<usize> {
+        if paths.is_empty() {
+            return Ok(0);
+        }
 
-        // sort wal segments first by index, then offset
-        wal_segments.sort_by(|a, b| {
-            let ordering = a.index.partial_cmp(&b.index).unwrap();
-            if ordering.is_eq() {
-                a.offset.partial_cmp(&b.offset).unwrap()
-            } else {
-                ordering
-            }
-        });
+        let start = Instant::now();
+        let num = paths.len();
+        self.operator
+            .with_limit(self.delete_concurrency)
+            .remove(paths)
+            .await?;
+
+        info!(
+            "db {} removed {} objects in {:?}",
+            self.db_name,
+            num,
+            start.elapsed()
+        );
+        Ok(num)
+    }
+
+    // removes wal segments of generation whose index is less than `index`,
+    // returns the number of removed wal segments.
+    pub async fn remove_wal_segments_before(&self, generation: &str, index: u64) -> Result<usize> {
+        let paths: Vec<String> = self
+            .wal_segments(generation)
+            .await?
+            .into_iter()
+            .filter(|wal_segment| wal_segment.index < index)
+            .map(|wal_segment| {
+                walsegment_file(
+                    &self.db_dir,
+                    wal_segment.generation.as_str(),
+                    wal_segment.index,
+                    wal_segment.offset,
+                    wal_segment.compression,
+                )
+            })
+            .collect();
+
+        let num = paths.len();
+        self.remove_objects(self.with_checksum_files(paths)).await?;
+        Ok(num)
+    }
 
-        let mut restore_wal_segments: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    // merges contiguous small wal segments of each index into objects of about
+    // `target_size` compressed bytes, returns the number of removed wal segments.
+    // a merged segment overwrites the first segment of its run before the rest
+    // are removed, so readers may see both, restore skips the covered ones.
+    pub async fn compact_wal_segments(&self, generation: &str, target_size: u64) -> Result<usize> {
+        let mut wal_segments = self.wal_segments(generation).await?;
+        wal_segments.sort_by_key(|wal_segment| (wal_segment.index, wal_segment.offset));
 
+        let mut removed = 0;
+        let mut run: Vec<WalSegmentInfo> = vec![];
+        let mut run_size = 0;
+        let mut data = vec![];
         for wal_segment in wal_segments {
-            if wal_segment.index < snapshot.index {
+            // segments in another compression would be written to another path,
+            // leave them as is like the large ones.
+            let mergeable =
+                wal_segment.size < target_size && wal_segment.compression == self.compression;
+            let contiguous = match run.first() {
+                Some(first) => {
+                    first.index == wal_segment.index
+                        && first.offset + data.len() as u64 == wal_segment.offset
+                        && run_size + wal_segment.size <= target_size
+                }
+                None => false,
+            };
+
+            if !mergeable || !contiguous {
+                removed += self.merge_wal_segments(&run, &data).await?;
+                run.clear();
+                run_size = 0;
+                data.clear();
+            }
+            if !mergeable {
                 continue;
             }
 
-            match restore_wal_segments.get_mut(&wal_segment.index) {
-                Some(offsets) => {
-                    if *offsets.last().unwrap() >= wal_segment.offset {
-                        let msg = format!(
-                            "wal segment out of order, generation: {:?}, index: {}, offset: {}",
-                            snapshot.generation.as_str(),
-                            wal_segment.index,
-                            wal_segment.offset
-                        );
-                        error!("{}", msg);
-                        return Err(Error::InvalidWalSegmentError(msg));
-                    }
-                    offsets.push(wal_segment.offset);
-                }
-                None => {
-                    if wal_segment.offset != 0 {
-                        let msg = format!(
-                            "missing initial wal segment, generation: {:?}, index: {}, offset: {}",
-                            snapshot.generation.as_str(),
-                            wal_segment.index,
-                            wal_segment.offset
-                        );
-                        error!("{}", msg);
-                        return Err(Error::InvalidWalSegmentError(msg));
-                    }
-                    restore_wal_segments.insert(wal_segment.index, vec![wal_segment.offset]);
-                }
+            let compressed_data = self.read_wal_segment(&wal_segment).await?;
+            data.extend(decompressed_data(compressed_data, wal_segment.compression)?);
+            run_size += wal_segment.size;
+            run.push(wal_segment);
+        }
+        removed += self.merge_wal_segments(&run, &data).await?;
+
+        Ok(removed)
+    }
+
+    // writes decompressed `data` of contiguous wal segments in `run` as the
+    // first segment, then removes the others.
+    async fn merge_wal_segments(&self, run: &[WalSegmentInfo], data: &[u8]) -> Result<usize> {
+        if run.len() < 2 {
+            return Ok(0);
+        }
+
+        let first = &run[0];
+        let pos = WalGenerationPos {
+            generation: first.generation.clone(),
+            index: first.index,
+            offset: first.offset,
+        };
+        // remove the old sidecar first, an interrupted merge then leaves an
+        // unverified segment instead of a checksum mismatch.
+        if self.checksum {
+            let path = walsegment_file(
+                &self.db_dir,
+                first.generation.as_str(),
+                first.index,
+                first.offset,
+                first.compression,
+            );
+            self.operator.delete(&checksum_file(&path)).await?;
+        }
+        let compressed_data = compress_buffer(data, self.compression, self.compression_level)?;
+        self.write_wal_segment(&pos, compressed_data).await?;
+
+        let paths: Vec<String> = run[1..]
+            .iter()
+            .map(|wal_segment| {
+                walsegment_file(
+                    &self.db_dir,
+                    wal_segment.generation.as_str(),
+                    wal_segment.index,
+                    wal_segment.offset,
+                    wal_segment.compression,
+                )
+            })
+            .collect();
+        self.remove_objects(self.with_checksum_files(paths)).await?;
+
+        debug!(
+            "db {} merged {} wal segments of index {} from offset {}",
+            self.db_name,
+            run.len(),
+            first.index,
+            first.offset
+        );
+        Ok(run.len() - 1)
+    }
+
+    // appends checksum sidecars of paths if checksum is enabled.
+    fn with_checksum_files(&self, mut paths: Vec<String>) -> Vec<String> {
+        if self.checksum {
+            let checksum_files: Vec<String> = paths.iter().map(|p| checksum_file(p)).collect();
+            paths.extend(checksum_files);
+        }
+        paths
+    }
+
+    // requests to restore the archived snapshots and wal segments of generation
+    // for `days` days, returns the number of objects of each restore status, or
+    // None if the storage backend has no archive storage class.
+    pub async fn thaw_generation(
+        &self,
+        generation: &str,
+        days: u32,
+        tier: &str,
+    ) -> Result<Option<BTreeMap<RestoreStatus, usize>>> {
+        let client = match (&self.params, &self.http_client) {
+            (StorageParams::S3(cfg), Some(http_client)) => {
+                S3RestoreClient::new(cfg, http_client.clone())?
             }
+            _ => return Ok(None),
+        };
+
+        let mut paths: Vec<String> = self
+            .snapshots(generation)
+            .await?
+            .into_iter()
+            .map(|snapshot| {
+                snapshot_file(
+                    &self.db_dir,
+                    snapshot.generation.as_str(),
+                    snapshot.index,
+                    snapshot.compression,
+                )
+            })
+            .collect();
+        paths.extend(
+            self.wal_segments(generation)
+                .await?
+                .into_iter()
+                .map(|wal_segment| {
+                    walsegment_file(
+                        &self.db_dir,
+                        wal_segment.generation.as_str(),
+                        wal_segment.index,
+                        wal_segment.offset,
+                        wal_segment.compression,
+                    )
+                }),
+        );
+
+        let mut result = BTreeMap::new();
+        for path in self.with_checksum_files(paths) {
+            let status = client.restore_object(&path, days, tier).await?;
+            *result.entry(status).or_insert(0) += 1;
         }
 
-        Ok(restore_wal_segments.into_iter().collect())
+        Ok(Some(result))
     }
 
-    pub async fn restore_info(&self) -> Result<Option<RestoreInfo>> {
-        let dir = remote_generations_dir(&self.db_name);
+    // returns wal segments to apply after snapshot, grouped by index.
+    // if `timestamp` is set, stop at the first wal segment created after it.
+    async fn restore_wal_segments_of(
+        &self,
+        snapshot: &SnapshotInfo,
+        best_effort: bool,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Result<RestoreWalSegments> {
+        let wal_segments = self.wal_segments(snapshot.generation.as_str()).await?;
+
+        chain_wal_segments(snapshot, wal_segments, best_effort, timestamp)
+    }
+
+    // returns wal segments of generation from `index` upward, grouped by index,
+    // fails if there is a gap in them.
+    pub async fn wal_segments_from(
+        &self,
+        generation: &Generation,
+        index: u64,
+    ) -> Result<RestoreWalSegments> {
+        let snapshot = SnapshotInfo {
+            generation: generation.clone(),
+            index,
+            ..Default::default()
+        };
+        self.restore_wal_segments_of(&snapshot, false, None).await
+    }
+
+    // returns the time range db can be restored to, None if there is no snapshot.
+    pub async fn restorable_window(&self) -> Result<Option<RestorableWindow>> {
+        let mut window: Option<RestorableWindow> = None;
+        for generation in self.generations().await? {
+            let snapshots = self.snapshots(generation.as_str()).await?;
+            let earliest = match snapshots.iter().map(|s| s.created_at).min() {
+                Some(earliest) => earliest,
+                // nothing in the generation can be restored without snapshot.
+                None => continue,
+            };
+            let latest = self
+                .wal_segments(generation.as_str())
+                .await?
+                .iter()
+                .map(|w| w.created_at)
+                .chain(snapshots.iter().map(|s| s.created_at))
+                .max()
+                .unwrap_or(earliest);
+
+            window = Some(match window {
+                Some(window) => RestorableWindow {
+                    earliest: window.earliest.min(earliest),
+                    latest: window.latest.max(latest),
+                },
+                None => RestorableWindow { earliest, latest },
+            });
+        }
+
+        Ok(window)
+    }
+
+    // returns all generations of db, sorted from the newest to the oldest.
+    pub async fn generations(&self) -> Result<Vec<Generation>> {
+        let dir = remote_generations_dir(&self.db_dir);
         let entries = self.operator.list(&dir).await?;
 
-        let mut entry_with_generation = Vec::with_capacity(entries.len());
+        let mut generations = Vec::with_capacity(entries.len());
         for entry in entries {
             let metadata = entry.metadata();
             if !metadata.is_dir() {
@@ -336,31 +1382,758 @@ impl StorageClient {
                 }
             };
 
-            entry_with_generation.push((entry, generation));
+            generations.push(generation);
         }
 
-        // sort the entries in reverse order
-        entry_with_generation.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        // sort the generations in reverse order
+        generations.sort_by(|a, b| b.cmp(a));
+
+        Ok(generations)
+    }
+
+    // returns generations beyond the retention policy, the newest generation
+    // and `keep` are never included.
+    pub async fn generations_to_prune(
+        &self,
+        retention: &RetentionConfig,
+        keep: &Generation,
+    ) -> Result<Vec<Generation>> {
+        let now = Utc::now();
+        let mut generations = vec![];
+        for (i, generation) in self.generations().await?.into_iter().enumerate() {
+            if i == 0 || generation == *keep {
+                continue;
+            }
+
+            let too_many = retention.max_generations > 0 && i >= retention.max_generations;
+            let too_old = retention.max_age_secs > 0
+                && generation.created_at().is_some_and(|created_at| {
+                    (now - created_at).num_seconds() > retention.max_age_secs as i64
+                });
+            if too_many || too_old {
+                generations.push(generation);
+            }
+        }
 
-        for (_entry, generation) in entry_with_generation {
-            let snapshot = match self.max_snapshot(generation.as_str()).await? {
-                Some(snapshot) => snapshot,
+        Ok(generations)
+    }
+
+    // removes generations beyond the retention policy and returns them, the
+    // newest generation and `keep` are never removed.
+    pub async fn prune_generations(
+        &self,
+        retention: &RetentionConfig,
+        keep: &Generation,
+    ) -> Result<Vec<Generation>> {
+        let generations = self.generations_to_prune(retention, keep).await?;
+        for generation in &generations {
+            let dir = format!("{}/", generation_dir(&self.db_dir, generation.as_str()));
+            self.operator.remove_all(&dir).await?;
+            info!(
+                "db {} removed generation {} by retention policy",
+                self.db_name, generation
+            );
+        }
+
+        Ok(generations)
+    }
+
+    // returns the wal segments left without a snapshot by failed snapshot
+    // uploads or partial prunes: wal segments below the minimum snapshot index
+    // of their generation, and generations with wal segments but no snapshot.
+    // The newest generation without snapshot is skipped, as its first snapshot
+    // may still be uploading.
+    pub async fn orphaned_wal_segments(&self) -> Result<Vec<OrphanedWalSegments>> {
+        let mut orphans = vec![];
+        for (i, generation) in self.generations().await?.into_iter().enumerate() {
+            let min_index = self
+                .snapshots(generation.as_str())
+                .await?
+                .into_iter()
+                .map(|snapshot| snapshot.index)
+                .min();
+            if min_index.is_none() && i == 0 {
+                continue;
+            }
+
+            let wal_segments: Vec<WalSegmentInfo> = self
+                .wal_segments(generation.as_str())
+                .await?
+                .into_iter()
+                .filter(|wal_segment| min_index.is_none_or(|index| wal_segment.index < index))
+                .collect();
+            if wal_segments.is_empty() {
+                continue;
+            }
+
+            orphans.push(OrphanedWalSegments {
+                generation,
+                no_snapshot: min_index.is_none(),
+                wal_segments,
+            });
+        }
+
+        Ok(orphans)
+    }
+
+    // removes orphaned wal segments, returns the number of removed wal segments.
+    pub async fn remove_orphaned_wal_segments(
+        &self,
+        orphans: &[OrphanedWalSegments],
+    ) -> Result<usize> {
+        let mut removed = 0;
+        for orphan in orphans {
+            if orphan.no_snapshot {
+                let dir = format!(
+                    "{}/",
+                    generation_dir(&self.db_dir, orphan.generation.as_str())
+                );
+                self.operator.remove_all(&dir).await?;
+                info!(
+                    "db {} removed generation {} without snapshot",
+                    self.db_name, orphan.generation
+                );
+                removed += orphan.wal_segments.len();
+                continue;
+            }
+
+            let paths: Vec<String> = orphan
+                .wal_segments
+                .iter()
+                .map(|wal_segment| {
+                    walsegment_file(
+                        &self.db_dir,
+                        wal_segment.generation.as_str(),
+                        wal_segment.index,
+                        wal_segment.offset,
+                        wal_segment.compression,
+                    )
+                })
+                .collect();
+            self.remove_objects(self.with_checksum_files(paths)).await?;
+            info!(
+                "db {} removed {} wal segments before the first snapshot of generation {}",
+                self.db_name,
+                orphan.wal_segments.len(),
+                orphan.generation
+            );
+            removed += orphan.wal_segments.len();
+        }
+
+        Ok(removed)
+    }
+
+    // returns the number and total size of objects of a generation.
+    pub async fn generation_usage(&self, generation: &Generation) -> Result<(usize, u64)> {
+        let dir = format!("{}/", generation_dir(&self.db_dir, generation.as_str()));
+        let entries = self
+            .operator
+            .list_with(&dir)
+            .recursive(true)
+            .metakey(Metakey::ContentLength)
+            .await?;
+
+        let mut count = 0;
+        let mut size = 0;
+        for entry in entries {
+            let metadata = entry.metadata();
+            if !metadata.is_file() {
+                continue;
+            }
+            count += 1;
+            size += metadata.content_length();
+        }
+        Ok((count, size))
+    }
+
+    // returns the snapshot of generation to restore from, which is the latest
+    // snapshot created before `timestamp` if it is set.
+    async fn restore_snapshot_of(
+        &self,
+        generation: &Generation,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> Result<Option<SnapshotInfo>> {
+        Ok(self
+            .snapshots(generation.as_str())
+            .await?
+            .into_iter()
+            .filter(|snapshot| match timestamp {
+                Some(timestamp) => snapshot.created_at <= timestamp,
+                None => true,
+            })
+            .max_by_key(|snapshot| snapshot.index))
+    }
+
+    // returns restore info of generation, None if the generation has no
+    // snapshot to restore from.
+    pub async fn restore_info_of(
+        &self,
+        generation: &Generation,
+        options: &RestoreOptions,
+    ) -> Result<Option<RestoreInfo>> {
+        let snapshot = match self
+            .restore_snapshot_of(generation, options.timestamp)
+            .await?
+        {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        let manifest = self.check_compatible(generation).await?;
+
+        let wal_segments = self
+            .restore_wal_segments_of(&snapshot, options.best_effort, options.timestamp)
+            .await?;
+        Ok(Some(RestoreInfo {
+            snapshot,
+            wal_segments,
+            page_size: manifest.map(|manifest| manifest.page_size),
+        }))
+    }
+
+    // returns restore info of the latest generation which has a snapshot,
+    // see `restore_wal_segments_of` for `best_effort` and `timestamp`.
+    pub async fn restore_info(&self, options: &RestoreOptions) -> Result<Option<RestoreInfo>> {
+        let mut has_snapshot = false;
+        for generation in self.generations().await? {
+            match self.restore_info_of(&generation, options).await? {
+                Some(restore_info) => return Ok(Some(restore_info)),
                 // if generation has no snapshot, ignore and skip to the next generation
                 None => {
-                    error!("dir {:?} has no snapshots", generation);
-                    continue;
+                    has_snapshot |= !self.snapshots(generation.as_str()).await?.is_empty();
+                    error!("dir {:?} has no snapshots to restore", generation);
                 }
-            };
+            }
+        }
 
-            // return only if wal segments in this snapshot is valid.
-            if let Ok(wal_segments) = self.restore_wal_segments_of(&snapshot).await {
-                return Ok(Some(RestoreInfo {
-                    snapshot,
-                    wal_segments,
-                }));
+        if let Some(timestamp) = options.timestamp {
+            if has_snapshot {
+                let msg = format!(
+                    "db {} timestamp {} is before the earliest snapshot",
+                    self.db_name,
+                    timestamp.to_rfc3339()
+                );
+                error!("{}", msg);
+                return Err(Error::NoSnapshotError(msg));
             }
         }
 
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Seek;
+    use std::io::SeekFrom;
+    use std::io::Write;
+    use std::time::Duration;
+
+    use chrono::DateTime;
+    use chrono::Utc;
+    use clap::Parser;
+    use tempfile::tempfile;
+    use uuid::timestamp::Timestamp;
+    use uuid::NoContext;
+    use uuid::Uuid;
+
+    use super::chain_wal_segments;
+    use super::SnapshotInfo;
+    use super::StorageClient;
+    use super::WalSegmentInfo;
+    use crate::base::heartbeat_file;
+    use crate::base::walsegment_file;
+    use crate::base::Generation;
+    use crate::config::RestoreOptions;
+    use crate::config::RetentionConfig;
+    use crate::config::StorageConfig;
+    use crate::database::WalGenerationPos;
+    use crate::error::Error;
+    use crate::error::Result;
+
+    fn wal_segment(generation: &Generation, index: u64, offset: u64) -> WalSegmentInfo {
+        WalSegmentInfo {
+            generation: generation.clone(),
+            index,
+            offset,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_chain_wal_segments_with_index_gap() -> Result<()> {
+        let generation = Generation::new();
+        let snapshot = SnapshotInfo {
+            generation: generation.clone(),
+            index: 5,
+            ..Default::default()
+        };
+        let wal_segments = vec![
+            wal_segment(&generation, 7, 0),
+            wal_segment(&generation, 5, 4152),
+            wal_segment(&generation, 5, 0),
+            wal_segment(&generation, 4, 0),
+        ];
+
+        let result = chain_wal_segments(&snapshot, wal_segments.clone(), false, None);
+        let err = result.unwrap_err();
+        assert_eq!(err.code(), Error::INVALID_WAL_SEGMENT_ERROR);
+        assert!(err.message().contains("missing wal segment index 6"));
+
+        // best effort restore stops at the gap.
+        let chained = chain_wal_segments(&snapshot, wal_segments, true, None)?;
+        assert_eq!(chained.len(), 1);
+        assert_eq!(chained[0].0, 5);
+        assert_eq!(chained[0].1.len(), 2);
+
+        // the index of snapshot is missing.
+        let wal_segments = vec![wal_segment(&generation, 6, 0)];
+        let result = chain_wal_segments(&snapshot, wal_segments, false, None);
+        assert!(result
+            .unwrap_err()
+            .message()
+            .contains("missing wal segment index 5"));
+
+        Ok(())
+    }
+
+    fn memory_client(root: &str) -> Result<StorageClient> {
+        let config: StorageConfig = toml::from_str(&format!(
+            "name = \"memory\"\nparams.type = \"Memory\"\nparams.root = \"{}\"",
+            root
+        ))
+        .unwrap();
+        StorageClient::try_create("/tmp/test.db".to_string(), config)
+    }
+
+    #[tokio::test]
+    async fn test_restore_info_of_memory_storage() -> Result<()> {
+        let client = memory_client("/test_restore_info")?;
+        let generation = Generation::new();
+        let pos = |index, offset| WalGenerationPos {
+            generation: generation.clone(),
+            index,
+            offset,
+        };
+
+        client.write_snapshot(&pos(0, 0), 4096, tempfile()?).await?;
+        for (index, offset) in [(0, 0), (0, 4152), (1, 0)] {
+            client
+                .write_wal_segment(&pos(index, offset), vec![])
+                .await?;
+        }
+
+        // clients of the same memory root share objects.
+        let client = memory_client("/test_restore_info")?;
+        let options = RestoreOptions::parse_from(["restore", "--db", "test.db"]);
+        let restore_info = client.restore_info(&options).await?.unwrap();
+        assert_eq!(restore_info.snapshot.generation, generation);
+        assert_eq!(restore_info.snapshot.index, 0);
+        assert_eq!(restore_info.page_size, Some(4096));
+        let indexes: Vec<(u64, usize)> = restore_info
+            .wal_segments
+            .iter()
+            .map(|(index, segments)| (*index, segments.len()))
+            .collect();
+        assert_eq!(indexes, vec![(0, 2), (1, 1)]);
+
+        // another root is empty.
+        let client = memory_client("/test_restore_info_empty")?;
+        assert!(client.restore_info(&options).await?.is_none());
+
+        Ok(())
+    }
+
+    fn encrypted_memory_client(
+        root: &str,
+        key: &str,
+        previous_keys: &[&str],
+    ) -> Result<StorageClient> {
+        let previous_keys: Vec<String> = previous_keys
+            .iter()
+            .map(|key| format!("{{ key = \"{}\" }}", key))
+            .collect();
+        let config: StorageConfig = toml::from_str(&format!(
+            "name = \"memory\"\nparams.type = \"Memory\"\nparams.root = \"{}\"\nencryption.key = \"{}\"\nencryption.previous_keys = [{}]",
+            root,
+            key,
+            previous_keys.join(", ")
+        ))
+        .unwrap();
+        StorageClient::try_create("/tmp/test.db".to_string(), config)
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_objects() -> Result<()> {
+        let root = "/test_encrypted_objects";
+        let key = "01".repeat(32);
+        let client = encrypted_memory_client(root, &key, &[])?;
+        let generation = Generation::new();
+        let pos = WalGenerationPos {
+            generation: generation.clone(),
+            index: 0,
+            offset: 0,
+        };
+
+        let mut snapshot = tempfile()?;
+        snapshot.write_all(&[1; 100])?;
+        snapshot.seek(SeekFrom::Start(0))?;
+        let info = client.write_snapshot(&pos, 4096, snapshot).await?;
+        client.write_wal_segment(&pos, vec![2; 100]).await?;
+
+        // size of snapshot includes the encryption overhead, the same as listed.
+        let snapshots = client.snapshots(generation.as_str()).await?;
+        assert_eq!(snapshots[0].size, info.size);
+        assert!(info.size > 100);
+        let wal_segments = client.wal_segments(generation.as_str()).await?;
+        assert_eq!(
+            client.read_wal_segment(&wal_segments[0]).await?,
+            vec![2; 100]
+        );
+
+        // reading without the key fails before decompressing.
+        let plain = memory_client(root)?;
+        let err = plain
+            .download_snapshot(&info, &mut |_| {})
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Error::ENCRYPTION_KEY_REQUIRED);
+        let err = plain.read_wal_segment(&wal_segments[0]).await.unwrap_err();
+        assert_eq!(err.code(), Error::ENCRYPTION_KEY_REQUIRED);
+
+        // plain objects are not read as encrypted ones.
+        let pos = WalGenerationPos { offset: 100, ..pos };
+        plain.write_wal_segment(&pos, vec![3; 100]).await?;
+        let wal_segments = client.wal_segments(generation.as_str()).await?;
+        let err = client.read_wal_segment(&wal_segments[1]).await.unwrap_err();
+        assert_eq!(err.code(), Error::DECRYPT_ERROR);
+
+        // another key cannot unwrap the data key of the generation.
+        let other = encrypted_memory_client(root, &"02".repeat(32), &[])?;
+        let err = other.read_wal_segment(&wal_segments[0]).await.unwrap_err();
+        assert_eq!(err.code(), Error::ENCRYPTION_KEY_REQUIRED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encryption_of_plain_generations() -> Result<()> {
+        let root = "/test_encryption_of_plain_generations";
+        let plain = memory_client(root)?;
+        let client = encrypted_memory_client(root, &"01".repeat(32), &[])?;
+        let generation = Generation::new();
+        let pos = WalGenerationPos {
+            generation: generation.clone(),
+            index: 0,
+            offset: 0,
+        };
+
+        // a generation started before encryption was turned on keeps its
+        // plain objects readable.
+        plain.write_snapshot(&pos, 4096, tempfile()?).await?;
+        plain.write_wal_segment(&pos, vec![1; 100]).await?;
+        let pos = WalGenerationPos { offset: 100, ..pos };
+        client.write_wal_segment(&pos, vec![2; 100]).await?;
+        let wal_segments = client.wal_segments(generation.as_str()).await?;
+        assert_eq!(
+            client.read_wal_segment(&wal_segments[0]).await?,
+            vec![1; 100]
+        );
+        assert_eq!(
+            client.read_wal_segment(&wal_segments[1]).await?,
+            vec![2; 100]
+        );
+
+        // the data key of a generation without manifest is never generated
+        // on reads or writes of wal segments.
+        let generation = Generation::new();
+        let pos = WalGenerationPos {
+            generation: generation.clone(),
+            index: 0,
+            offset: 0,
+        };
+        plain.write_wal_segment(&pos, vec![1; 100]).await?;
+        let wal_segments = client.wal_segments(generation.as_str()).await?;
+        let err = client.read_wal_segment(&wal_segments[0]).await.unwrap_err();
+        assert_eq!(err.code(), Error::STORAGE_NOT_FOUND);
+        let err = client
+            .write_wal_segment(&pos, vec![1; 100])
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), Error::STORAGE_NOT_FOUND);
+        assert!(client.manifest(&generation).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rekey() -> Result<()> {
+        let root = "/test_rekey";
+        let (old_key, new_key) = ("01".repeat(32), "02".repeat(32));
+        let old = encrypted_memory_client(root, &old_key, &[])?;
+        let generation = Generation::new();
+        let pos = WalGenerationPos {
+            generation: generation.clone(),
+            index: 0,
+            offset: 0,
+        };
+        old.write_snapshot(&pos, 4096, tempfile()?).await?;
+        old.write_wal_segment(&pos, vec![1; 100]).await?;
+        let manifest = old.manifest(&generation).await?.unwrap();
+        assert!(manifest.wrapped_key.is_some());
+
+        // the previous key reads generations not rekeyed yet.
+        let new = encrypted_memory_client(root, &new_key, &[&old_key])?;
+        let wal_segments = new.wal_segments(generation.as_str()).await?;
+        assert_eq!(new.read_wal_segment(&wal_segments[0]).await?, vec![1; 100]);
+
+        let result = new.rekey().await?;
+        assert_eq!(result.rekeyed, vec![generation.clone()]);
+        let result = new.rekey().await?;
+        assert!(result.rekeyed.is_empty());
+        assert_eq!(result.unchanged, vec![generation.clone()]);
+
+        // only the manifest is rewritten, the new key alone reads the generation.
+        let rekeyed = encrypted_memory_client(root, &new_key, &[])?;
+        assert_eq!(
+            rekeyed.read_wal_segment(&wal_segments[0]).await?,
+            vec![1; 100]
+        );
+        let old = encrypted_memory_client(root, &old_key, &[])?;
+        let err = old.read_wal_segment(&wal_segments[0]).await.unwrap_err();
+        assert_eq!(err.code(), Error::ENCRYPTION_KEY_REQUIRED);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_objects_in_batches() -> Result<()> {
+        let config: StorageConfig = toml::from_str(
+            "name = \"memory\"\nparams.type = \"Memory\"\nparams.root = \"/test_remove_objects\"\ndelete_concurrency = 2",
+        )
+        .unwrap();
+        let client = StorageClient::try_create("/tmp/test.db".to_string(), config)?;
+        let generation = Generation::new();
+        let mut paths = vec![];
+        for index in 0..5 {
+            let pos = WalGenerationPos {
+                generation: generation.clone(),
+                index,
+                offset: 0,
+            };
+            client.write_wal_segment(&pos, vec![]).await?;
+            paths.push(walsegment_file(
+                &client.db_dir,
+                generation.as_str(),
+                index,
+                0,
+                client.compression,
+            ));
+        }
+        assert_eq!(client.wal_segments(generation.as_str()).await?.len(), 5);
+
+        // more objects than the batch size are all removed.
+        assert_eq!(client.remove_objects(paths).await?, 5);
+        assert!(client.wal_segments(generation.as_str()).await?.is_empty());
+        assert_eq!(client.remove_objects(vec![]).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_heartbeat() -> Result<()> {
+        let client = memory_client("/test_write_heartbeat")?;
+        let read_heartbeat = || async {
+            let data = client
+                .operator
+                .read(&heartbeat_file(&client.db_dir))
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&data.to_vec()).unwrap()
+        };
+
+        let before = Utc::now();
+        client.write_heartbeat().await?;
+        let heartbeat = read_heartbeat().await;
+        let timestamp: DateTime<Utc> = heartbeat["timestamp"].as_str().unwrap().parse().unwrap();
+        assert!(timestamp >= before);
+        assert!(!heartbeat["host"].as_str().unwrap().is_empty());
+
+        // the heartbeat is overwritten, and is not taken as a generation.
+        client.write_heartbeat().await?;
+        let next: DateTime<Utc> = read_heartbeat().await["timestamp"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(next >= timestamp);
+        let options = RestoreOptions::parse_from(["restore", "--db", "test.db"]);
+        assert!(client.restore_info(&options).await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_remove_wal_segments_before() -> Result<()> {
+        let client = memory_client("/test_remove_wal_segments_before")?;
+        let generation = Generation::new();
+        let pos = |index, offset| WalGenerationPos {
+            generation: generation.clone(),
+            index,
+            offset,
+        };
+
+        client.write_snapshot(&pos(2, 0), 4096, tempfile()?).await?;
+        for (index, offset) in [(0, 0), (1, 0), (1, 4152), (2, 0), (2, 4152), (3, 0)] {
+            client
+                .write_wal_segment(&pos(index, offset), vec![])
+                .await?;
+        }
+
+        assert_eq!(
+            client
+                .remove_wal_segments_before(generation.as_str(), 2)
+                .await?,
+            3
+        );
+        let mut wal_segments: Vec<(u64, u64)> = client
+            .wal_segments(generation.as_str())
+            .await?
+            .iter()
+            .map(|wal_segment| (wal_segment.index, wal_segment.offset))
+            .collect();
+        wal_segments.sort();
+        assert_eq!(wal_segments, vec![(2, 0), (2, 4152), (3, 0)]);
+
+        // the snapshot is kept, and nothing is left to remove.
+        assert_eq!(client.snapshots(generation.as_str()).await?.len(), 1);
+        assert_eq!(
+            client
+                .remove_wal_segments_before(generation.as_str(), 2)
+                .await?,
+            0
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_restore_info_before_timestamp() -> Result<()> {
+        let client = memory_client("/test_restore_info_before_timestamp")?;
+        let generation = Generation::new();
+        let pos = |index, offset| WalGenerationPos {
+            generation: generation.clone(),
+            index,
+            offset,
+        };
+        // returns the current time, with objects written before and after it
+        // in different milliseconds.
+        let now = || async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let now = Utc::now();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            now
+        };
+        let restore_info = |timestamp: Option<DateTime<Utc>>| {
+            let mut args = vec![
+                "restore".to_string(),
+                "--db".to_string(),
+                "test.db".to_string(),
+            ];
+            if let Some(timestamp) = timestamp {
+                args.push("--timestamp".to_string());
+                args.push(timestamp.to_rfc3339());
+            }
+            let options = RestoreOptions::parse_from(args);
+            let client = client.clone();
+            async move { client.restore_info(&options).await }
+        };
+
+        let before_snapshot = now().await;
+        client.write_snapshot(&pos(0, 0), 4096, tempfile()?).await?;
+        client.write_wal_segment(&pos(0, 0), vec![]).await?;
+        client.write_wal_segment(&pos(0, 4152), vec![]).await?;
+        let before_segment = now().await;
+        client.write_wal_segment(&pos(0, 8304), vec![]).await?;
+        client.write_snapshot(&pos(1, 0), 4096, tempfile()?).await?;
+        client.write_wal_segment(&pos(1, 0), vec![]).await?;
+
+        // segments and snapshots created after the timestamp are not restored.
+        let info = restore_info(Some(before_segment)).await?.unwrap();
+        assert_eq!(info.snapshot.index, 0);
+        let offsets: Vec<(u64, Vec<u64>)> = info
+            .wal_segments
+            .iter()
+            .map(|(index, segments)| (*index, segments.iter().map(|s| s.offset).collect()))
+            .collect();
+        assert_eq!(offsets, vec![(0, vec![0, 4152])]);
+
+        // without timestamp the latest snapshot is restored.
+        let info = restore_info(None).await?.unwrap();
+        assert_eq!(info.snapshot.index, 1);
+        assert_eq!(info.wal_segments.len(), 1);
+
+        // no snapshot was created before the timestamp.
+        let err = restore_info(Some(before_snapshot)).await.unwrap_err();
+        assert_eq!(err.code(), Error::NO_SNAPSHOT_ERROR);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prune_generations() -> Result<()> {
+        let client = memory_client("/test_prune_generations")?;
+        let now = Utc::now().timestamp() as u64;
+        let generation = |secs_ago: u64| {
+            let timestamp = Timestamp::from_unix(NoContext, now - secs_ago, 0);
+            Generation::try_create(&Uuid::new_v7(timestamp).simple().to_string()).unwrap()
+        };
+        // newest first.
+        let generations: Vec<Generation> = [1000, 2000, 3000, 4000, 5 * 24 * 3600]
+            .into_iter()
+            .map(generation)
+            .collect();
+        for generation in &generations {
+            let pos = WalGenerationPos {
+                generation: generation.clone(),
+                index: 0,
+                offset: 0,
+            };
+            client.write_snapshot(&pos, 4096, tempfile()?).await?;
+        }
+        assert_eq!(client.generations().await?, generations);
+
+        // generations beyond max_generations are removed, except `keep`.
+        let retention = RetentionConfig {
+            max_generations: 3,
+            ..Default::default()
+        };
+        let removed = client
+            .prune_generations(&retention, &generations[3])
+            .await?;
+        assert_eq!(removed, vec![generations[4].clone()]);
+        assert_eq!(client.generations().await?, generations[..4]);
+
+        // generations older than max_age_secs are removed.
+        let retention = RetentionConfig {
+            max_age_secs: 2500,
+            ..Default::default()
+        };
+        let removed = client
+            .prune_generations(&retention, &Generation::default())
+            .await?;
+        assert_eq!(removed, generations[2..4]);
+
+        // the newest generation is always kept.
+        let retention = RetentionConfig {
+            max_age_secs: 10,
+            ..Default::default()
+        };
+        let removed = client
+            .prune_generations(&retention, &Generation::default())
+            .await?;
+        assert_eq!(removed, generations[1..2]);
+        assert_eq!(client.generations().await?, generations[..1]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use log::debug;
+use log::error;
+
+use crate::base::decompressed_data;
+use crate::base::Generation;
+use crate::config::DbConfig;
+use crate::config::DiffOptions;
+use crate::config::StorageConfig;
+use crate::error::Error;
+use crate::error::Result;
+use crate::sqlite::db_page_size;
+use crate::storage::StorageClient;
+
+// page numbers changed between two db files, page number starts from 1.
+#[derive(Debug, Default)]
+struct PageDiff {
+    page_size: u64,
+    from_pages: u64,
+    to_pages: u64,
+    changed: Vec<u64>,
+}
+
+// compare two db files page by page, pages only exist in one file are
+// treated as changed.
+fn diff_pages<'a>(from: &'a [u8], to: &'a [u8], page_size: u64) -> PageDiff {
+    let size = page_size as usize;
+    let from_pages = from.len().div_ceil(size);
+    let to_pages = to.len().div_ceil(size);
+
+    let page = |data: &'a [u8], i: usize| data.get(i * size..data.len().min((i + 1) * size));
+
+    let mut changed = vec![];
+    for i in 0..from_pages.max(to_pages) {
+        if page(from, i) != page(to, i) {
+            changed.push(i as u64 + 1);
+        }
+    }
+
+    PageDiff {
+        page_size,
+        from_pages: from_pages as u64,
+        to_pages: to_pages as u64,
+        changed,
+    }
+}
+
+struct Diff {
+    db: String,
+    config: Vec<StorageConfig>,
+}
+
+impl Diff {
+    // read and decompress the latest snapshot of generation from the first
+    // replicate which has it.
+    async fn read_snapshot(&self, generation: &str) -> Result<Vec<u8>> {
+        let generation = Generation::try_create(generation)?;
+
+        for config in &self.config {
+            let client = StorageClient::try_create(self.db.clone(), config.clone())?;
+            let snapshot = match client.max_snapshot(generation.as_str()).await {
+                Ok(Some(snapshot)) => snapshot,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!(
+                        "db {} replicate {} read snapshot of generation {} error: {:?}",
+                        self.db, config.name, generation, e
+                    );
+                    continue;
+                }
+            };
+
+            debug!(
+                "db {} diff use snapshot {:?} in replicate {}",
+                self.db, snapshot, config.name
+            );
+            let compressed_data = client.read_snapshot(&snapshot).await?;
+            return decompressed_data(compressed_data, snapshot.compression);
+        }
+
+        error!(
+            "db {} cannot find snapshot of generation {}",
+            self.db, generation
+        );
+        Err(Error::NoSnapshotError(format!(
+            "cannot find snapshot of generation {}",
+            generation
+        )))
+    }
+
+    async fn run(&self, options: &DiffOptions) -> Result<()> {
+        let from = self.read_snapshot(&options.from).await?;
+        let to = self.read_snapshot(&options.to).await?;
+
+        let from_page_size = db_page_size(&from)?;
+        let to_page_size = db_page_size(&to)?;
+        if from_page_size != to_page_size {
+            return Err(Error::SqliteInvalidDbHeaderError(format!(
+                "page size of generation {}({}) mismatch with generation {}({})",
+                options.from, from_page_size, options.to, to_page_size
+            )));
+        }
+
+        let diff = diff_pages(&from, &to, from_page_size);
+
+        println!(
+            "db {} diff from generation {} to {}, page size: {}, pages: {} -> {}, changed: {}",
+            self.db,
+            options.from,
+            options.to,
+            diff.page_size,
+            diff.from_pages,
+            diff.to_pages,
+            diff.changed.len()
+        );
+        for page in &diff.changed {
+            println!("{}", page);
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn run_diff(config: &DbConfig, options: &DiffOptions) -> Result<()> {
+    let diff = Diff {
+        db: config.db.clone(),
+        config: config.replicate.clone(),
+    };
+
+    diff.run(options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff_pages;
+
+    #[test]
+    fn test_diff_pages() {
+        let from = [vec![0; 4], vec![1; 4], vec![2; 4]].concat();
+        let to = [vec![0; 4], vec![9; 4], vec![2; 4], vec![3; 4]].concat();
+
+        let diff = diff_pages(&from, &to, 4);
+        assert_eq!(3, diff.from_pages);
+        assert_eq!(4, diff.to_pages);
+        assert_eq!(vec![2, 4], diff.changed);
+
+        let diff = diff_pages(&to, &to, 4);
+        assert!(diff.changed.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs;
+use std::time::Duration;
+
+use log::error;
+use log::info;
+use log::warn;
+use rusqlite::backup::Backup;
+use rusqlite::backup::StepResult;
+use rusqlite::Connection;
+use tempfile::NamedTempFile;
+use tokio::time::interval;
+use tokio::time::MissedTickBehavior;
+
+use super::restore::Restore;
+use crate::base::parent_dir;
+use crate::base::Generation;
+use crate::config::DbConfig;
+use crate::config::FollowOptions;
+use crate::error::Error;
+use crate::error::Result;
+use crate::storage::RestoreInfo;
+use crate::storage::StorageClient;
+
+// position of the last wal segment applied to the follower db.
+struct FollowPosition {
+    // client of the replicate followed.
+    client: StorageClient,
+    generation: Generation,
+    index: u64,
+    // offset of the last applied wal segment of index, None if no wal
+    // segment of index has been applied.
+    offset: Option<u64>,
+    // private copy of the db wal segments are applied to, then copied into
+    // output. Nobody else opens it, so its wal can be written directly.
+    staging: NamedTempFile,
+    // output lags behind staging, e.g. its last copy failed as another
+    // writer held the lock of output.
+    output_stale: bool,
+}
+
+impl FollowPosition {
+    fn new(restore_info: RestoreInfo, client: StorageClient, staging: NamedTempFile) -> Self {
+        let snapshot = restore_info.snapshot;
+        let (index, offset) = match restore_info.wal_segments.last() {
+            Some((index, segments)) => (*index, segments.last().map(|s| s.offset)),
+            None => (snapshot.index, None),
+        };
+
+        Self {
+            client,
+            generation: snapshot.generation,
+            index,
+            offset,
+            staging,
+            output_stale: true,
+        }
+    }
+
+    fn staging_path(&self) -> &str {
+        self.staging.path().to_str().unwrap()
+    }
+
+    // copies staging into output if output lags behind.
+    fn sync_output(&mut self, output: &str) -> Result<()> {
+        if self.output_stale {
+            copy_to_output(self.staging_path(), output)?;
+            self.output_stale = false;
+        }
+        Ok(())
+    }
+}
+
+// keeps the output db up to date from replicates: restore it once, then poll
+// the replicate for new wal segments and apply them to the output db. A new
+// generation, or wal segments removed before being applied, restores the
+// output db again. Output is only written through sqlite, so it is safe for
+// readers to keep it open.
+pub async fn run_follow(config: &DbConfig, options: &FollowOptions) -> Result<()> {
+    let restore = Restore::try_create(
+        config.db.clone(),
+        config.replicate.clone(),
+        options.restore_options(&config.db),
+    )?;
+
+    let mut ticker = interval(Duration::from_secs(options.interval_secs));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut position: Option<FollowPosition> = None;
+    loop {
+        ticker.tick().await;
+
+        let result = match position.as_mut() {
+            Some(pos) => follow(&restore, pos, &options.output).await,
+            None => match restore_staging(&restore, &options.output).await {
+                Ok(pos) => {
+                    position = Some(pos);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+        };
+        if let Err(e) = result {
+            error!("follow db {} error: {:?}", config.db, e);
+            // staging cannot catch up with the replicate, restore it again.
+            if e.code() == Error::INVALID_WAL_SEGMENT_ERROR
+                || e.code() == Error::INCOMPLETE_CHECKPOINT_ERROR
+            {
+                position = None;
+            }
+        }
+
+        // a locked output is retried in the next poll.
+        if let Some(pos) = position.as_mut() {
+            if let Err(e) = pos.sync_output(&options.output) {
+                warn!(
+                    "follow db {} copy to {} error, retry in the next poll: {:?}",
+                    config.db, options.output, e
+                );
+            }
+        }
+    }
+}
+
+// restores the latest state of db into a new staging db next to output.
+async fn restore_staging(restore: &Restore, output: &str) -> Result<FollowPosition> {
+    let dir = parent_dir(output).unwrap_or(".".to_string());
+    fs::create_dir_all(&dir)?;
+    let staging = NamedTempFile::new_in(&dir)?;
+    let staging_path = staging.path().to_str().unwrap().to_string();
+
+    let (restore_info, client) = restore.restore_to(&staging_path).await?;
+
+    let position = FollowPosition::new(restore_info, client, staging);
+    info!(
+        "follow db {} restored generation {} up to index {} offset {:?}",
+        output,
+        position.generation.as_str(),
+        position.index,
+        position.offset
+    );
+    Ok(position)
+}
+
+// copies the staging db into output with the online backup of sqlite, as one
+// write transaction of output. Readers of output keep a consistent view and
+// see the new state in their next read transaction, instead of having the
+// wal of output rewritten behind their back.
+fn copy_to_output(staging: &str, output: &str) -> Result<()> {
+    let source = Connection::open(staging)?;
+    let mut connection = Connection::open(output)?;
+    {
+        let backup = Backup::new(&source, &mut connection)?;
+        match backup.step(-1)? {
+            StepResult::Done => {}
+            result => {
+                return Err(Error::DbLockedError(format!(
+                    "copy follower db to {} not done: {:?}",
+                    output, result
+                )));
+            }
+        }
+    }
+
+    // keep the wal of output small, pages still read by readers are left to
+    // a later checkpoint.
+    connection.query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |_row| Ok(()))?;
+    if let Err((_connection, e)) = connection.close() {
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+// applies the wal segments after position to the staging db.
+async fn follow(restore: &Restore, position: &mut FollowPosition, output: &str) -> Result<()> {
+    let latest = position.client.generations().await?.into_iter().next();
+    if latest.as_ref() != Some(&position.generation) {
+        info!(
+            "follow db {} generation changed from {} to {:?}, restore again",
+            output,
+            position.generation.as_str(),
+            latest.as_ref().map(|g| g.as_str())
+        );
+        *position = restore_staging(restore, output).await?;
+        return Ok(());
+    }
+
+    let mut wal_segments = position
+        .client
+        .wal_segments_from(&position.generation, position.index)
+        .await?;
+
+    // a wal index is applied as a whole from offset 0, so the index of
+    // position is applied again only if it has new wal segments.
+    if let Some((index, segments)) = wal_segments.first() {
+        if *index == position.index && segments.last().map(|s| s.offset) <= position.offset {
+            wal_segments.remove(0);
+        }
+    }
+    let (index, offset) = match wal_segments.last() {
+        Some((index, segments)) => (*index, segments.last().map(|s| s.offset)),
+        None => return Ok(()),
+    };
+
+    restore
+        .apply_wal_frames(&position.client, &wal_segments, position.staging_path())
+        .await?;
+
+    position.index = index;
+    position.offset = offset;
+    position.output_stale = true;
+    info!(
+        "follow db {} applied generation {} up to index {} offset {:?}",
+        output,
+        position.generation.as_str(),
+        index,
+        offset
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use rusqlite::Connection;
+    use tempfile::tempdir;
+
+    use super::follow;
+    use super::restore_staging;
+    use crate::config::fs_db_config;
+    use crate::config::FollowOptions;
+    use crate::database::sync_database;
+    use crate::error::Result;
+    use crate::sync::restore::Restore;
+
+    fn insert_rows(db: &str, range: std::ops::Range<i64>) -> Result<()> {
+        let connection = Connection::open(db)?;
+        for i in range {
+            connection.execute("INSERT INTO test (id) VALUES (?1)", (i,))?;
+        }
+        Ok(())
+    }
+
+    fn count(connection: &Connection) -> Result<i64> {
+        Ok(connection.query_row("SELECT COUNT(*) FROM test", [], |row| row.get(0))?)
+    }
+
+    #[tokio::test]
+    async fn test_follow_with_open_reader() -> Result<()> {
+        let dir = tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let db = path("test.db");
+        let output = path("follower.db");
+
+        let connection = Connection::open(&db)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL; CREATE TABLE test (id INTEGER PRIMARY KEY);",
+        )?;
+        insert_rows(&db, 0..100)?;
+
+        let config = fs_db_config(&db, &[&path("replica")]);
+        sync_database(config.clone()).await?;
+
+        let options = FollowOptions::parse_from(["follow", "--db", &db, "--output", &output]);
+        let restore = Restore::try_create(
+            config.db.clone(),
+            config.replicate.clone(),
+            options.restore_options(&config.db),
+        )?;
+        let mut position = restore_staging(&restore, &output).await?;
+        position.sync_output(&output)?;
+
+        // a reader in the middle of a read transaction.
+        let reader = Connection::open(&output)?;
+        reader.execute_batch("BEGIN")?;
+        assert_eq!(count(&reader)?, 100);
+
+        insert_rows(&db, 100..150)?;
+        sync_database(config.clone()).await?;
+        follow(&restore, &mut position, &output).await?;
+        position.sync_output(&output)?;
+
+        // the reader keeps its snapshot, and sees the new rows in its next
+        // read transaction.
+        assert_eq!(count(&reader)?, 100);
+        reader.execute_batch("COMMIT")?;
+        assert_eq!(count(&reader)?, 150);
+        let result: String = reader.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        assert_eq!(result, "ok");
+
+        Ok(())
+    }
+}
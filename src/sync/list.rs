@@ -0,0 +1,129 @@
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::config::DbConfig;
+use crate::config::OutputFormat;
+use crate::error::Result;
+use crate::storage::StorageClient;
+
+#[derive(Debug, Serialize)]
+pub struct SnapshotEntry {
+    pub index: u64,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+// range of wal segments in a generation, in (index, offset) order.
+#[derive(Debug, Serialize)]
+pub struct WalSegmentRange {
+    pub first_index: u64,
+    pub first_offset: u64,
+    pub last_index: u64,
+    pub last_offset: u64,
+    pub count: usize,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GenerationInfo {
+    pub db: String,
+    pub replicate: String,
+    pub generation: String,
+    pub snapshots: Vec<SnapshotEntry>,
+    pub wal_segments: Option<WalSegmentRange>,
+}
+
+async fn generations_of(
+    client: &StorageClient,
+    db: &str,
+    replicate: &str,
+) -> Result<Vec<GenerationInfo>> {
+    let mut generations = vec![];
+    for generation in client.generations().await? {
+        let mut snapshots: Vec<SnapshotEntry> = client
+            .snapshots(generation.as_str())
+            .await?
+            .into_iter()
+            .map(|snapshot| SnapshotEntry {
+                index: snapshot.index,
+                size: snapshot.size,
+                created_at: snapshot.created_at,
+            })
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.index);
+
+        let wal_segments = client.wal_segments(generation.as_str()).await?;
+        let first = wal_segments.iter().map(|w| (w.index, w.offset)).min();
+        let last = wal_segments.iter().map(|w| (w.index, w.offset)).max();
+        let wal_segments = match (first, last) {
+            (Some(first), Some(last)) => Some(WalSegmentRange {
+                first_index: first.0,
+                first_offset: first.1,
+                last_index: last.0,
+                last_offset: last.1,
+                count: wal_segments.len(),
+                size: wal_segments.iter().map(|w| w.size).sum(),
+            }),
+            _ => None,
+        };
+
+        generations.push(GenerationInfo {
+            db: db.to_string(),
+            replicate: replicate.to_string(),
+            generation: generation.as_str().to_string(),
+            snapshots,
+            wal_segments,
+        });
+    }
+
+    Ok(generations)
+}
+
+// returns generations of db in all replicates, newest first in each replicate.
+pub async fn list_generations(config: &DbConfig) -> Result<Vec<GenerationInfo>> {
+    let mut generations = vec![];
+    for replicate in &config.replicate {
+        let client = StorageClient::try_create(config.db.clone(), replicate.clone())?;
+        generations.extend(generations_of(&client, &config.db, &replicate.name).await?);
+    }
+
+    Ok(generations)
+}
+
+pub fn print_generations(generations: &[GenerationInfo], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(generations)?);
+        }
+        OutputFormat::Text => {
+            for info in generations {
+                println!(
+                    "db: {}, replicate: {}, generation: {}",
+                    info.db, info.replicate, info.generation
+                );
+                for snapshot in &info.snapshots {
+                    println!(
+                        "  snapshot index: {}, size: {}, created_at: {}",
+                        snapshot.index,
+                        snapshot.size,
+                        snapshot.created_at.to_rfc3339()
+                    );
+                }
+                if let Some(range) = &info.wal_segments {
+                    println!(
+                        "  wal segments: {}/{} - {}/{}, count: {}, size: {}",
+                        range.first_index,
+                        range.first_offset,
+                        range.last_index,
+                        range.last_offset,
+                        range.count,
+                        range.size
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,8 +1,25 @@
+mod diff;
+mod follow;
+mod list;
+mod prune;
+mod rekey;
 mod replicate;
 mod restore;
 mod shadow_wal_reader;
+mod status;
+mod thaw;
 
+pub use diff::run_diff;
+pub use follow::run_follow;
+pub use list::list_generations;
+pub use list::print_generations;
+pub use prune::run_prune;
+pub use rekey::run_rekey;
 pub use replicate::Replicate;
 pub use replicate::ReplicateCommand;
 pub use restore::run_restore;
+pub use restore::run_verify;
 pub(crate) use shadow_wal_reader::ShadowWalReader;
+pub use status::db_status;
+pub use status::print_status;
+pub use thaw::run_thaw;
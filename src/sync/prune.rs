@@ -0,0 +1,114 @@
+use crate::base::Generation;
+use crate::config::DbConfig;
+use crate::config::StorageConfig;
+use crate::error::Result;
+use crate::storage::OrphanedWalSegments;
+use crate::storage::StorageClient;
+
+// prune generations of db by the retention policy of each replicate, the
+// newest generation of each replicate is always kept, then remove wal
+// segments without a snapshot. With `dry_run` only report what would be
+// removed.
+pub async fn run_prune(config: &DbConfig, dry_run: bool) -> Result<()> {
+    for replicate in &config.replicate {
+        let client = StorageClient::try_create(config.db.clone(), replicate.clone())?;
+        if !replicate.retention.is_enabled() {
+            println!(
+                "db {} replicate {} has no retention policy, skip pruning generations",
+                config.db, replicate.name
+            );
+        } else if dry_run {
+            print_prune_plan(config, replicate, &client).await?;
+        } else {
+            let removed = client
+                .prune_generations(&replicate.retention, &Generation::default())
+                .await?;
+            println!(
+                "db {} replicate {} removed {} generations: {:?}",
+                config.db,
+                replicate.name,
+                removed.len(),
+                removed.iter().map(|g| g.as_str()).collect::<Vec<_>>()
+            );
+        }
+
+        let orphans = client.orphaned_wal_segments().await?;
+        if dry_run {
+            print_orphans(config, replicate, &orphans);
+            continue;
+        }
+        let removed = client.remove_orphaned_wal_segments(&orphans).await?;
+        println!(
+            "db {} replicate {} removed {} wal segments without snapshot",
+            config.db, replicate.name, removed
+        );
+    }
+
+    Ok(())
+}
+
+// prints the wal segments without snapshot which would be removed.
+fn print_orphans(config: &DbConfig, replicate: &StorageConfig, orphans: &[OrphanedWalSegments]) {
+    println!(
+        "db {} replicate {} would remove wal segments without snapshot of {} generations (dry run):",
+        config.db,
+        replicate.name,
+        orphans.len()
+    );
+    for orphan in orphans {
+        let bytes: u64 = orphan.wal_segments.iter().map(|w| w.size).sum();
+        println!(
+            "  {:<32}  {:>10}  {:>16}  {}",
+            orphan.generation.as_str(),
+            orphan.wal_segments.len(),
+            bytes,
+            if orphan.no_snapshot {
+                "no snapshot, whole generation"
+            } else {
+                "before the first snapshot"
+            }
+        );
+    }
+}
+
+// prints the generations which would be pruned with their objects and size.
+async fn print_prune_plan(
+    config: &DbConfig,
+    replicate: &StorageConfig,
+    client: &StorageClient,
+) -> Result<()> {
+    let generations = client
+        .generations_to_prune(&replicate.retention, &Generation::default())
+        .await?;
+
+    println!(
+        "db {} replicate {} would remove {} generations (dry run):",
+        config.db,
+        replicate.name,
+        generations.len()
+    );
+    if generations.is_empty() {
+        return Ok(());
+    }
+
+    println!("  {:<32}  {:>10}  {:>16}", "generation", "objects", "bytes");
+    let mut total_objects = 0;
+    let mut total_bytes = 0;
+    for generation in &generations {
+        let (objects, bytes) = client.generation_usage(generation).await?;
+        println!(
+            "  {:<32}  {:>10}  {:>16}",
+            generation.as_str(),
+            objects,
+            bytes
+        );
+        total_objects += objects;
+        total_bytes += bytes;
+    }
+    println!(
+        "  {:<32}  {:>10}  {:>16}",
+        "total", total_objects, total_bytes
+    );
+
+    Ok(())
+}
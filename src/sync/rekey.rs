@@ -0,0 +1,44 @@
+use crate::config::DbConfig;
+use crate::error::Result;
+use crate::storage::StorageClient;
+
+// wraps the data keys of all generations of db with the current encryption
+// key of each replicate, so previous keys can be removed from config.
+// replicates without encryption are skipped.
+pub async fn run_rekey(config: &DbConfig) -> Result<()> {
+    for replicate in &config.replicate {
+        if replicate.encryption.is_none() {
+            println!(
+                "db {} replicate {} has no encryption, skip",
+                config.db, replicate.name
+            );
+            continue;
+        }
+
+        let client = StorageClient::try_create(config.db.clone(), replicate.clone())?;
+        let result = client.rekey().await?;
+        println!(
+            "db {} replicate {} rekeyed {} generations, {} already use the current key: {:?}",
+            config.db,
+            replicate.name,
+            result.rekeyed.len(),
+            result.unchanged.len(),
+            result
+                .rekeyed
+                .iter()
+                .map(|g| g.as_str())
+                .collect::<Vec<_>>()
+        );
+        if !result.skipped.is_empty() {
+            println!(
+                "db {} replicate {} skipped {} generations without data key, they are still read with the key: {:?}",
+                config.db,
+                replicate.name,
+                result.skipped.len(),
+                result.skipped.iter().map(|g| g.as_str()).collect::<Vec<_>>()
+            );
+        }
+    }
+
+    Ok(())
+}
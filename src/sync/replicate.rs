@@ -1,17 +1,31 @@
+use std::cmp::max;
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
+use chrono::Utc;
 use log::debug;
 use log::error;
 use log::info;
 use parking_lot::RwLock;
+use serde::Deserialize;
+use serde::Serialize;
 use tokio::select;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
 use tokio::task::JoinHandle;
+use tokio::time::interval;
+use tokio::time::MissedTickBehavior;
 
 use super::ShadowWalReader;
 use crate::base::compress_buffer;
-use crate::base::decompressed_data;
+use crate::base::replicate_position_file;
+use crate::base::shadow_wal_file;
 use crate::base::Generation;
 use crate::config::StorageConfig;
 use crate::database::DatabaseInfo;
@@ -19,17 +33,25 @@ use crate::database::DbCommand;
 use crate::database::WalGenerationPos;
 use crate::error::Error;
 use crate::error::Result;
+use crate::metrics::register_replicate;
+use crate::metrics::ReplicateStats;
 use crate::sqlite::align_frame;
+use crate::sqlite::checksum;
+use crate::sqlite::read_checksum_before;
 use crate::sqlite::WALFrame;
 use crate::sqlite::WALHeader;
+use crate::sqlite::WAL_FRAME_HEADER_SIZE;
 use crate::storage::SnapshotInfo;
 use crate::storage::StorageClient;
-use crate::storage::WalSegmentInfo;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum ReplicateCommand {
     DbChanged(WalGenerationPos),
-    Snapshot((WalGenerationPos, Vec<u8>)),
+    // position and the temp file of the compressed snapshot.
+    Snapshot((WalGenerationPos, File)),
+    // replies the error of the last handled command once the commands queued
+    // before it have been handled.
+    Flush(oneshot::Sender<Result<()>>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -38,6 +60,59 @@ enum ReplicateState {
     WaitSnapshot,
 }
 
+// replicate position persisted under meta dir, so that a restarted replicate
+// resumes without recalculating it from the remote wal segments. The name and
+// params of the replicate are recorded, the file is indexed by the position of
+// the replicate in db config, which may be of another replicate after the
+// config changed.
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedPosition {
+    #[serde(default)]
+    replicate: String,
+    #[serde(default)]
+    params: String,
+    generation: String,
+    index: u64,
+    offset: u64,
+}
+
+fn load_position(path: &str, config: &StorageConfig) -> Result<Option<WalGenerationPos>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let persisted: PersistedPosition = serde_json::from_slice(&data)?;
+    if persisted.replicate != config.name || persisted.params != config.params.to_string() {
+        info!(
+            "position file {} is of replicate {} {}, not of replicate {}, discard it",
+            path, persisted.replicate, persisted.params, config.name
+        );
+        return Ok(None);
+    }
+
+    Ok(Some(WalGenerationPos {
+        generation: Generation::try_create(&persisted.generation)?,
+        index: persisted.index,
+        offset: persisted.offset,
+    }))
+}
+
+// write to a temp file then rename, so a crash never leaves a partial file.
+fn save_position(path: &str, config: &StorageConfig, pos: &WalGenerationPos) -> Result<()> {
+    let persisted = PersistedPosition {
+        replicate: config.name.clone(),
+        params: config.params.to_string(),
+        generation: pos.generation.as_str().to_string(),
+        index: pos.index,
+        offset: pos.offset,
+    };
+    let temp_path = format!("{}.tmp", path);
+    fs::write(&temp_path, serde_json::to_vec(&persisted)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub struct Replicate {
     db: String,
@@ -48,6 +123,14 @@ pub struct Replicate {
     state: ReplicateState,
     info: DatabaseInfo,
     config: StorageConfig,
+    stats: Arc<ReplicateStats>,
+    position_file: String,
+    // false until the generation of a persisted position is found remotely.
+    position_verified: bool,
+    // error of the last handled command, replied to `Flush`.
+    last_error: Option<String>,
+    // when the status object was written last time.
+    last_status_at: Option<Instant>,
 }
 
 impl Replicate {
@@ -58,15 +141,34 @@ impl Replicate {
         db_notifier: Sender<DbCommand>,
         info: DatabaseInfo,
     ) -> Result<Self> {
+        let position_file = replicate_position_file(&info.meta_dir, index);
+        let persisted = match load_position(&position_file, &config) {
+            Ok(persisted) => persisted,
+            Err(e) => {
+                error!(
+                    "db {} replicate {} load position from {} error: {:?}",
+                    db, config.name, position_file, e
+                );
+                None
+            }
+        };
+        let position_verified = persisted.is_none();
+        let position = Arc::new(RwLock::new(persisted.unwrap_or_default()));
+        let stats = register_replicate(&db, &config.name, position.clone());
         Ok(Self {
             db: db.clone(),
             index,
-            position: Arc::new(RwLock::new(WalGenerationPos::default())),
+            position,
             db_notifier,
             client: StorageClient::try_create(db, config.clone())?,
             config,
             state: ReplicateState::WaitDbChanged,
             info,
+            stats,
+            position_file,
+            position_verified,
+            last_error: None,
+            last_status_at: None,
         })
     }
 
@@ -83,15 +185,141 @@ impl Replicate {
     pub async fn main(s: Replicate, rx: Receiver<ReplicateCommand>) -> Result<()> {
         let mut rx = rx;
         let mut s = s;
+        let heartbeat_interval_secs = s.config.heartbeat_interval_secs;
+        let mut heartbeat = interval(Duration::from_secs(max(heartbeat_interval_secs, 1)));
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let max_wal_segment_age_secs = s.config.max_wal_segment_age_secs;
+        let mut wal_segment_age =
+            interval(Duration::from_secs(max(max_wal_segment_age_secs / 2, 1)));
+        wal_segment_age.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let retention_enabled = s.config.retention.is_enabled();
+        let mut retention = interval(Duration::from_secs(max(
+            s.config.retention.check_interval_secs,
+            1,
+        )));
+        retention.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let compaction_enabled = s.config.compaction.is_enabled();
+        let mut compaction = interval(Duration::from_secs(max(
+            s.config.compaction.interval_secs,
+            1,
+        )));
+        compaction.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
             select! {
-                cmd = rx.recv() => if let Some(cmd) = cmd {
-                    s.command(cmd).await?
+                cmd = rx.recv() => match cmd {
+                    Some(cmd) => s.command(cmd).await?,
+                    // db is shutting down and all commands have been handled.
+                    None => return Ok(()),
+                },
+                _ = heartbeat.tick(), if heartbeat_interval_secs > 0 => {
+                    if let Err(e) = s.client.write_heartbeat().await {
+                        error!(
+                            "db {} replicate {} write heartbeat error: {:?}",
+                            s.db, s.config.name, e
+                        );
+                    }
+                },
+                _ = wal_segment_age.tick(), if max_wal_segment_age_secs > 0 => {
+                    if let Err(e) = s.check_wal_segment_age().await {
+                        error!(
+                            "db {} replicate {} check wal segment age error: {:?}",
+                            s.db, s.config.name, e
+                        );
+                    }
+                },
+                _ = retention.tick(), if retention_enabled => {
+                    if let Err(e) = s
+                        .client
+                        .prune_generations(&s.config.retention, &s.position().generation)
+                        .await
+                    {
+                        error!(
+                            "db {} replicate {} prune generations error: {:?}",
+                            s.db, s.config.name, e
+                        );
+                    }
+                },
+                _ = compaction.tick(), if compaction_enabled => {
+                    if let Err(e) = s.compact_wal_segments().await {
+                        error!(
+                            "db {} replicate {} compact wal segments error: {:?}",
+                            s.db, s.config.name, e
+                        );
+                    }
                 }
             }
         }
     }
 
+    // take a new snapshot if the oldest wal segment after the latest snapshot
+    // is older than `max_wal_segment_age_secs`, wal segments before the new
+    // snapshot will be removed in `sync_snapshot`.
+    async fn check_wal_segment_age(&mut self) -> Result<()> {
+        if self.state != ReplicateState::WaitDbChanged {
+            return Ok(());
+        }
+        let generation = self.position().generation;
+        if generation.is_empty() {
+            return Ok(());
+        }
+
+        let snapshot = self.max_snapshot(generation.as_str()).await?;
+        let oldest = self
+            .client
+            .wal_segments(generation.as_str())
+            .await?
+            .into_iter()
+            .filter(|wal_segment| wal_segment.created_at > snapshot.created_at)
+            .map(|wal_segment| wal_segment.created_at)
+            .min();
+        let oldest = match oldest {
+            Some(oldest) => oldest,
+            None => return Ok(()),
+        };
+
+        let age = (Utc::now() - oldest).num_seconds();
+        if age < self.config.max_wal_segment_age_secs as i64 {
+            return Ok(());
+        }
+
+        info!(
+            "db {} replicate {} oldest wal segment age {}s exceeds max_wal_segment_age_secs({}), take snapshot",
+            self.db, self.config.name, age, self.config.max_wal_segment_age_secs
+        );
+        self.db_notifier
+            .send(DbCommand::Snapshot(self.index))
+            .await?;
+        self.state = ReplicateState::WaitSnapshot;
+        Ok(())
+    }
+
+    // merges small wal segments of the current generation. It runs in between
+    // syncs of this replicate, so the wal segments never change under it.
+    async fn compact_wal_segments(&self) -> Result<()> {
+        if self.state != ReplicateState::WaitDbChanged {
+            return Ok(());
+        }
+        let generation = self.position().generation;
+        if generation.is_empty() {
+            return Ok(());
+        }
+
+        let removed = self
+            .client
+            .compact_wal_segments(generation.as_str(), self.config.compaction.target_size)
+            .await?;
+        if removed > 0 {
+            info!(
+                "db {} replicate {} compacted {} wal segments of generation {}",
+                self.db,
+                self.config.name,
+                removed,
+                generation.as_str()
+            );
+        }
+        Ok(())
+    }
+
     // returns the last snapshot in a generation.
     async fn max_snapshot(&self, generation: &str) -> Result<SnapshotInfo> {
         let snapshots = self.client.snapshots(generation).await?;
@@ -110,55 +338,8 @@ impl Replicate {
         Ok(snapshots[max_index].clone())
     }
 
-    // returns the highest WAL segment in a generation.
-    async fn max_wal_segment(&self, generation: &str) -> Result<WalSegmentInfo> {
-        let wal_segments = self.client.wal_segments(generation).await?;
-        if wal_segments.is_empty() {
-            return Err(Error::NoWalsegmentError(generation));
-        }
-        let mut max_index = 0;
-        let mut max_wg_index = 0;
-        for (i, wg) in wal_segments.iter().enumerate() {
-            if wg.index > max_wg_index {
-                max_wg_index = wg.index;
-                max_index = i;
-            }
-        }
-
-        Ok(wal_segments[max_index].clone())
-    }
-
     async fn calculate_generation_position(&self, generation: &str) -> Result<WalGenerationPos> {
-        // Fetch last snapshot. Return error if no snapshots exist.
-        let snapshot = self.max_snapshot(generation).await?;
-        let generation = Generation::try_create(generation)?;
-
-        // Determine last WAL segment available.
-        let segment = self.max_wal_segment(generation.as_str()).await;
-        let segment = match segment {
-            Err(e) => {
-                if e.code() == Error::NO_WALSEGMENT_ERROR {
-                    // Use snapshot if none exist.
-                    return Ok(WalGenerationPos {
-                        generation,
-                        index: snapshot.index,
-                        offset: 0,
-                    });
-                } else {
-                    return Err(e);
-                }
-            }
-            Ok(segment) => segment,
-        };
-
-        let compressed_data = self.client.read_wal_segment(&segment).await?;
-        let decompressed_data = decompressed_data(compressed_data)?;
-
-        Ok(WalGenerationPos {
-            generation: segment.generation.clone(),
-            index: segment.index,
-            offset: segment.offset + decompressed_data.len() as u64,
-        })
+        self.client.generation_position(generation).await
     }
 
     async fn sync_wal(&mut self) -> Result<()> {
@@ -171,6 +352,14 @@ impl Replicate {
 
         debug!("db {} write wal segment position {:?}", self.db, init_pos,);
 
+        // running checksum and byte order of frames, only tracked if
+        // `verify_wal_checksum` is enabled.
+        let mut running_checksum = if self.config.verify_wal_checksum {
+            Some(self.shadow_wal_checksum(&init_pos)?)
+        } else {
+            None
+        };
+
         // Copy header if at offset zero.
         let mut salt1 = 0;
         let mut salt2 = 0;
@@ -181,9 +370,10 @@ impl Replicate {
             data.extend_from_slice(&wal_header.data);
         }
 
-        // Copy frames.
+        // Copy frames, a trailing partial frame is left for the next sync.
+        let frame_size = WAL_FRAME_HEADER_SIZE + self.info.page_size;
         loop {
-            if reader.left == 0 {
+            if reader.left < frame_size {
                 break;
             }
 
@@ -202,28 +392,102 @@ impl Replicate {
             salt1 = wal_frame.salt1;
             salt2 = wal_frame.salt2;
 
+            if let Some((ck1, ck2, is_big_endian)) = running_checksum {
+                let (ck1, ck2) = checksum(&wal_frame.data[0..8], ck1, ck2, is_big_endian);
+                let (ck1, ck2) = checksum(&wal_frame.data[24..], ck1, ck2, is_big_endian);
+                if ck1 != wal_frame.checksum1 || ck2 != wal_frame.checksum2 {
+                    return Err(Error::SqliteInvalidWalFrameError(format!(
+                        "db {} WAL frame checksum mismatch at offset {}",
+                        self.db, pos.offset
+                    )));
+                }
+                running_checksum = Some((ck1, ck2, is_big_endian));
+            }
+
             data.extend_from_slice(&wal_frame.data);
         }
-        let compressed_data = compress_buffer(&data)?;
+        let compressed_data = compress_buffer(
+            &data,
+            self.config.compression,
+            self.config.compression_level,
+        )?;
 
+        let size = compressed_data.len() as u64;
         self.client
             .write_wal_segment(&init_pos, compressed_data)
             .await?;
+        self.stats.uploaded_bytes.fetch_add(size, Ordering::Relaxed);
 
         // update position
-        let mut position = self.position.write();
-        *position = reader.position();
+        self.set_position(reader.position());
         Ok(())
     }
 
+    // returns the checksum before `pos` in its shadow wal file, and whether
+    // the checksums are big endian.
+    fn shadow_wal_checksum(&self, pos: &WalGenerationPos) -> Result<(u32, u32, bool)> {
+        let file_name = shadow_wal_file(&self.info.meta_dir, pos.generation.as_str(), pos.index);
+        let wal_header = WALHeader::read(&file_name)?;
+        let (ck1, ck2) = read_checksum_before(&file_name, self.info.page_size, pos.offset)?;
+        Ok((ck1, ck2, wal_header.is_big_endian))
+    }
+
     pub fn position(&self) -> WalGenerationPos {
         let position = self.position.read();
         position.clone()
     }
 
+    // update and persist the position, a failed persist only costs
+    // recalculating the position after restart.
+    fn set_position(&self, pos: WalGenerationPos) {
+        if let Err(e) = save_position(&self.position_file, &self.config, &pos) {
+            error!(
+                "db {} replicate {} save position to {} error: {:?}",
+                self.db, self.config.name, self.position_file, e
+            );
+        }
+        *self.position.write() = pos;
+    }
+
     fn reset_position(&self) {
         let mut position = self.position.write();
         *position = WalGenerationPos::default();
+        if let Err(e) = fs::remove_file(&self.position_file) {
+            if e.kind() != ErrorKind::NotFound {
+                error!(
+                    "db {} replicate {} remove position file {} error: {:?}",
+                    self.db, self.config.name, self.position_file, e
+                );
+            }
+        }
+    }
+
+    // the position loaded from meta dir is trusted only if its generation
+    // still exists in the replicate, and it is not beyond the end of the wal
+    // segments replicated.
+    async fn verify_position(&mut self) -> Result<()> {
+        let position = self.position();
+        let snapshots = self.client.snapshots(position.generation.as_str()).await?;
+        if snapshots.is_empty() {
+            info!(
+                "db {} replicate {} generation of persisted position {:?} not found, recalculate position",
+                self.db, self.config.name, position
+            );
+            self.reset_position();
+        } else {
+            let replicated = self
+                .calculate_generation_position(position.generation.as_str())
+                .await?;
+            if (position.index, position.offset) > (replicated.index, replicated.offset) {
+                info!(
+                    "db {} replicate {} persisted position {:?} is beyond replicated position {:?}, recalculate position",
+                    self.db, self.config.name, position, replicated
+                );
+                self.reset_position();
+            }
+        }
+        self.position_verified = true;
+        Ok(())
     }
 
     async fn command(&mut self, cmd: ReplicateCommand) -> Result<()> {
@@ -231,31 +495,98 @@ impl Replicate {
             ReplicateCommand::DbChanged(pos) => {
                 if let Err(e) = self.sync(pos).await {
                     error!("sync db error: {:?}", e);
+                    self.stats.sync_failure.fetch_add(1, Ordering::Relaxed);
+                    self.last_error = Some(format!("sync db error: {}", e));
                     // Clear last position if if an error occurs during sync.
                     self.reset_position();
+                } else {
+                    self.stats.sync_success.fetch_add(1, Ordering::Relaxed);
+                    self.stats
+                        .last_sync_timestamp
+                        .store(Utc::now().timestamp(), Ordering::Relaxed);
+                    self.last_error = None;
+                    self.write_status().await;
                 }
             }
-            ReplicateCommand::Snapshot((pos, compressed_data)) => {
-                if let Err(e) = self.sync_snapshot(pos, compressed_data).await {
+            ReplicateCommand::Snapshot((pos, compressed_file)) => {
+                if let Err(e) = self.sync_snapshot(pos, compressed_file).await {
                     error!("sync db snapshot error: {:?}", e);
+                    self.last_error = Some(format!("sync db snapshot error: {}", e));
+                } else {
+                    self.last_error = None;
+                    self.write_status().await;
                 }
             }
+            ReplicateCommand::Flush(reply) => {
+                let result = match &self.last_error {
+                    Some(e) => Err(Error::ReplicateSyncError(format!(
+                        "db {} replicate {} {}",
+                        self.db, self.config.name, e
+                    ))),
+                    None => Ok(()),
+                };
+                // the receiver may have given up waiting.
+                let _ = reply.send(result);
+            }
         }
         Ok(())
     }
 
-    async fn sync_snapshot(
-        &mut self,
-        pos: WalGenerationPos,
-        compressed_data: Vec<u8>,
-    ) -> Result<()> {
+    // writes the status object at most once per `status_interval_secs`, it is
+    // best effort and never fails the sync.
+    async fn write_status(&mut self) {
+        let interval_secs = self.config.status_interval_secs;
+        if interval_secs == 0 {
+            return;
+        }
+        if let Some(last) = self.last_status_at {
+            if last.elapsed() < Duration::from_secs(interval_secs) {
+                return;
+            }
+        }
+
+        let position = self.position();
+        if position.is_empty() {
+            return;
+        }
+        self.last_status_at = Some(Instant::now());
+        if let Err(e) = self.client.write_status(&position).await {
+            error!(
+                "db {} replicate {} write status error: {:?}",
+                self.db, self.config.name, e
+            );
+        }
+    }
+
+    async fn sync_snapshot(&mut self, pos: WalGenerationPos, compressed_file: File) -> Result<()> {
         info!("db {} sync snapshot {:?}", self.db, pos);
         debug_assert_eq!(self.state, ReplicateState::WaitSnapshot);
         if pos.offset == 0 {
             return Ok(());
         }
 
-        let _ = self.client.write_snapshot(&pos, compressed_data).await?;
+        let snapshot = self
+            .client
+            .write_snapshot(&pos, self.info.page_size, compressed_file)
+            .await?;
+        self.stats
+            .uploaded_bytes
+            .fetch_add(snapshot.size, Ordering::Relaxed);
+        self.stats.snapshots.fetch_add(1, Ordering::Relaxed);
+
+        // remove wal segments not needed by the new snapshot.
+        if self.config.max_wal_segment_age_secs > 0 {
+            if let Err(e) = self
+                .client
+                .remove_wal_segments_before(snapshot.generation.as_str(), snapshot.index)
+                .await
+            {
+                error!(
+                    "db {} replicate {} remove wal segments before snapshot {:?} error: {:?}",
+                    self.db, self.config.name, snapshot, e
+                );
+            }
+        }
 
         // change state from WaitSnapshot to WaitDbChanged
         self.state = ReplicateState::WaitDbChanged;
@@ -276,6 +607,10 @@ impl Replicate {
             return Ok(());
         }
 
+        if !self.position_verified {
+            self.verify_position().await?;
+        }
+
         // Create a new snapshot and update the current replica position if
         // the generation on the database has changed.
         let generation = pos.generation.clone();
@@ -312,7 +647,7 @@ impl Replicate {
                 "db {} replicate {} calc position: {:?}",
                 self.db, self.config.name, pos
             );
-            *self.position.write() = pos;
+            self.set_position(pos);
         }
 
         // Read all WAL files since the last position.
@@ -326,3 +661,110 @@ impl Replicate {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+    use tempfile::tempfile;
+    use tokio::sync::mpsc;
+
+    use super::load_position;
+    use super::save_position;
+    use super::Replicate;
+    use crate::base::compress_buffer;
+    use crate::base::replicate_position_file;
+    use crate::base::Generation;
+    use crate::config::fs_storage_config;
+    use crate::database::DatabaseInfo;
+    use crate::database::WalGenerationPos;
+    use crate::error::Result;
+
+    #[test]
+    fn test_persist_position() -> Result<()> {
+        let dir = tempdir()?;
+        let path = replicate_position_file(dir.path().to_str().unwrap(), 0);
+        let config = fs_storage_config("a", "/tmp/a");
+        assert!(load_position(&path, &config)?.is_none());
+
+        let pos = WalGenerationPos {
+            generation: Generation::new(),
+            index: 2,
+            offset: 4152,
+        };
+        save_position(&path, &config, &pos)?;
+
+        let loaded = load_position(&path, &config)?.unwrap();
+        assert_eq!(loaded.generation, pos.generation);
+        assert_eq!(loaded.index, pos.index);
+        assert_eq!(loaded.offset, pos.offset);
+
+        // the position of another replicate at the same index is discarded.
+        assert!(load_position(&path, &fs_storage_config("b", "/tmp/a"))?.is_none());
+        assert!(load_position(&path, &fs_storage_config("a", "/tmp/b"))?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_verify_position() -> Result<()> {
+        let dir = tempdir()?;
+        let meta_dir = dir.path().join("meta");
+        fs::create_dir_all(&meta_dir)?;
+        let info = DatabaseInfo {
+            meta_dir: meta_dir.to_str().unwrap().to_string(),
+            page_size: 4096,
+        };
+        let config = fs_storage_config("a", dir.path().join("a").to_str().unwrap());
+        let path = replicate_position_file(&info.meta_dir, 0);
+        let (db_notifier, _db_receiver) = mpsc::channel(8);
+
+        // replicate a snapshot and a wal segment of 100 bytes.
+        let generation = Generation::new();
+        let pos = |index, offset| WalGenerationPos {
+            generation: generation.clone(),
+            index,
+            offset,
+        };
+        let replicate = Replicate::new(
+            config.clone(),
+            "test.db".to_string(),
+            0,
+            db_notifier.clone(),
+            info.clone(),
+        )?;
+        replicate
+            .client
+            .write_snapshot(&pos(0, 0), 4096, tempfile()?)
+            .await?;
+        let data = compress_buffer(&[1; 100], config.compression, config.compression_level)?;
+        replicate.client.write_wal_segment(&pos(0, 0), data).await?;
+
+        // a position at the end of the replicated wal segments is kept.
+        save_position(&path, &config, &pos(0, 100))?;
+        let mut replicate = Replicate::new(
+            config.clone(),
+            "test.db".to_string(),
+            0,
+            db_notifier.clone(),
+            info.clone(),
+        )?;
+        replicate.verify_position().await?;
+        assert_eq!(replicate.position().offset, 100);
+
+        // a position beyond them is recalculated.
+        save_position(&path, &config, &pos(0, 200))?;
+        let mut replicate = Replicate::new(
+            config.clone(),
+            "test.db".to_string(),
+            0,
+            db_notifier.clone(),
+            info.clone(),
+        )?;
+        replicate.verify_position().await?;
+        assert_eq!(replicate.position(), WalGenerationPos::default());
+        assert!(load_position(&path, &config)?.is_none());
+
+        Ok(())
+    }
+}
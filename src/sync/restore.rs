@@ -1,5 +1,14 @@
+use std::cmp::min;
 use std::fs;
+use std::fs::File;
 use std::fs::OpenOptions;
+use std::io;
+use std::io::stderr;
+use std::io::stdout;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::IsTerminal;
+use std::io::Read;
 use std::io::Write;
 
 use log::debug;
@@ -7,22 +16,85 @@ use log::error;
 use rusqlite::Connection;
 use tempfile::NamedTempFile;
 
-use crate::base::decompressed_data;
+use crate::base::decompress_to;
 use crate::base::parent_dir;
+use crate::base::Generation;
 use crate::config::DbConfig;
 use crate::config::RestoreOptions;
 use crate::config::StorageConfig;
+use crate::config::VerifyOptions;
 use crate::error::Error;
 use crate::error::Result;
+use crate::sqlite::db_page_size;
+use crate::sqlite::WALHeader;
+use crate::sqlite::DB_HEADER_SIZE;
+use crate::sqlite::WAL_HEADER_SIZE;
 use crate::storage::RestoreInfo;
 use crate::storage::RestoreWalSegments;
 use crate::storage::SnapshotInfo;
 use crate::storage::StorageClient;
-use crate::storage::WalSegmentInfo;
 
+static WAL_CHECKPOINT_FULL: &str = "PRAGMA wal_checkpoint(FULL);";
 static WAL_CHECKPOINT_TRUNCATE: &str = "PRAGMA wal_checkpoint(TRUNCATE);";
+// max number of integrity errors reported in verify error
+static MAX_REPORTED_VERIFY_ERRORS: usize = 5;
+static DROP_INTERNAL_TABLES: &str =
+    "DROP TABLE IF EXISTS _replited_seq; DROP TABLE IF EXISTS _replited_lock;";
+
+// prints restore progress to stderr. On a terminal the progress line is
+// updated in place, otherwise only the final state of each step is printed
+// so that logs are not flooded.
+struct Progress {
+    quiet: bool,
+    terminal: bool,
+    last_percent: Option<u64>,
+}
+
+impl Progress {
+    fn new(quiet: bool) -> Self {
+        Self {
+            quiet,
+            terminal: stderr().is_terminal(),
+            last_percent: None,
+        }
+    }
+
+    fn download_snapshot(&mut self, downloaded: u64, total: u64) {
+        if self.quiet {
+            return;
+        }
+        let percent = min((downloaded * 100).checked_div(total).unwrap_or(100), 100);
+        if self.last_percent == Some(percent) {
+            return;
+        }
+        self.last_percent = Some(percent);
+
+        if self.terminal {
+            eprint!("\rdownloading snapshot: {}% of {} bytes", percent, total);
+            if percent == 100 {
+                eprintln!();
+            }
+        } else if percent == 100 {
+            eprintln!("downloaded snapshot of {} bytes", total);
+        }
+    }
 
-struct Restore {
+    fn apply_wal_index(&self, index: u64, n: usize, total: usize) {
+        if self.quiet {
+            return;
+        }
+        if self.terminal {
+            eprint!("\rapplied wal index {} ({} of {})", index, n, total);
+            if n == total {
+                eprintln!();
+            }
+        } else if n == total {
+            eprintln!("applied {} wal indexes", total);
+        }
+    }
+}
+
+pub(super) struct Restore {
     db: String,
     config: Vec<StorageConfig>,
     options: RestoreOptions,
@@ -41,12 +113,42 @@ impl Restore {
         })
     }
 
+    // returns restore info of the generation in options from the first replicate
+    // which has snapshots of it.
+    async fn decide_generation_restore_info(
+        &self,
+        generation: &str,
+    ) -> Result<Option<(RestoreInfo, StorageClient)>> {
+        let generation = Generation::try_create(generation)?;
+
+        for config in &self.config {
+            let client = StorageClient::try_create(self.db.clone(), config.clone())?;
+            if let Some(restore_info) = client.restore_info_of(&generation, &self.options).await? {
+                return Ok(Some((restore_info, client)));
+            }
+        }
+
+        let msg = format!(
+            "db {} generation {} has no snapshot to restore in any replicate",
+            self.db,
+            generation.as_str()
+        );
+        error!("{}", msg);
+        Err(Error::NoSnapshotError(msg))
+    }
+
     pub async fn decide_restore_info(&self) -> Result<Option<(RestoreInfo, StorageClient)>> {
+        if !self.options.generation.is_empty() {
+            return self
+                .decide_generation_restore_info(&self.options.generation)
+                .await;
+        }
+
         let mut latest_restore_info: Option<(RestoreInfo, StorageClient)> = None;
 
         for config in &self.config {
             let client = StorageClient::try_create(self.db.clone(), config.clone())?;
-            let restore_info = match client.restore_info().await? {
+            let restore_info = match client.restore_info(&self.options).await? {
                 Some(snapshot_into) => snapshot_into,
                 None => continue,
             };
@@ -71,24 +173,35 @@ impl Restore {
         snapshot: &SnapshotInfo,
         path: &str,
     ) -> Result<()> {
-        let compressed_data = client.read_snapshot(snapshot).await?;
-        let decompressed_data = decompressed_data(compressed_data)?;
+        // download the compressed snapshot into a temp file, then decompress it
+        // into db file, so neither of them is buffered in memory.
+        let mut progress = Progress::new(self.options.quiet);
+        let compressed_file = client
+            .download_snapshot(snapshot, &mut |downloaded| {
+                progress.download_snapshot(downloaded, snapshot.size)
+            })
+            .await?;
 
-        let mut file = OpenOptions::new()
+        let file = OpenOptions::new()
             .write(true)
             .create(true)
             .truncate(true)
             .open(path)?;
+        let mut writer = BufWriter::new(file);
 
-        file.write_all(&decompressed_data)?;
+        decompress_to(
+            BufReader::new(compressed_file),
+            snapshot.compression,
+            &mut writer,
+        )?;
+        writer.flush()?;
 
         Ok(())
     }
 
-    async fn apply_wal_frames(
+    pub(super) async fn apply_wal_frames(
         &self,
         client: &StorageClient,
-        snapshot: &SnapshotInfo,
         wal_segments: &RestoreWalSegments,
         db_path: &str,
     ) -> Result<()> {
@@ -97,82 +210,275 @@ impl Restore {
             self.db, wal_segments
         );
         let wal_file_name = format!("{}-wal", db_path);
-
-        for (index, offsets) in wal_segments {
-            let mut wal_decompressed_data = Vec::new();
-            for offset in offsets {
-                let wal_segment = WalSegmentInfo {
-                    generation: snapshot.generation.clone(),
-                    index: *index,
-                    offset: *offset,
-                    size: 0,
-                };
-
-                let compressed_data = client.read_wal_segment(&wal_segment).await?;
-                let data = decompressed_data(compressed_data)?;
-                wal_decompressed_data.extend_from_slice(&data);
-            }
-
-            // prepare db wal before open db connection
+        let page_size = read_db_page_size(db_path)?;
+
+        // Each group of wal segments is applied with a new connection: sqlite
+        // caches the wal-index in the shm file and will not rescan a wal file
+        // written behind its back, and it deletes the wal file once the last
+        // connection is closed. So neither the connection nor the wal file
+        // handle can be reused across groups, instead make sure at most one
+        // of them is opened at any time.
+        let progress = Progress::new(self.options.quiet);
+        for (n, (index, segments)) in wal_segments.iter().enumerate() {
+            // prepare db wal before open db connection, decompressed segments
+            // are written into wal file directly to avoid buffering the whole group.
             let mut wal_file = OpenOptions::new()
                 .write(true)
                 .create(true)
                 .truncate(true)
                 .open(&wal_file_name)?;
 
-            wal_file.write_all(&wal_decompressed_data)?;
+            // a compacted segment covers the segments after it which may not
+            // be removed yet, skip them.
+            let mut next_offset = 0;
+            for wal_segment in segments {
+                if wal_segment.offset < next_offset {
+                    debug!("skip wal segment {:?} covered by compaction", wal_segment);
+                    continue;
+                }
+                let compressed_data = client.read_wal_segment(wal_segment).await?;
+                let size = decompress_to(
+                    compressed_data.as_slice(),
+                    wal_segment.compression,
+                    &mut wal_file,
+                )?;
+                next_offset = wal_segment.offset + size;
+            }
+
             wal_file.flush()?;
+            drop(wal_file);
+
+            // frames of another page size would corrupt db when checkpointed.
+            let header = WALHeader::read(&wal_file_name)?;
+            if header.page_size != page_size {
+                let msg = format!(
+                    "db {} wal index {} page size {} mismatch with db page size {}",
+                    self.db, index, header.page_size, page_size
+                );
+                error!("{}", msg);
+                return Err(Error::PageSizeMismatchError(msg));
+            }
 
             let connection = Connection::open(db_path)?;
 
-            if let Err(e) = connection.query_row(WAL_CHECKPOINT_TRUNCATE, [], |_row| Ok(())) {
+            // returns (busy, frames in wal, frames checkpointed). A truncation
+            // checkpoint resets the wal and reports no frames, so checkpoint
+            // fully first and truncate the wal afterwards.
+            let result = connection.query_row(WAL_CHECKPOINT_FULL, [], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            });
+            let (busy, log, checkpointed) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    error!("checkpoint failed during restore {}:{:?}", index, segments);
+                    return Err(e.into());
+                }
+            };
+            // another connection to db keeps a stale wal-index in the -shm
+            // file, then sqlite does not see the frames written behind its back.
+            if busy != 0 || checkpointed != log || (log == 0 && next_offset > WAL_HEADER_SIZE) {
+                let msg = format!(
+                    "db {} wal index {} not applied, busy: {}, frames: {}, checkpointed: {}, is db opened by others?",
+                    self.db, index, busy, log, checkpointed
+                );
+                error!("{}", msg);
+                return Err(Error::IncompleteCheckpointError(msg));
+            }
+            connection.query_row(WAL_CHECKPOINT_TRUNCATE, [], |_row| Ok(()))?;
+
+            if let Err((_connection, e)) = connection.close() {
                 error!(
-                    "truncation checkpoint failed during restore {}:{:?}",
-                    index, offsets
+                    "close connection failed during restore {}:{:?}",
+                    index, segments
                 );
                 return Err(e.into());
             }
 
-            // connection.close().unwrap();
+            progress.apply_wal_index(*index, n + 1, wal_segments.len());
         }
 
         Ok(())
     }
 
-    pub async fn run(&self) -> Result<()> {
-        // Ensure output path does not already exist.
-        if fs::exists(&self.options.output)? {
-            println!("db {} already exists but cannot overwrite", self.db);
-            return Err(Error::OverwriteDbError("cannot overwrite exist db"));
+    // drop replited internal tables from restored db, then checkpoint so
+    // that nothing is left in the wal.
+    fn drop_internal_tables(&self, db_path: &str) -> Result<()> {
+        let connection = Connection::open(db_path)?;
+        connection.execute_batch(DROP_INTERNAL_TABLES)?;
+
+        if let Err(e) = connection.query_row(WAL_CHECKPOINT_TRUNCATE, [], |_row| Ok(())) {
+            error!(
+                "truncation checkpoint failed after drop internal tables of db {}",
+                self.db
+            );
+            return Err(e.into());
+        }
+
+        if let Err((_connection, e)) = connection.close() {
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+
+    // run integrity_check and foreign_key_check on restored db, fail if any
+    // of them reports errors.
+    fn verify_db(&self, db_path: &str) -> Result<()> {
+        let connection = Connection::open(db_path)?;
+
+        let mut stmt = connection.prepare("PRAGMA integrity_check;")?;
+        let results = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if results.len() != 1 || results[0] != "ok" {
+            let msg = format!(
+                "restored db {} integrity check failed: {}",
+                self.db,
+                results
+                    .iter()
+                    .take(MAX_REPORTED_VERIFY_ERRORS)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+            error!("{}", msg);
+            return Err(Error::RestoredDbVerifyError(msg));
         }
 
+        // each row of foreign_key_check is a violation: (table, rowid, parent, fkid)
+        let mut stmt = connection.prepare("PRAGMA foreign_key_check;")?;
+        let violations = stmt
+            .query_map([], |row| {
+                Ok(format!(
+                    "table {} rowid {:?} parent {}",
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Option<i64>>(1)?,
+                    row.get::<_, String>(2)?
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if !violations.is_empty() {
+            let msg = format!(
+                "restored db {} foreign key check failed: {}",
+                self.db,
+                violations
+                    .iter()
+                    .take(MAX_REPORTED_VERIFY_ERRORS)
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+            error!("{}", msg);
+            return Err(Error::RestoredDbVerifyError(msg));
+        }
+
+        Ok(())
+    }
+
+    // verify restored db file size matches `page_count * page_size`, this catches
+    // partial writes which `integrity_check` may miss.
+    fn verify_db_size(&self, db_path: &str) -> Result<()> {
+        let connection = Connection::open(db_path)?;
+        let page_count: u64 =
+            connection.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let page_size: u64 = connection.pragma_query_value(None, "page_size", |row| row.get(0))?;
+        drop(connection);
+
+        let expected_size = page_count * page_size;
+        let file_size = fs::metadata(db_path)?.len();
+        if file_size != expected_size {
+            let msg = format!(
+                "restored db {} size {} mismatch with page_count({}) * page_size({}) = {}",
+                self.db, file_size, page_count, page_size, expected_size
+            );
+            error!("{}", msg);
+            return Err(Error::RestoredDbSizeMismatchError(msg));
+        }
+
+        Ok(())
+    }
+
+    // restore db into path, which is an intermediate file not the output.
+    // returns the restore info and the client of the replicate restored from.
+    pub(super) async fn restore_to(&self, path: &str) -> Result<(RestoreInfo, StorageClient)> {
         let (latest_restore_info, client) = match self.decide_restore_info().await? {
             Some(latest_restore_info) => latest_restore_info,
             None => {
-                debug!("cannot find snapshot");
-                return Ok(());
+                let msg = format!("db {} cannot find snapshot to restore", self.db);
+                error!("{}", msg);
+                return Err(Error::NoSnapshotError(msg));
             }
         };
 
-        // create a temp file to write snapshot
-        let temp_file = NamedTempFile::new()?;
-        let temp_file_name = temp_file.path().to_str().unwrap().to_string();
-
-        let dir = parent_dir(&temp_file_name).unwrap();
+        let dir = parent_dir(path).unwrap();
         fs::create_dir_all(&dir)?;
+        remove_wal_files(path)?;
 
         // restore snapshot
-        self.restore_snapshot(&client, &latest_restore_info.snapshot, &temp_file_name)
+        self.restore_snapshot(&client, &latest_restore_info.snapshot, path)
             .await?;
+        if let Some(expected) = latest_restore_info.page_size {
+            let page_size = read_db_page_size(path)?;
+            if page_size != expected {
+                let msg = format!(
+                    "db {} snapshot {:?} page size {} mismatch with page size {} in manifest",
+                    self.db, latest_restore_info.snapshot, page_size, expected
+                );
+                error!("{}", msg);
+                return Err(Error::PageSizeMismatchError(msg));
+            }
+        }
 
         // apply wal frames
-        self.apply_wal_frames(
-            &client,
-            &latest_restore_info.snapshot,
-            &latest_restore_info.wal_segments,
-            &temp_file_name,
-        )
-        .await?;
+        self.apply_wal_frames(&client, &latest_restore_info.wal_segments, path)
+            .await?;
+
+        if !self.options.keep_internal_tables {
+            self.drop_internal_tables(path)?;
+        }
+
+        // verify restored db size
+        self.verify_db_size(path)?;
+
+        // all connections are closed, the wal has been checkpointed into db
+        // and the -shm file is of no use.
+        remove_wal_files(path)?;
+
+        Ok((latest_restore_info, client))
+    }
+
+    pub async fn run(&self) -> Result<()> {
+        if self.options.output_to_stdout() {
+            return self.run_to_stdout().await;
+        }
+
+        // Ensure output path does not already exist unless overwrite is allowed.
+        if !self.options.overwrite && fs::exists(&self.options.output)? {
+            error!("db {} already exists but cannot overwrite", self.db);
+            return Err(Error::OverwriteDbError("cannot overwrite exist db"));
+        }
+
+        // create a temp file to write snapshot
+        let temp_file = NamedTempFile::new()?;
+        let temp_file_name = temp_file.path().to_str().unwrap().to_string();
+
+        self.restore_to(&temp_file_name).await?;
+
+        if self.options.verify {
+            self.verify_db(&temp_file_name)?;
+        }
+
+        if self.options.overwrite {
+            remove_db_files(&self.options.output)?;
+        } else {
+            // output does not exist, but stale -wal or -shm files of a removed
+            // db may still be there.
+            remove_wal_files(&self.options.output)?;
+        }
 
         // rename the temp file to output file
         fs::rename(&temp_file_name, &self.options.output)?;
@@ -184,6 +490,77 @@ impl Restore {
 
         Ok(())
     }
+
+    // restore into a temp file, then copy it to stdout. stdout carries only
+    // the db, so the result is reported to stderr.
+    async fn run_to_stdout(&self) -> Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let temp_file_name = temp_file.path().to_str().unwrap().to_string();
+
+        self.restore_to(&temp_file_name).await?;
+
+        if self.options.verify {
+            self.verify_db(&temp_file_name)?;
+        }
+
+        let mut file = File::open(&temp_file_name)?;
+        let mut stdout = stdout().lock();
+        let size = io::copy(&mut file, &mut stdout)?;
+        stdout.flush()?;
+
+        eprintln!(
+            "restore db {} to stdout success, {} bytes",
+            self.options.db, size
+        );
+
+        Ok(())
+    }
+
+    // restore into a temp file and check it, the restored db is discarded.
+    pub async fn verify(&self) -> Result<RestoreInfo> {
+        let temp_file = NamedTempFile::new()?;
+        let temp_file_name = temp_file.path().to_str().unwrap().to_string();
+
+        let result = match self.restore_to(&temp_file_name).await {
+            Ok((restore_info, _)) => self.verify_db(&temp_file_name).map(|_| restore_info),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = remove_db_files(&temp_file_name) {
+            error!("remove verified db {} error: {:?}", temp_file_name, e);
+        }
+        result
+    }
+}
+
+// returns page size in the header of db file.
+fn read_db_page_size(db_path: &str) -> Result<u64> {
+    let mut header = [0; DB_HEADER_SIZE];
+    File::open(db_path)?.read_exact(&mut header)?;
+    db_page_size(&header)
+}
+
+// remove db file and its -wal and -shm files if exist.
+pub(super) fn remove_db_files(db_path: &str) -> Result<()> {
+    remove_file_if_exists(db_path)?;
+    remove_wal_files(db_path)
+}
+
+// remove -wal and -shm files of db if exist. sqlite trusts a -shm file left
+// by a previous db of the same path as the wal-index, so it must never be
+// left next to a restored db.
+fn remove_wal_files(db_path: &str) -> Result<()> {
+    remove_file_if_exists(&format!("{}-wal", db_path))?;
+    remove_file_if_exists(&format!("{}-shm", db_path))
+}
+
+fn remove_file_if_exists(path: &str) -> Result<()> {
+    if fs::exists(path)? {
+        debug!("remove existing file {}", path);
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
 }
 
 pub async fn run_restore(config: &DbConfig, options: &RestoreOptions) -> Result<()> {
@@ -194,3 +571,272 @@ pub async fn run_restore(config: &DbConfig, options: &RestoreOptions) -> Result<
 
     Ok(())
 }
+
+pub async fn run_verify(config: &DbConfig, options: &VerifyOptions) -> Result<()> {
+    let restore = Restore::try_create(
+        config.db.clone(),
+        config.replicate.clone(),
+        options.restore_options(&config.db),
+    )?;
+
+    match restore.verify().await {
+        Ok(restore_info) => {
+            let snapshot = &restore_info.snapshot;
+            let last_wal_segment = restore_info
+                .wal_segments
+                .last()
+                .and_then(|(_, segments)| segments.last());
+            match last_wal_segment {
+                Some(wal_segment) => eprintln!(
+                    "verify db {} pass, restorable position: generation {} snapshot index {}, last wal segment index {} offset {}",
+                    config.db,
+                    snapshot.generation.as_str(),
+                    snapshot.index,
+                    wal_segment.index,
+                    wal_segment.offset
+                ),
+                None => eprintln!(
+                    "verify db {} pass, restorable position: generation {} snapshot index {}, no wal segment",
+                    config.db,
+                    snapshot.generation.as_str(),
+                    snapshot.index
+                ),
+            }
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("verify db {} fail: {:?}", config.db, e);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::Duration;
+
+    use clap::Parser;
+    use rusqlite::Connection;
+    use tempfile::tempdir;
+    use tempfile::tempfile;
+
+    use super::run_restore;
+    use super::Restore;
+    use crate::base::Generation;
+    use crate::config::fs_db_config;
+    use crate::config::fs_storage_config;
+    use crate::config::RestoreOptions;
+    use crate::database::sync_database;
+    use crate::database::WalGenerationPos;
+    use crate::error::Error;
+    use crate::error::Result;
+    use crate::storage::StorageClient;
+
+    fn insert_rows(db: &str, range: std::ops::Range<i64>) -> Result<()> {
+        let connection = Connection::open(db)?;
+        for i in range {
+            connection.execute(
+                "INSERT INTO test (id, value) VALUES (?1, ?2)",
+                (i, format!("value-{}", i)),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn rows(db: &str) -> Result<Vec<(i64, String)>> {
+        let connection = Connection::open(db)?;
+        let mut stmt = connection.prepare("SELECT id, value FROM test ORDER BY id")?;
+        let rows = stmt
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(rows)
+    }
+
+    fn tables(db: &str) -> Result<Vec<String>> {
+        let connection = Connection::open(db)?;
+        let mut stmt = connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")?;
+        let tables = stmt
+            .query_map((), |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(tables)
+    }
+
+    #[test]
+    fn test_verify_db_size() -> Result<()> {
+        let dir = tempdir()?;
+        let db = dir.path().join("test.db").to_str().unwrap().to_string();
+        let connection = Connection::open(&db)?;
+        connection.execute_batch("CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);")?;
+        drop(connection);
+        insert_rows(&db, 0..100)?;
+
+        let options = RestoreOptions::parse_from(["restore", "--db", &db]);
+        let restore = Restore::try_create(db.clone(), vec![], options)?;
+        restore.verify_db_size(&db)?;
+
+        // a partially written page at the end of db.
+        let mut data = fs::read(&db)?;
+        data.extend_from_slice(&[0; 100]);
+        fs::write(&db, data)?;
+        let err = restore.verify_db_size(&db).unwrap_err();
+        assert_eq!(err.code(), Error::RESTORED_DB_SIZE_MISMATCH_ERROR);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_internal_tables() -> Result<()> {
+        let dir = tempdir()?;
+        let db = dir.path().join("test.db").to_str().unwrap().to_string();
+        let connection = Connection::open(&db)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);
+             CREATE TABLE _replited_seq (id INTEGER PRIMARY KEY, seq INTEGER);
+             CREATE TABLE _replited_lock (id INTEGER);",
+        )?;
+        drop(connection);
+        insert_rows(&db, 0..10)?;
+        assert_eq!(
+            tables(&db)?,
+            vec!["_replited_lock", "_replited_seq", "test"]
+        );
+
+        let options = RestoreOptions::parse_from(["restore", "--db", &db]);
+        let restore = Restore::try_create(db.clone(), vec![], options)?;
+        restore.drop_internal_tables(&db)?;
+        assert_eq!(tables(&db)?, vec!["test"]);
+
+        // the drop is checkpointed into the db file.
+        let wal = format!("{}-wal", db);
+        assert!(!fs::exists(&wal)? || fs::metadata(&wal)?.len() == 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decide_generation_restore_info() -> Result<()> {
+        let dir = tempdir()?;
+        let config = fs_storage_config("fs", dir.path().to_str().unwrap());
+        let client = StorageClient::try_create("test.db".to_string(), config.clone())?;
+        let first = Generation::new();
+        // generations created in different milliseconds are ordered.
+        thread::sleep(Duration::from_millis(2));
+        let generations = [first, Generation::new()];
+        for generation in &generations {
+            let pos = WalGenerationPos {
+                generation: generation.clone(),
+                index: 0,
+                offset: 0,
+            };
+            client.write_snapshot(&pos, 4096, tempfile()?).await?;
+        }
+
+        let restore_info = |generation: &str| {
+            let options = RestoreOptions::parse_from([
+                "restore",
+                "--db",
+                "test.db",
+                "--generation",
+                generation,
+            ]);
+            let restore = Restore::try_create("test.db".to_string(), vec![config.clone()], options);
+            async move { restore?.decide_restore_info().await }
+        };
+        // the latest generation is restored by default.
+        let (info, _) = restore_info("").await?.unwrap();
+        assert_eq!(info.snapshot.generation, generations[1]);
+        let (info, _) = restore_info(generations[0].as_str()).await?.unwrap();
+        assert_eq!(info.snapshot.generation, generations[0]);
+
+        let err = restore_info(Generation::new().as_str()).await.unwrap_err();
+        assert_eq!(err.code(), Error::NO_SNAPSHOT_ERROR);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_db() -> Result<()> {
+        let dir = tempdir()?;
+        let db = dir.path().join("test.db").to_str().unwrap().to_string();
+        let connection = Connection::open(&db)?;
+        connection.execute_batch(
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);
+             CREATE TABLE child (id INTEGER PRIMARY KEY, parent INTEGER REFERENCES test(id));",
+        )?;
+        drop(connection);
+        insert_rows(&db, 0..10)?;
+
+        let options = RestoreOptions::parse_from(["restore", "--db", &db, "--verify"]);
+        let restore = Restore::try_create(db.clone(), vec![], options)?;
+        restore.verify_db(&db)?;
+
+        // a foreign key violation fails the verification.
+        let connection = Connection::open(&db)?;
+        connection.execute("INSERT INTO child (id, parent) VALUES (1, 100)", ())?;
+        drop(connection);
+        let err = restore.verify_db(&db).unwrap_err();
+        assert_eq!(err.code(), Error::RESTORED_DB_VERIFY_ERROR);
+        assert!(err.message().contains("foreign key check failed"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replicate_restore_round_trip() -> Result<()> {
+        let dir = tempdir()?;
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let db = path("test.db");
+
+        let connection = Connection::open(&db)?;
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL; CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);",
+        )?;
+        insert_rows(&db, 0..100)?;
+
+        let config = fs_db_config(&db, &[&path("replica")]);
+        // the first sync uploads a snapshot, rows inserted after it are
+        // restored from wal segments.
+        sync_database(config.clone()).await?;
+        insert_rows(&db, 100..150)?;
+        sync_database(config.clone()).await?;
+
+        let output = path("restored.db");
+        let options = RestoreOptions::parse_from([
+            "restore",
+            "--db",
+            &db,
+            "--output",
+            &output,
+            "--quiet",
+            "--keep-internal-tables",
+        ]);
+        run_restore(&config, &options).await?;
+        assert_eq!(rows(&output)?.len(), 150);
+        assert_eq!(rows(&output)?, rows(&db)?);
+
+        // all changes are in the db file after a checkpoint, the restored db
+        // is identical to it page by page.
+        connection.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+        assert_eq!(fs::read(&output)?, fs::read(&db)?);
+
+        // an existing output is only replaced with overwrite.
+        insert_rows(&db, 150..160)?;
+        sync_database(config.clone()).await?;
+        let err = run_restore(&config, &options).await.unwrap_err();
+        assert_eq!(err.code(), Error::OVERWRITE_DB_ERROR);
+        assert_eq!(rows(&output)?.len(), 150);
+
+        let options = RestoreOptions {
+            overwrite: true,
+            ..options
+        };
+        run_restore(&config, &options).await?;
+        assert_eq!(rows(&output)?, rows(&db)?);
+
+        Ok(())
+    }
+}
@@ -1,10 +1,10 @@
+use std::cmp::min;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::io;
 use std::io::Read;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::os::unix::fs::MetadataExt;
 
 use crate::base::shadow_wal_file;
 use crate::database::DatabaseInfo;
@@ -52,7 +52,7 @@ impl ShadowWalReader {
     fn new(pos: WalGenerationPos, info: &DatabaseInfo) -> Result<ShadowWalReader> {
         let file_name = shadow_wal_file(&info.meta_dir, pos.generation.as_str(), pos.index);
         let mut file = OpenOptions::new().read(true).open(file_name)?;
-        let size = align_frame(info.page_size, file.metadata()?.size());
+        let size = align_frame(info.page_size, file.metadata()?.len());
 
         if pos.offset > size {
             return Err(Error::WalReaderOffsetTooHighError(format!(
@@ -82,15 +82,68 @@ impl Read for ShadowWalReader {
             // nothing to read
             return Ok(0);
         }
-        let n = buf.len() as u64;
-        if self.left < n {
-            return Err(std::io::Error::from(io::ErrorKind::Interrupted));
-        }
-
-        let ret = self.file.read(buf)?;
-        self.left -= n;
-        self.position.offset += n;
+        // never read beyond the aligned size, and only advance by the bytes
+        // actually read.
+        let n = min(self.left, buf.len() as u64) as usize;
+        let ret = self.file.read(&mut buf[..n])?;
+        self.left -= ret as u64;
+        self.position.offset += ret as u64;
 
         Ok(ret)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use tempfile::tempdir;
+
+    use super::ShadowWalReader;
+    use crate::base::shadow_wal_dir;
+    use crate::base::shadow_wal_file;
+    use crate::base::Generation;
+    use crate::database::DatabaseInfo;
+    use crate::database::WalGenerationPos;
+    use crate::error::Result;
+    use crate::sqlite::WAL_FRAME_HEADER_SIZE;
+    use crate::sqlite::WAL_HEADER_SIZE;
+
+    #[test]
+    fn test_shadow_wal_reader_short_read() -> Result<()> {
+        let dir = tempdir()?;
+        let info = DatabaseInfo {
+            meta_dir: dir.path().to_str().unwrap().to_string(),
+            page_size: 512,
+        };
+        let generation = Generation::new();
+        let frame_size = WAL_FRAME_HEADER_SIZE + info.page_size;
+        let size = WAL_HEADER_SIZE + 2 * frame_size;
+        fs::create_dir_all(shadow_wal_dir(&info.meta_dir, generation.as_str()))?;
+        fs::write(
+            shadow_wal_file(&info.meta_dir, generation.as_str(), 0),
+            vec![1u8; size as usize],
+        )?;
+
+        let pos = WalGenerationPos {
+            generation,
+            index: 0,
+            offset: 0,
+        };
+        let mut reader = ShadowWalReader::try_create(pos, &info)?;
+        assert_eq!(reader.left, size);
+
+        // not a multiple of the frame size, reads stop at it.
+        let left = WAL_HEADER_SIZE + frame_size + 100;
+        reader.left = left;
+        let mut buf = vec![0u8; size as usize];
+        let n = reader.read(&mut buf)?;
+        assert_eq!(n as u64, left);
+        assert_eq!(reader.left, 0);
+        assert_eq!(reader.position().offset, left);
+        assert_eq!(reader.read(&mut buf)?, 0);
+
+        Ok(())
+    }
+}
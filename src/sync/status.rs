@@ -0,0 +1,150 @@
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::config::DbConfig;
+use crate::config::OutputFormat;
+use crate::config::StorageConfig;
+use crate::database::local_wal_position;
+use crate::database::WalGenerationPos;
+use crate::error::Result;
+use crate::storage::RestorableWindow;
+use crate::storage::StorageClient;
+
+#[derive(Debug, Serialize)]
+pub struct Position {
+    pub generation: String,
+    pub index: u64,
+    pub offset: u64,
+}
+
+impl From<&WalGenerationPos> for Position {
+    fn from(pos: &WalGenerationPos) -> Self {
+        Self {
+            generation: pos.generation.as_str().to_string(),
+            index: pos.index,
+            offset: pos.offset,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplicateStatus {
+    pub db: String,
+    pub replicate: String,
+    // position of the local shadow wal.
+    pub local: Option<Position>,
+    // position replicated to the remote in the local generation.
+    pub remote: Option<Position>,
+    // number of wal indexes the remote is behind.
+    pub lag_index: Option<u64>,
+    // bytes the remote is behind, only known when both are in the same index.
+    pub lag_bytes: Option<u64>,
+    pub last_snapshot_at: Option<DateTime<Utc>>,
+    pub secs_since_last_snapshot: Option<i64>,
+    // time range db can be restored to from the replicate.
+    pub restorable: Option<RestorableWindow>,
+    // error of reading the status, e.g. the replicate is unreachable.
+    pub error: Option<String>,
+}
+
+async fn replicate_status(
+    config: &DbConfig,
+    replicate: &StorageConfig,
+    local: &Option<WalGenerationPos>,
+    status: &mut ReplicateStatus,
+) -> Result<()> {
+    let client = StorageClient::try_create(config.db.clone(), replicate.clone())?;
+    status.restorable = client.restorable_window().await?;
+
+    let local = match local {
+        Some(local) => local,
+        None => return Ok(()),
+    };
+    let generation = local.generation.as_str();
+
+    let snapshot = match client.max_snapshot(generation).await? {
+        Some(snapshot) => snapshot,
+        // nothing of the local generation has been replicated yet.
+        None => return Ok(()),
+    };
+    status.last_snapshot_at = Some(snapshot.created_at);
+    status.secs_since_last_snapshot = Some((Utc::now() - snapshot.created_at).num_seconds());
+
+    let remote = client.generation_position(generation).await?;
+    status.lag_index = Some(local.index.saturating_sub(remote.index));
+    if local.index == remote.index {
+        status.lag_bytes = Some(local.offset.saturating_sub(remote.offset));
+    }
+    status.remote = Some(Position::from(&remote));
+
+    Ok(())
+}
+
+// returns replication status of db in all replicates, by reading the meta dir
+// and the replicates only.
+pub async fn db_status(config: &DbConfig) -> Result<Vec<ReplicateStatus>> {
+    let local = local_wal_position(&config.db)?;
+
+    let mut statuses = Vec::with_capacity(config.replicate.len());
+    for replicate in &config.replicate {
+        let mut status = ReplicateStatus {
+            db: config.db.clone(),
+            replicate: replicate.name.clone(),
+            local: local.as_ref().map(Position::from),
+            ..Default::default()
+        };
+        if let Err(e) = replicate_status(config, replicate, &local, &mut status).await {
+            status.error = Some(e.message());
+        }
+        statuses.push(status);
+    }
+
+    Ok(statuses)
+}
+
+fn format_position(pos: &Option<Position>) -> String {
+    match pos {
+        Some(pos) => format!("{}/{}/{}", pos.generation, pos.index, pos.offset),
+        None => "none".to_string(),
+    }
+}
+
+pub fn print_status(statuses: &[ReplicateStatus], format: OutputFormat) -> Result<()> {
+    match format {
+        OutputFormat::Json => {
+            println!("{}", serde_json::to_string_pretty(statuses)?);
+        }
+        OutputFormat::Text => {
+            for status in statuses {
+                println!("db: {}, replicate: {}", status.db, status.replicate);
+                println!("  local position: {}", format_position(&status.local));
+                println!("  remote position: {}", format_position(&status.remote));
+                match (status.lag_index, status.lag_bytes) {
+                    (Some(lag_index), Some(lag_bytes)) => {
+                        println!("  lag: {} indexes, {} bytes", lag_index, lag_bytes)
+                    }
+                    (Some(lag_index), None) => println!("  lag: {} indexes", lag_index),
+                    _ => println!("  lag: unknown"),
+                }
+                if let (Some(at), Some(secs)) =
+                    (status.last_snapshot_at, status.secs_since_last_snapshot)
+                {
+                    println!("  last snapshot: {} ({}s ago)", at.to_rfc3339(), secs);
+                }
+                if let Some(restorable) = &status.restorable {
+                    println!(
+                        "  restorable from {} to {}",
+                        restorable.earliest.to_rfc3339(),
+                        restorable.latest.to_rfc3339()
+                    );
+                }
+                if let Some(error) = &status.error {
+                    println!("  error: {}", error);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use crate::config::DbConfig;
+use crate::config::ThawOptions;
+use crate::error::Result;
+use crate::storage::StorageClient;
+
+// request to restore the archived objects of a generation, so that a later
+// restore can read them. replicates without archive storage class are skipped.
+pub async fn run_thaw(config: &DbConfig, options: &ThawOptions) -> Result<()> {
+    for replicate in &config.replicate {
+        let client = StorageClient::try_create(config.db.clone(), replicate.clone())?;
+        let result = client
+            .thaw_generation(&options.generation, options.days, &options.tier)
+            .await?;
+        match result {
+            Some(result) => println!(
+                "db {} replicate {} generation {} thaw result: {:?}",
+                config.db, replicate.name, options.generation, result
+            ),
+            None => println!(
+                "db {} replicate {} has no archive storage class, skip",
+                config.db, replicate.name
+            ),
+        }
+    }
+
+    Ok(())
+}
@@ -47,9 +47,16 @@ class Test:
 
         print("finish insert test data, total: ", num_records)
 
+    # delete some data then vacuum to shrink the db file
+    def delete_and_vacuum(self, max_value):
+        self.cursor.execute('DELETE FROM random_data WHERE value < ?', (max_value,))
+        self.conn.commit()
+        self.conn.execute('VACUUM')
+        print("finish delete data with value < ", max_value, " and vacuum")
+
     def query_data(self):
         cursor = self.conn.cursor()
-        cursor.execute('SELECT * FROM random_data order by value')
+        cursor.execute('SELECT * FROM random_data order by value, id')
         return cursor.fetchall()
 
 class ConfigGenerator:
@@ -169,11 +176,35 @@ def test_restore(p, config_file, root, exp_data):
 
     conn = sqlite3.connect(output)
     cursor = conn.cursor()
-    cursor.execute('SELECT * FROM random_data order by value')
+    cursor.execute('SELECT * FROM random_data order by value, id')
     data = cursor.fetchall()
     print("data len: ", len(data), ", exp_data len: ", len(exp_data))
     assert data == exp_data
 
+# restore with `--output -`, stdout MUST carry only the restored db.
+def test_restore_stdout(p, config_file, root, exp_data):
+    db = root + "/test.db"
+    output = os.getcwd() + "/test_stdout.db"
+    try:
+        os.remove(output)
+    except:
+        pass
+    cmds = [p, "--config", config_file, "restore", "--db", db, "--output", "-"]
+    print("restore to stdout: ", " ".join(cmds))
+    with open(output, 'wb') as file:
+        subprocess.run(cmds, stdout=file, check=True)
+
+    with open(output, 'rb') as file:
+        assert file.read(16) == b"SQLite format 3\x00"
+
+    conn = sqlite3.connect(output)
+    cursor = conn.cursor()
+    assert cursor.execute('PRAGMA integrity_check').fetchone()[0] == "ok"
+    cursor.execute('SELECT * FROM random_data order by value, id')
+    data = cursor.fetchall()
+    print("stdout data len: ", len(data), ", exp_data len: ", len(exp_data))
+    assert data == exp_data
+
 def decide_config_generator(config_type):
     if config_type == "fs":
         return FsConfigGenerator()
@@ -207,10 +238,16 @@ if __name__ == '__main__':
 
     test.insert()
 
+    # vacuum between syncs, restore MUST see the shrunk db and later inserts
+    time.sleep(3)
+    test.delete_and_vacuum(number // 2)
+    time.sleep(3)
+    test.insert()
+
     time.sleep(3)
     data = test.query_data()
 
     stop_replicate()
 
     test_restore(bin_path, config.config_file, config.root, data)
-
+    test_restore_stdout(bin_path, config.config_file, config.root, data)